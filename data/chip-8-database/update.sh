@@ -0,0 +1,12 @@
+#!/bin/sh
+# Refresh the bundled copy of https://github.com/chip-8/chip-8-database
+# (MIT licensed, LICENSE is kept next to the data). Pass a tag or commit to
+# pin it, default master.
+set -e
+ref=${1:-master}
+url=https://raw.githubusercontent.com/chip-8/chip-8-database/$ref
+cd "$(dirname "$0")"
+for name in programs.json sha1-hashes.json platforms.json; do
+    curl -fsSL "$url/database/$name" -o "$name"
+done
+curl -fsSL "$url/LICENSE" -o LICENSE
@@ -0,0 +1,215 @@
+use crate::chip8::Chip8;
+use crate::chip8::FRAMES_PER_SECOND;
+use std::f32::consts::PI;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+// The sound timer beeps while it is non-zero. We synthesize the tone one
+// frame at a time and hand the samples to a backend.
+
+pub const SAMPLE_RATE: u32 = 44100;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Waveform {
+    Square,
+    Triangle,
+    Sawtooth,
+    Sine,
+}
+
+impl Waveform {
+    pub fn from_name(name: &str) -> Option<Waveform> {
+        match name.to_lowercase().as_str() {
+            "square" => Some(Waveform::Square),
+            "triangle" => Some(Waveform::Triangle),
+            "sawtooth" | "saw" => Some(Waveform::Sawtooth),
+            "sine" => Some(Waveform::Sine),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Tone {
+    pub frequency: f32, // hz
+    pub volume: f32,    // 0.0 - 1.0
+    pub waveform: Waveform,
+}
+
+impl Default for Tone {
+    fn default() -> Tone {
+        Tone {
+            frequency: 440.0,
+            volume: 0.25,
+            waveform: Waveform::Square,
+        }
+    }
+}
+
+pub struct Synth {
+    pub tone: Tone,
+    pub sample_rate: u32,
+    phase: f32, // 0.0 - 1.0 through the current cycle
+}
+
+impl Synth {
+    pub fn new(tone: Tone, sample_rate: u32) -> Synth {
+        Synth {
+            tone,
+            sample_rate,
+            phase: 0.0,
+        }
+    }
+
+    // Fill the buffer with the tone, or silence when off.
+    pub fn fill(&mut self, on: bool, out: &mut [f32]) {
+        if !on {
+            // restart the wave so every beep starts the same
+            self.phase = 0.0;
+            for sample in out.iter_mut() {
+                *sample = 0.0;
+            }
+            return;
+        }
+        let step = self.tone.frequency / self.sample_rate as f32;
+        for sample in out.iter_mut() {
+            let p = self.phase;
+            let value = match self.tone.waveform {
+                Waveform::Square => if p < 0.5 { 1.0 } else { -1.0 },
+                Waveform::Triangle => 1.0 - 4.0 * (p - 0.5).abs(),
+                Waveform::Sawtooth => 2.0 * p - 1.0,
+                Waveform::Sine => (2.0 * PI * p).sin(),
+            };
+            *sample = value * self.tone.volume;
+            self.phase = (self.phase + step).fract();
+        }
+    }
+}
+
+pub trait AudioBackend {
+    fn play(&mut self, samples: &[f32]);
+    fn finish(&mut self) {}
+}
+
+// For machines with no sound card
+pub struct NullBackend;
+
+impl AudioBackend for NullBackend {
+    fn play(&mut self, _samples: &[f32]) {}
+}
+
+// 16 bit mono PCM .wav, the sizes in the header are filled in by finish
+pub struct WavWriter {
+    writer: BufWriter<File>,
+    sample_count: u32,
+}
+
+impl WavWriter {
+    pub fn create(path: &Path, sample_rate: u32) -> std::io::Result<WavWriter> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(&wav_header(sample_rate, 0))?;
+        Ok(WavWriter {
+            writer,
+            sample_count: 0,
+        })
+    }
+}
+
+impl AudioBackend for WavWriter {
+    fn play(&mut self, samples: &[f32]) {
+        for sample in samples {
+            let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            if self.writer.write_all(&value.to_le_bytes()).is_err() {
+                return;
+            }
+            self.sample_count += 1;
+        }
+    }
+
+    fn finish(&mut self) {
+        let data_size = self.sample_count * 2;
+        let result = self
+            .writer
+            .seek(SeekFrom::Start(4))
+            .and_then(|_| self.writer.write_all(&(36 + data_size).to_le_bytes()))
+            .and_then(|_| self.writer.seek(SeekFrom::Start(40)))
+            .and_then(|_| self.writer.write_all(&data_size.to_le_bytes()))
+            .and_then(|_| self.writer.flush());
+        if let Err(e) = result {
+            println!("Could not finish wav file: {}", e);
+        }
+    }
+}
+
+fn wav_header(sample_rate: u32, data_size: u32) -> Vec<u8> {
+    let mut header = Vec::with_capacity(44);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&(36 + data_size).to_le_bytes());
+    header.extend_from_slice(b"WAVEfmt ");
+    header.extend_from_slice(&16u32.to_le_bytes()); // fmt chunk size
+    header.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    header.extend_from_slice(&1u16.to_le_bytes()); // mono
+    header.extend_from_slice(&sample_rate.to_le_bytes());
+    header.extend_from_slice(&(sample_rate * 2).to_le_bytes()); // byte rate
+    header.extend_from_slice(&2u16.to_le_bytes()); // block align
+    header.extend_from_slice(&16u16.to_le_bytes()); // bits per sample
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&data_size.to_le_bytes());
+    header
+}
+
+pub struct Audio {
+    pub synth: Synth,
+    pub backend: Box<dyn AudioBackend>,
+    buffer: Vec<f32>,
+}
+
+impl Audio {
+    pub fn new(tone: Tone, backend: Box<dyn AudioBackend>) -> Audio {
+        Audio {
+            synth: Synth::new(tone, SAMPLE_RATE),
+            backend,
+            buffer: vec![0.0; (SAMPLE_RATE / FRAMES_PER_SECOND) as usize],
+        }
+    }
+
+    // Produce one frame of sound, call once per frame before the timers tick
+    pub fn frame(&mut self, chip8: &Chip8) {
+        self.synth.fill(chip8.timer_sound > 0, &mut self.buffer);
+        self.backend.play(&self.buffer);
+    }
+
+    pub fn finish(&mut self) {
+        self.backend.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn square_wave_test() {
+        let tone = Tone {
+            frequency: 1000.0,
+            volume: 0.5,
+            waveform: Waveform::Square,
+        };
+        let mut synth = Synth::new(tone, 4000);
+        let mut out = [0.0; 8];
+        synth.fill(true, &mut out);
+        assert_eq!([0.5, 0.5, -0.5, -0.5, 0.5, 0.5, -0.5, -0.5], out);
+        synth.fill(false, &mut out);
+        assert_eq!([0.0; 8], out);
+    }
+
+    #[test]
+    fn wav_header_test() {
+        let header = wav_header(44100, 8);
+        assert_eq!(44, header.len());
+        assert_eq!(b"RIFF", &header[0..4]);
+        assert_eq!(44, u32::from_le_bytes([header[4], header[5], header[6], header[7]]));
+        assert_eq!(8, u32::from_le_bytes([header[40], header[41], header[42], header[43]]));
+    }
+}
@@ -0,0 +1,167 @@
+use serde_json::Value;
+
+// Octo "cartridges": GIFs of a label image that also carry the program and
+// its options. Every byte of the payload is spread over four pixels, two bits
+// in the low bits of each color index, high bits first. The payload is a big
+// endian 32 bit length, then that much UTF-8 JSON:
+//
+//   {"options": {"tickrate": 20, "shiftQuirks": false, ...}, "program": ": main ..."}
+//
+// Octo stores the program as source. A list of byte values is taken too.
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CartridgeProgram {
+    Bytes(Vec<u8>),
+    Source(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cartridge {
+    pub program: CartridgeProgram,
+    pub options: Value,
+}
+
+// Octo option names and the config settings they set. clipQuirks is the
+// opposite of wrap. loadStoreQuirks, vfOrderQuirks and jumpQuirks aren't
+// emulated.
+const QUIRK_OPTIONS: [(&str, &str, bool); 4] = [
+    ("shiftQuirks", "quirks.shift", true),
+    ("logicQuirks", "quirks.logic", true),
+    ("clipQuirks", "quirks.wrap", false),
+    ("vBlankQuirks", "quirks.vblank", true),
+];
+
+// Palette order: off, plane 1, plane 2, both
+const COLOR_OPTIONS: [&str; 4] = ["backgroundColor", "fillColor", "fillColor2", "blendColor"];
+
+pub fn read_cartridge(gif_bytes: &[u8]) -> Result<Cartridge, String> {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::Indexed);
+    let mut decoder = options
+        .read_info(gif_bytes)
+        .map_err(|e| format!("Bad cartridge gif: {}", e))?;
+    let mut indices = Vec::new();
+    while let Some(frame) = decoder
+        .read_next_frame()
+        .map_err(|e| format!("Bad cartridge gif: {}", e))?
+    {
+        indices.extend_from_slice(&frame.buffer);
+    }
+    parse_payload(&unpack_bytes(&indices))
+}
+
+// Four color indices to a byte
+pub fn unpack_bytes(indices: &[u8]) -> Vec<u8> {
+    indices
+        .chunks_exact(4)
+        .map(|p| (p[0] & 3) << 6 | (p[1] & 3) << 4 | (p[2] & 3) << 2 | (p[3] & 3))
+        .collect()
+}
+
+pub fn parse_payload(data: &[u8]) -> Result<Cartridge, String> {
+    if data.len() < 4 {
+        return Err("Not an Octo cartridge: no payload".to_string());
+    }
+    let size = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+    let json = match data.get(4..4 + size) {
+        Some(x) => x,
+        None => return Err("Not an Octo cartridge: payload is cut short".to_string()),
+    };
+    let json: Value = serde_json::from_slice(json)
+        .map_err(|e| format!("Not an Octo cartridge: {}", e))?;
+    let program = match &json["program"] {
+        Value::String(source) => CartridgeProgram::Source(source.clone()),
+        Value::Array(values) => {
+            let bytes: Option<Vec<u8>> = values
+                .iter()
+                .map(|v| v.as_u64().filter(|b| *b < 256).map(|b| b as u8))
+                .collect();
+            match bytes {
+                Some(x) => CartridgeProgram::Bytes(x),
+                None => return Err("Cartridge program bytes should be 0 to 255".to_string()),
+            }
+        }
+        _ => return Err("Cartridge has no program".to_string()),
+    };
+    Ok(Cartridge {
+        program,
+        options: json["options"].clone(),
+    })
+}
+
+impl Cartridge {
+    // Config settings for the cartridge's options
+    pub fn settings(&self) -> Vec<(&'static str, toml::Value)> {
+        let mut settings = Vec::new();
+        for (option, key, same) in QUIRK_OPTIONS.iter() {
+            if let Some(on) = self.options[*option].as_bool() {
+                settings.push((*key, toml::Value::Boolean(on == *same)));
+            }
+        }
+        if let Some(tickrate) = self.options["tickrate"].as_u64() {
+            settings.push(("run.ips", toml::Value::Integer(tickrate as i64 * 60)));
+        }
+        let colors: Vec<&str> = COLOR_OPTIONS
+            .iter()
+            .filter_map(|c| self.options[*c].as_str())
+            .collect();
+        if colors.len() == COLOR_OPTIONS.len() {
+            settings.push(("display.palette", toml::Value::String(colors.join(" "))));
+        }
+        settings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two bits per color index on top of a base color, as Octo writes them
+    fn pack(payload: &str, base: u8) -> Vec<u8> {
+        let mut data = (payload.len() as u32).to_be_bytes().to_vec();
+        data.extend(payload.bytes());
+        data.iter()
+            .flat_map(|b| vec![b >> 6, (b >> 4) & 3, (b >> 2) & 3, b & 3])
+            .map(|bits| base | bits)
+            .collect()
+    }
+
+    #[test]
+    fn payload_test() {
+        let json = r##"{"options": {"tickrate": 20, "clipQuirks": true, "shiftQuirks": false,
+            "backgroundColor": "#000000", "fillColor": "#FFCC00", "fillColor2": "#FF6600", "blendColor": "#662200"},
+            "program": [0, 224, 18, 0]}"##;
+        let cartridge = parse_payload(&unpack_bytes(&pack(json, 0x40))).unwrap();
+        assert_eq!(CartridgeProgram::Bytes(vec![0x00, 0xE0, 0x12, 0x00]), cartridge.program);
+        let settings = cartridge.settings();
+        assert!(settings.contains(&("quirks.wrap", toml::Value::Boolean(false))));
+        assert!(settings.contains(&("quirks.shift", toml::Value::Boolean(false))));
+        assert!(settings.contains(&("run.ips", toml::Value::Integer(1200))));
+        assert!(settings.contains(&(
+            "display.palette",
+            toml::Value::String("#000000 #FFCC00 #FF6600 #662200".to_string())
+        )));
+        assert!(parse_payload(&[0, 0, 0, 9, b'{']).is_err());
+    }
+
+    #[test]
+    fn gif_test() {
+        let json = r#"{"options": {}, "program": ": main loop again"}"#;
+        let mut pixels = pack(json, 0);
+        pixels.resize(128 * 64, 0);
+        let mut gif_bytes = Vec::new();
+        {
+            let palette = [0u8; 3 * 4];
+            let mut encoder = gif::Encoder::new(&mut gif_bytes, 128, 64, &palette).unwrap();
+            let frame = gif::Frame {
+                width: 128,
+                height: 64,
+                buffer: pixels.into(),
+                ..gif::Frame::default()
+            };
+            encoder.write_frame(&frame).unwrap();
+        }
+        let cartridge = read_cartridge(&gif_bytes).unwrap();
+        assert_eq!(CartridgeProgram::Source(": main loop again".to_string()), cartridge.program);
+    }
+}
@@ -0,0 +1,340 @@
+use sha1::{Digest, Sha1};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Settings come in layers, each one overriding the one before:
+//   1. the defaults below
+//   2. the rom database entry for the rom (see romdb.rs)
+//   3. the options of an Octo cartridge (see cartridge.rs)
+//   4. ~/.config/chip8/config.toml (or $XDG_CONFIG_HOME/chip8/config.toml)
+//   5. the [roms.<sha1 of the rom>] section of that file
+//   6. command line flags
+//
+//   [display]
+//   renderer = "half"
+//   palette = "amber"
+//   [roms.0a1b2c...]
+//   quirks.vblank = true
+//   run.ips = 1000
+
+pub const DEFAULTS: [(&str, DefaultValue); 21] = [
+    ("quirks.shift", DefaultValue::Bool(true)),
+    ("quirks.logic", DefaultValue::Bool(false)),
+    ("quirks.wrap", DefaultValue::Bool(true)),
+    ("quirks.vblank", DefaultValue::Bool(false)),
+    ("run.ips", DefaultValue::Int(600)),
+    ("run.iterations", DefaultValue::Int(10)),
+    ("run.registers", DefaultValue::Bool(false)),
+    ("display.gui", DefaultValue::Bool(false)),
+    ("display.tui", DefaultValue::Bool(false)),
+    ("display.renderer", DefaultValue::Str("char")),
+    ("display.glyph", DefaultValue::Str("auto")),
+    ("display.palette", DefaultValue::Str("none")),
+    ("display.filter", DefaultValue::Str("none")),
+    ("font.small", DefaultValue::Str("chip8")),
+    ("font.big", DefaultValue::Str("schip")),
+    ("machine.memory", DefaultValue::Str("vip")),
+    ("input.keys", DefaultValue::Str("qwerty")),
+    ("input.keymap", DefaultValue::Str("")),
+    ("audio.tone", DefaultValue::Float(440.0)),
+    ("audio.volume", DefaultValue::Float(0.25)),
+    ("audio.waveform", DefaultValue::Str("square")),
+];
+
+#[derive(Clone, Copy, Debug)]
+pub enum DefaultValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(&'static str),
+}
+
+impl DefaultValue {
+    fn value(&self) -> toml::Value {
+        match self {
+            DefaultValue::Bool(x) => toml::Value::Boolean(*x),
+            DefaultValue::Int(x) => toml::Value::Integer(*x),
+            DefaultValue::Float(x) => toml::Value::Float(*x),
+            DefaultValue::Str(x) => toml::Value::String(x.to_string()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Source {
+    Default,
+    Database,
+    Cartridge(PathBuf),
+    File(PathBuf),
+    Rom(PathBuf, String),
+    Flag,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::Database => write!(f, "chip-8-database"),
+            Source::Cartridge(path) => write!(f, "{} (cartridge)", path.display()),
+            Source::File(path) => write!(f, "{}", path.display()),
+            Source::Rom(path, hash) => write!(f, "{} [roms.{}]", path.display(), hash),
+            Source::Flag => write!(f, "command line"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Setting {
+    pub key: &'static str,
+    pub value: toml::Value,
+    pub source: Source,
+}
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub settings: Vec<Setting>,
+}
+
+pub fn sha1_hex(bytes: &[u8]) -> String {
+    Sha1::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+// `--set key=value`. The value is read as TOML, anything else is a string.
+pub fn parse_assignment(text: &str) -> Result<(String, toml::Value), String> {
+    let (key, value) = match text.find('=') {
+        Some(at) => (text[..at].trim(), text[at + 1..].trim()),
+        None => return Err(format!("Expected key=value, not {}", text)),
+    };
+    let value = match format!("value = {}", value).parse::<toml::Value>() {
+        Ok(table) => table["value"].clone(),
+        Err(_) => toml::Value::String(value.to_string()),
+    };
+    Ok((key.to_string(), value))
+}
+
+pub fn user_config_path() -> Option<PathBuf> {
+    if let Ok(dir) = env::var("XDG_CONFIG_HOME") {
+        if !dir.is_empty() {
+            return Some(Path::new(&dir).join("chip8").join("config.toml"));
+        }
+    }
+    env::var("HOME")
+        .ok()
+        .map(|home| Path::new(&home).join(".config").join("chip8").join("config.toml"))
+}
+
+impl Config {
+    pub fn new() -> Config {
+        Config {
+            settings: DEFAULTS
+                .iter()
+                .map(|(key, default)| Setting {
+                    key,
+                    value: default.value(),
+                    source: Source::Default,
+                })
+                .collect(),
+        }
+    }
+
+    // Layers 4 and 5. A missing file is fine, a broken one is not.
+    pub fn load_file(&mut self, path: &Path, rom_hash: Option<&str>) -> Result<(), String> {
+        let text = match fs::read_to_string(path) {
+            Ok(x) => x,
+            Err(_) if !path.exists() => return Ok(()),
+            Err(e) => return Err(format!("Could not read config {}: {}", path.display(), e)),
+        };
+        self.apply_text(&text, path, rom_hash)
+    }
+
+    pub fn apply_text(&mut self, text: &str, path: &Path, rom_hash: Option<&str>) -> Result<(), String> {
+        let table: toml::Value = text
+            .parse()
+            .map_err(|e| format!("Bad config {}: {}", path.display(), e))?;
+        self.apply_table(&table, &Source::File(path.to_path_buf()))?;
+        if let Some(hash) = rom_hash {
+            if let Some(rom) = table.get("roms").and_then(|r| r.get(hash)) {
+                self.apply_table(rom, &Source::Rom(path.to_path_buf(), hash.to_string()))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_table(&mut self, table: &toml::Value, source: &Source) -> Result<(), String> {
+        let sections = match table.as_table() {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+        for (section, values) in sections {
+            if section == "roms" {
+                continue;
+            }
+            // a [section] table, or dotted keys in the rom sections
+            let values = match values.as_table() {
+                Some(x) => x,
+                None => return Err(format!("Unknown config setting: {}", section)),
+            };
+            for (name, value) in values {
+                let key = format!("{}.{}", section, name);
+                self.set(&key, value.clone(), source.clone())?;
+            }
+        }
+        Ok(())
+    }
+
+    // Layer 6, and every layer ends up here. The value has to have the
+    // default's type, integers are accepted for decimals.
+    pub fn set(&mut self, key: &str, value: toml::Value, source: Source) -> Result<(), String> {
+        let setting = match self.settings.iter_mut().find(|s| s.key == key) {
+            Some(x) => x,
+            None => return Err(format!("Unknown config setting: {}", key)),
+        };
+        let value = match (&setting.value, value) {
+            (toml::Value::Float(_), toml::Value::Integer(x)) => toml::Value::Float(x as f64),
+            (old, new) if old.type_str() == new.type_str() => new,
+            (old, new) => {
+                return Err(format!(
+                    "{} should be a {}, not {} ({})",
+                    key,
+                    old.type_str(),
+                    new,
+                    source
+                ))
+            }
+        };
+        setting.value = value;
+        setting.source = source;
+        Ok(())
+    }
+
+    fn setting(&self, key: &str) -> &Setting {
+        match self.settings.iter().find(|s| s.key == key) {
+            Some(setting) => setting,
+            None => panic!("No config setting {}", key),
+        }
+    }
+
+    fn get(&self, key: &str) -> &toml::Value {
+        &self.setting(key).value
+    }
+
+    pub fn source(&self, key: &str) -> &Source {
+        &self.setting(key).source
+    }
+
+    pub fn bool(&self, key: &str) -> bool {
+        self.get(key).as_bool().unwrap_or(false)
+    }
+
+    pub fn int(&self, key: &str) -> i64 {
+        self.get(key).as_integer().unwrap_or(0)
+    }
+
+    pub fn float(&self, key: &str) -> f64 {
+        self.get(key).as_float().unwrap_or(0.0)
+    }
+
+    pub fn str(&self, key: &str) -> &str {
+        self.get(key).as_str().unwrap_or("")
+    }
+
+    // A path setting, relative to the config file that set it. Empty is none.
+    pub fn path(&self, key: &str) -> Option<PathBuf> {
+        let path = match self.str(key) {
+            "" => return None,
+            x => PathBuf::from(x),
+        };
+        let dir = match self.source(key) {
+            Source::File(file) | Source::Rom(file, _) => file.parent().map(|d| d.to_path_buf()),
+            _ => None,
+        };
+        match dir {
+            Some(dir) if path.is_relative() => Some(dir.join(path)),
+            _ => Some(path),
+        }
+    }
+
+    // For `chip8 config show`
+    pub fn describe(&self) -> String {
+        let mut out = String::new();
+        for setting in &self.settings {
+            let line = format!("{:<18} = {}", setting.key, setting.value);
+            out.push_str(&format!("{:<40} {}\n", line, setting.source));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layers_test() {
+        let mut config = Config::new();
+        let text = "[display]\nrenderer = \"half\"\n[audio]\ntone = 300\n[roms.abc]\nquirks.vblank = true\ndisplay.renderer = \"braille\"\n";
+        let path = Path::new("config.toml");
+        config.apply_text(text, path, Some("abc")).unwrap();
+        config
+            .set("quirks.shift", toml::Value::Boolean(false), Source::Database)
+            .unwrap();
+        config
+            .set("run.ips", toml::Value::Integer(1000), Source::Flag)
+            .unwrap();
+        assert_eq!("braille", config.str("display.renderer"));
+        assert_eq!(300.0, config.float("audio.tone"));
+        assert!(config.bool("quirks.vblank"));
+        assert_eq!(1000, config.int("run.ips"));
+        assert!(!config.bool("quirks.shift"));
+        let source = |key| config.source(key).to_string();
+        assert_eq!("config.toml [roms.abc]", source("quirks.vblank"));
+        assert_eq!("chip-8-database", source("quirks.shift"));
+        assert_eq!("command line", source("run.ips"));
+        assert_eq!("default", source("run.iterations"));
+    }
+
+    #[test]
+    fn bad_settings_test() {
+        let mut config = Config::new();
+        let path = Path::new("config.toml");
+        assert!(config.apply_text("[display]\ngui = \"yes\"\n", path, None).is_err());
+        assert!(config.apply_text("[display]\ncolour = \"red\"\n", path, None).is_err());
+    }
+
+    #[test]
+    fn assignment_test() {
+        let mut config = Config::new();
+        for text in &["quirks.shift=false", "run.ips = 900", "display.renderer=half", "audio.tone=300"] {
+            let (key, value) = parse_assignment(text).unwrap();
+            config.set(&key, value, Source::Flag).unwrap();
+        }
+        assert!(!config.bool("quirks.shift"));
+        assert_eq!(900, config.int("run.ips"));
+        assert_eq!("half", config.str("display.renderer"));
+        assert_eq!(300.0, config.float("audio.tone"));
+        assert!(parse_assignment("quirks.shift").is_err());
+    }
+
+    #[test]
+    fn path_test() {
+        let mut config = Config::new();
+        assert_eq!(None, config.path("input.keymap"));
+        let file = Path::new("/home/me/.config/chip8/config.toml");
+        config.apply_text("[input]\nkeymap = \"keys.toml\"\n", file, None).unwrap();
+        assert_eq!(Some(PathBuf::from("/home/me/.config/chip8/keys.toml")), config.path("input.keymap"));
+        config
+            .set("input.keymap", toml::Value::String("keys.toml".to_string()), Source::Flag)
+            .unwrap();
+        assert_eq!(Some(PathBuf::from("keys.toml")), config.path("input.keymap"));
+    }
+
+    #[test]
+    fn sha1_test() {
+        assert_eq!("a9993e364706816aba3e25717850c26c9cd0d89d", sha1_hex(b"abc"));
+    }
+}
@@ -0,0 +1,252 @@
+use crate::chip8::symbols::Symbols;
+use crate::chip8::Chip8;
+use crate::chip8::MEMORY_SIZE;
+use c8_disasm_lib::decode;
+use std::fmt::Write;
+
+pub const EXECUTED: u8 = 0x1;
+pub const READ: u8 = 0x2;
+pub const WRITTEN: u8 = 0x4;
+
+// Per byte record of how memory was used during a run.
+pub struct Coverage {
+    pub flags: Vec<u8>,
+    pub hits: Vec<u32>,
+    pub rom_start: usize,
+    pub rom: Vec<u8>,
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage {
+            flags: vec![0; MEMORY_SIZE],
+            hits: vec![0; MEMORY_SIZE],
+            rom_start: 0,
+            rom: Vec::new(),
+        }
+    }
+
+    // Keep a copy of the program as loaded, self modifying code may change memory
+    pub fn load_rom(&mut self, start: usize, bytes: &[u8]) {
+        self.rom_start = start;
+        self.rom = bytes.to_vec();
+    }
+
+    // Call before fetching the instruction at pc
+    pub fn record_step(&mut self, chip8: &Chip8) {
+        let pc = chip8.pc as usize;
+        if pc + 1 >= MEMORY_SIZE {
+            return;
+        }
+        self.flags[pc] |= EXECUTED;
+        self.flags[pc + 1] |= EXECUTED;
+        self.hits[pc] += 1;
+
+        let access = chip8.memory_accesses(chip8.memory[pc], chip8.memory[pc + 1]);
+        for a in access.reads {
+            self.flags[a] |= READ;
+        }
+        for a in access.writes {
+            self.flags[a] |= WRITTEN;
+        }
+    }
+
+    pub fn executed_rom_bytes(&self) -> usize {
+        let end = self.rom_start + self.rom.len();
+        self.flags[self.rom_start..end]
+            .iter()
+            .filter(|f| *f & EXECUTED != 0)
+            .count()
+    }
+
+    // Disassembly of the loaded rom with X/R/W markers per instruction.
+    // Anything never executed, read or written is marked with '!'.
+    pub fn listing(&self, symbols: Option<&Symbols>) -> String {
+        let mut out = String::new();
+        writeln!(
+            out,
+            "; coverage: {}/{} rom bytes executed",
+            self.executed_rom_bytes(),
+            self.rom.len()
+        )
+        .unwrap();
+        writeln!(out, "; X executed  R read  W written  ! untouched").unwrap();
+
+        let mut offset = 0;
+        while offset < self.rom.len() {
+            let address = self.rom_start + offset;
+            if let Some(label) = symbols.and_then(|s| s.label_at(address as u16)) {
+                writeln!(out, "{}:", label).unwrap();
+            }
+            let flags = self.flags[address];
+            let next_executed = address + 1 < MEMORY_SIZE && self.flags[address + 1] & EXECUTED != 0;
+            let is_pair = offset + 1 < self.rom.len() && (flags & EXECUTED != 0 || !next_executed);
+
+            if is_pair {
+                let b0 = self.rom[offset];
+                let b1 = self.rom[offset + 1];
+                let flags = flags | self.flags[address + 1];
+                let text = if flags & EXECUTED != 0 || flags == 0 {
+                    decode(b0, b1).to_string()
+                } else {
+                    format!("db {:#04X} {:#04X}", b0, b1)
+                };
+                writeln!(
+                    out,
+                    "{:03X}  {:02X}{:02X}  {}  {}",
+                    address,
+                    b0,
+                    b1,
+                    markers(flags),
+                    text
+                )
+                .unwrap();
+                offset += 2;
+            } else {
+                let b0 = self.rom[offset];
+                writeln!(
+                    out,
+                    "{:03X}  {:02X}    {}  db {:#04X}",
+                    address,
+                    b0,
+                    markers(flags),
+                    b0
+                )
+                .unwrap();
+                offset += 1;
+            }
+        }
+        out
+    }
+
+    // lcov tracefile keyed to the source lines in the symbol file
+    pub fn lcov(&self, symbols: &Symbols) -> String {
+        let mut files: Vec<&str> = Vec::new();
+        for (_, source) in &symbols.lines {
+            if !files.contains(&source.file.as_str()) {
+                files.push(&source.file);
+            }
+        }
+
+        let mut out = String::new();
+        writeln!(out, "TN:").unwrap();
+        for file in files {
+            let mut lines: Vec<(u32, u32)> = Vec::new();
+            for (address, source) in &symbols.lines {
+                if source.file != file {
+                    continue;
+                }
+                let hits = self.hits[*address as usize % MEMORY_SIZE];
+                match lines.iter_mut().find(|(l, _)| *l == source.line) {
+                    Some(entry) => entry.1 += hits,
+                    None => lines.push((source.line, hits)),
+                }
+            }
+            lines.sort();
+
+            writeln!(out, "SF:{}", file).unwrap();
+            for (line, hits) in &lines {
+                writeln!(out, "DA:{},{}", line, hits).unwrap();
+            }
+            writeln!(out, "LF:{}", lines.len()).unwrap();
+            writeln!(out, "LH:{}", lines.iter().filter(|(_, h)| *h > 0).count()).unwrap();
+            writeln!(out, "end_of_record").unwrap();
+        }
+        out
+    }
+}
+
+fn markers(flags: u8) -> String {
+    if flags == 0 {
+        return "!  ".to_string();
+    }
+    let mut m = String::new();
+    m.push(if flags & EXECUTED != 0 { 'X' } else { '-' });
+    m.push(if flags & READ != 0 { 'R' } else { '-' });
+    m.push(if flags & WRITTEN != 0 { 'W' } else { '-' });
+    m
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::symbols::parse_symbols;
+    use crate::chip8::{MemoryAccess, COL_SIZE_BYTE, DATA};
+
+    const ROM: [u8; 12] = [
+        0xA2, 0x09, // i := 0x209
+        0xF0, 0x33, // bcd v0
+        0x12, 0x04, // halt
+        0x00, 0xE0, // never run
+        0x55, 0x00, 0x00, 0x00, // data, the last three written
+    ];
+
+    fn run(steps: usize) -> Coverage {
+        let mut chip8 = Chip8::new();
+        chip8.load_program_at(&ROM, DATA);
+        let mut coverage = Coverage::new();
+        coverage.load_rom(DATA, &ROM);
+        for _ in 0..steps {
+            coverage.record_step(&chip8);
+            let (b0, b1) = chip8.fetch();
+            chip8.decode_execute(b0, b1);
+        }
+        coverage
+    }
+
+    #[test]
+    fn memory_accesses_test() {
+        let mut chip8 = Chip8::new();
+        chip8.i = 0x300;
+        assert_eq!(vec![0x300, 0x301, 0x302], chip8.memory_accesses(0xF0, 0x33).writes);
+        assert_eq!(16, chip8.memory_accesses(0xF5, 0x65).reads.len());
+        assert_eq!(MemoryAccess::default(), chip8.memory_accesses(0x60, 0x01));
+        // a call writes the return address to the stack
+        let map = chip8.memory_map;
+        assert_eq!(vec![map.callstack + 2, map.callstack + 3], chip8.memory_accesses(0x22, 0x00).writes);
+        // a sprite row reads I and writes the display byte it lands on
+        let access = chip8.memory_accesses(0xD0, 0x12);
+        assert_eq!(vec![0x300, 0x301], access.reads);
+        assert_eq!(vec![map.display, map.display + COL_SIZE_BYTE], access.writes);
+        // I wraps at the end of the addressable memory
+        chip8.i = 0xFFE;
+        assert_eq!(vec![0xFFE, 0xFFF, 0x000], chip8.memory_accesses(0xF0, 0x33).writes);
+    }
+
+    #[test]
+    fn record_step_test() {
+        let coverage = run(4);
+        assert_eq!(EXECUTED, coverage.flags[0x200]);
+        assert_eq!(EXECUTED, coverage.flags[0x205]);
+        assert_eq!(0, coverage.flags[0x206]);
+        assert_eq!((0, WRITTEN), (coverage.flags[0x208], coverage.flags[0x209]));
+        assert_eq!(WRITTEN, coverage.flags[0x20B]);
+        assert_eq!((1, 2), (coverage.hits[0x200], coverage.hits[0x204]));
+        assert_eq!(6, coverage.executed_rom_bytes());
+    }
+
+    #[test]
+    fn listing_test() {
+        let symbols = parse_symbols("main 0x200\nhalt 0x204\n").unwrap();
+        let listing = run(4).listing(Some(&symbols));
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!("; coverage: 6/12 rom bytes executed", lines[0]);
+        assert_eq!("main:", lines[2]);
+        assert!(lines[3].starts_with("200  A209  X--  "));
+        assert_eq!("halt:", lines[5]);
+        assert!(lines[7].starts_with("206  00E0  !    "));
+        assert_eq!("208  5500  --W  db 0x55 0x00", lines[8]);
+        assert_eq!("20A  0000  --W  db 0x00 0x00", lines[9]);
+        assert_eq!(10, lines.len());
+    }
+
+    #[test]
+    fn lcov_test() {
+        let symbols = parse_symbols("0x200 game.8o:1\n0x202 game.8o:2\n0x204 game.8o:2\n0x206 game.8o:4\n").unwrap();
+        let lcov = run(4).lcov(&symbols);
+        assert_eq!(
+            "TN:\nSF:game.8o\nDA:1,1\nDA:2,3\nDA:4,0\nLF:3\nLH:2\nend_of_record\n",
+            lcov
+        );
+    }
+}
@@ -1,19 +1,34 @@
-use crate::chip8::DATA;
+use crate::chip8::fonts::{FontEditor, FontSet};
+use crate::chip8::keymap::Keymap;
+use crate::chip8::palette::Palette;
+use crate::chip8::screenshot::{save_screenshot, DEFAULT_SCALE};
+use crate::chip8::symbols::{describe_address, Symbols};
+use crate::chip8::watch::RomWatcher;
 use crate::chip8::MEMORY_SIZE;
 use crate::Chip8;
 use crate::COLS;
-use crate::DISPLAY;
 use crate::ROWS;
 use crate::ROW_LEN;
 use c8_disasm_lib::decode;
+use cursive::direction::Direction;
+use cursive::event::{Event, EventResult, Key};
 use cursive::view::Resizable;
+use cursive::view::View;
 use cursive::views::DummyView;
 use cursive::views::LinearLayout;
 use cursive::views::TextContent;
 use cursive::views::TextView;
-use cursive::CursiveRunnable;
+use cursive::Printer;
+use cursive::Vec2;
 use std::fs;
-use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::time::Duration;
+
+// Emulator steps a key stays down after a terminal key press
+const KEY_HOLD_STEPS: u32 = 3;
 
 pub fn display_render_gui(chip8: &Chip8, glyph: char, tv: &TextContent) {
     // 32 rows x 64 cols
@@ -24,12 +39,12 @@ pub fn display_render_gui(chip8: &Chip8, glyph: char, tv: &TextContent) {
 
     tv.set_content("");
     for row_i in 0..ROWS {
-        let row_start = DISPLAY + (row_i * ROW_LEN);
+        let row_start = chip8.memory_map.display + (row_i * ROW_LEN);
         // print each col for row
         for i in 0..(COLS / 8) {
             let byte: u8 = chip8.memory[row_start + i];
             for b in 0..8 {
-                let bit = byte & (0x1 << 7 - b);
+                let bit = byte & (0x1 << (7 - b));
                 let draw = if bit == 0 { ' ' } else { glyph };
                 tv.append(draw);
             }
@@ -38,21 +53,37 @@ pub fn display_render_gui(chip8: &Chip8, glyph: char, tv: &TextContent) {
     }
 }
 
-pub fn decode_print_byte_gui(tv: &TextContent, b0: u8, b1: u8, should_show_ascii: bool) {
+pub fn decode_print_byte_gui(
+    tv: &TextContent,
+    address: u16,
+    symbols: Option<&Symbols>,
+    b0: u8,
+    b1: u8,
+    should_show_ascii: bool,
+) {
     let opcode = decode(b0, b1);
+    let location = describe_address(symbols, address);
 
     let b0_printable = b0 == b' ' || b0.is_ascii_alphanumeric();
     let b1_printable = b1 == b' ' || b1.is_ascii_alphanumeric();
     if should_show_ascii && b0_printable && b1_printable {
-        tv.set_content(format!("{} \"{}{}\"", opcode, b0 as char, b1 as char));
+        tv.set_content(format!("{}  {} \"{}{}\"", location, opcode, b0 as char, b1 as char));
     } else {
-        tv.set_content(format!("{}", opcode));
+        tv.set_content(format!("{}  {}", location, opcode));
     }
 }
 
-pub fn gui_debug_registers(chip8: &Chip8, tv: &TextContent) {
+pub fn gui_debug_registers(chip8: &Chip8, symbols: Option<&Symbols>, tv: &TextContent) {
     tv.set_content("PC    SP    I\n");
     tv.append(format!("{:#X} {:#X} {:#X}\n", chip8.pc, chip8.sp, chip8.i));
+    let stack = chip8.call_stack();
+    if !stack.is_empty() {
+        let names: Vec<String> = stack
+            .iter()
+            .map(|ret| describe_address(symbols, *ret))
+            .collect();
+        tv.append(format!("stack: {}\n", names.join(" < ")));
+    }
     tv.append("v0 v1 v2 v3 v4 v5 v6 v7 v8 v9 va vb vc vd ve vf dt st  k\n");
     tv.append(format!("{:2X} {:2X} {:2X} {:2X} {:2X} {:2X} {:2X} {:2X} {:2X} {:2X} {:2X} {:2X} {:2X} {:2X} {:2X} {:2X} {:2X} {:2X} {:2X}", 
             chip8.v[0], chip8.v[1], chip8.v[2], chip8.v[3],
@@ -63,7 +94,15 @@ pub fn gui_debug_registers(chip8: &Chip8, tv: &TextContent) {
     ));
 }
 
-pub fn run_gui_emulator(path: &Path, debug_registers: bool, glyph: char, should_autorun: bool) {
+// --tui: step the machine main set up, showing each instruction and the registers
+pub fn run_gui_emulator(
+    mut chip8: Chip8,
+    glyph: char,
+    should_autorun: bool,
+    mut symbols: Option<Symbols>,
+    keymap: Keymap,
+    mut watcher: Option<RomWatcher>,
+) {
     let mut siv = cursive::default();
     let mut display_tv = TextView::new("Waiting to draw to display...");
     let mut op_tv = TextView::new("Press \"n\" to run!");
@@ -81,66 +120,27 @@ pub fn run_gui_emulator(path: &Path, debug_registers: bool, glyph: char, should_
             .child(DummyView.fixed_height(1))
             .child(register_tv),
     );
-    siv.add_global_callback('q', |s| s.quit());
-    //Dialog::around(tv).title("Cursive").button("Quit", |s| s.quit()));
-    setup_gui(&mut siv);
-
-    let memory: [u8; MEMORY_SIZE] = [0; MEMORY_SIZE];
-    let mut chip8 = Chip8 {
-        memory: memory,
-        v: [0; 16],
-        address: 0,
-        timer_delay: 0,
-        timer_sound: 0,
-        pc: 0,
-        sp: 0,
-        i: 0,
-        keyboard: 0,
-        should_draw: false,
-        wait_key: false,
-        wait_key_v_x: 0,
-    };
-
-    // Input - hex keyboard: 16 keys 0-F.
-    // 1 2 3 C
-    // 4 5 6 D
-    // 7 8 9 E
-    // A 0 B F
-
-    let keyboard_state: [bool; 16] = [false; 16];
-
-    // Graphics is 64x32 monochrome
-    // Sprites are 8 wide 1-15 in height
-    // xor'd to screen pixels
-    // Carry flag VF is set to 1 if pixels are flipped when sprite drawn or else 0
-    let graphics_state: [bool; ROWS * COLS] = [false; ROWS * COLS];
-
-    // load fonts
-    chip8.load_fonts();
-
-    // fetch
-
-    println!(
-        "Loading {} into memory",
-        path.to_str().unwrap_or("BAD_PATH")
-    );
-    let bytes = match fs::read(path) {
-        Ok(x) => x,
-        Err(_e) => {
-            println!(
-                "Could not read file from path: {}",
-                path.to_str().unwrap_or("BAD_PATH")
-            );
-            return;
+    // Esc and F12 since letters and digits belong to the keymap
+    siv.add_global_callback(Key::Esc, |s| s.quit());
+    // F12 asks the emulator thread for a screenshot
+    let screenshot_requested = Arc::new(AtomicBool::new(false));
+    let request = screenshot_requested.clone();
+    siv.add_global_callback(Key::F12, move |_| request.store(true, Ordering::Relaxed));
+    // Mapped keys go to the emulator thread as hex keys
+    let (key_sender, key_receiver) = mpsc::channel::<u8>();
+    for (hex, host) in keymap.bindings() {
+        let mut events = vec![host];
+        if host.is_ascii_alphabetic() {
+            events.push(host.to_ascii_uppercase());
+        }
+        for event in events {
+            let sender = key_sender.clone();
+            siv.add_global_callback(event, move |_| {
+                sender.send(hex).ok();
+            });
         }
-    };
-    let start = DATA;
-    for i in 0..bytes.len() {
-        chip8.memory[start + i] = bytes[i];
     }
 
-    chip8.pc = 0x200;
-
     // Starts the event loop.
 
     // siv.set_user_data(chip8);
@@ -149,26 +149,147 @@ pub fn run_gui_emulator(path: &Path, debug_registers: bool, glyph: char, should_
 
     siv.set_autorefresh(should_autorun);
     std::thread::spawn(move || {
+        let mut steps: u32 = 0;
+        // steps left before each hex key is released
+        let mut held = [0u32; 16];
         loop {
+            // Terminals only send key presses, so hold each key for a few steps
+            while let Ok(hex) = key_receiver.try_recv() {
+                chip8.press_key(hex);
+                held[hex as usize] = KEY_HOLD_STEPS;
+            }
+            for (hex, steps) in held.iter_mut().enumerate() {
+                if *steps > 0 {
+                    *steps -= 1;
+                    if *steps == 0 {
+                        chip8.release_key(hex as u8);
+                    }
+                }
+            }
+            if screenshot_requested.swap(false, Ordering::Relaxed) {
+                let path = PathBuf::from(format!("chip8-{}.png", steps));
+                let text = match save_screenshot(&path, &chip8, DEFAULT_SCALE, &Palette::mono()) {
+                    Ok(()) => format!("Saved screenshot {}", path.display()),
+                    Err(e) => e,
+                };
+                op_content.set_content(text);
+            }
+            if let Some(watcher) = watcher.as_mut() {
+                match watcher.poll(&mut chip8) {
+                    Some(Ok(rom)) => {
+                        op_content.set_content(format!("Reloaded {}", rom.name));
+                        if rom.symbols.is_some() {
+                            symbols = rom.symbols;
+                        }
+                    }
+                    Some(Err(e)) => op_content.set_content(e),
+                    None => {}
+                }
+            }
+            steps += 1;
             // Next step
+            let pc = chip8.pc;
+            if pc as usize + 1 >= MEMORY_SIZE {
+                op_content.set_content(format!("pc ran off the end of memory at {:#05X}", pc));
+                break;
+            }
             let (b0, b1) = chip8.fetch();
             chip8.decode_execute(b0, b1);
-            decode_print_byte_gui(&op_content, b0, b1, true);
-            gui_debug_registers(&chip8, &register_content);
+            if chip8.frame_over() {
+                chip8.end_frame();
+            }
+            decode_print_byte_gui(&op_content, pc, symbols.as_ref(), b0, b1, true);
+            gui_debug_registers(&chip8, symbols.as_ref(), &register_content);
             if chip8.should_draw {
                 display_render_gui(&chip8, glyph, &display_content);
                 chip8.should_draw = false;
-                // TODO Need to implement timers for sounds and delays
             }
-            std::thread::sleep_ms(167)
+            std::thread::sleep(Duration::from_millis(167))
         }
     });
     siv.run();
 }
 
-fn setup_gui(siv: &mut CursiveRunnable) {
-    // Creates a dialog with a single "Quit" button
-    // siv.add_layer(Dialog::around(TextView::new("Hello Dialog!"))
-    //                      .title("Cursive")
-    //                      .button("Quit", |s| s.quit()));
+// dump_fonts as an editable grid
+struct FontEditorView {
+    editor: FontEditor,
+    path: PathBuf,
+    glyph: char,
+    status: String,
+}
+
+impl FontEditorView {
+    fn save(&mut self) {
+        self.status = match fs::write(&self.path, self.editor.font.to_text()) {
+            Ok(()) => {
+                self.editor.modified = false;
+                format!("Saved {}", self.path.display())
+            }
+            Err(e) => format!("Could not write {}: {}", self.path.display(), e),
+        };
+    }
+
+    // The raw bytes, to include in a rom
+    fn export(&mut self) {
+        let path = self.path.with_extension("bin");
+        self.status = match fs::write(&path, &self.editor.font.bytes) {
+            Ok(()) => format!("Exported {}", path.display()),
+            Err(e) => format!("Could not write {}: {}", path.display(), e),
+        };
+    }
+
+    fn lines(&self) -> Vec<String> {
+        let mut lines = self.editor.lines(self.glyph);
+        lines.push(String::new());
+        lines.push("arrows move, space toggles, tab/n next, p previous, s save, x export, esc quits".to_string());
+        let modified = if self.editor.modified { "modified" } else { "" };
+        lines.push(format!("{} {}", self.status, modified));
+        lines
+    }
+}
+
+impl View for FontEditorView {
+    fn draw(&self, printer: &Printer) {
+        for (y, line) in self.lines().iter().enumerate() {
+            printer.print((0, y), line);
+        }
+    }
+
+    fn required_size(&mut self, _constraint: Vec2) -> Vec2 {
+        let lines = self.lines();
+        let width = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+        Vec2::new(width, lines.len())
+    }
+
+    fn take_focus(&mut self, _source: Direction) -> bool {
+        true
+    }
+
+    fn on_event(&mut self, event: Event) -> EventResult {
+        match event {
+            Event::Key(Key::Left) => self.editor.move_cursor(-1, 0),
+            Event::Key(Key::Right) => self.editor.move_cursor(1, 0),
+            Event::Key(Key::Up) => self.editor.move_cursor(0, -1),
+            Event::Key(Key::Down) => self.editor.move_cursor(0, 1),
+            Event::Char(' ') => self.editor.toggle(),
+            Event::Key(Key::Tab) | Event::Char('n') => self.editor.next_glyph(1),
+            Event::Char('p') => self.editor.next_glyph(-1),
+            Event::Char('s') => self.save(),
+            Event::Char('x') => self.export(),
+            _ => return EventResult::Ignored,
+        }
+        EventResult::Consumed(None)
+    }
+}
+
+pub fn run_font_editor(font: FontSet, path: PathBuf, glyph: char) {
+    let mut siv = cursive::default();
+    siv.add_global_callback(Key::Esc, |s| s.quit());
+    siv.add_layer(FontEditorView {
+        editor: FontEditor::new(font),
+        path,
+        glyph,
+        status: String::new(),
+    });
+    siv.run();
 }
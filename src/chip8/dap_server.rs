@@ -0,0 +1,777 @@
+use crate::chip8::debugger::{Debugger, StopReason};
+use crate::chip8::fonts::Fonts;
+use crate::chip8::memory_map::MemoryMap;
+use crate::chip8::quirks::Quirks;
+use crate::chip8::rom_loader::RomLoader;
+use crate::chip8::symbols::{describe_address, load_symbols, Symbols};
+use crate::chip8::Chip8;
+use crate::chip8::MEMORY_SIZE;
+use crate::chip8::machine;
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+
+// Debug Adapter Protocol server.
+// https://microsoft.github.io/debug-adapter-protocol/specification
+//
+// The adapter listens on a local port, point the editor at it with
+// "debugServer": <port>. Launch arguments:
+//   "program": path to the rom
+//   "symbols": optional symbol file, needed for source breakpoints
+//   "stopOnEntry": stop before the first instruction
+
+const THREAD_ID: i64 = 1;
+
+const SCOPE_REGISTERS: i64 = 1;
+const SCOPE_TIMERS: i64 = 2;
+const SCOPE_KEYPAD: i64 = 3;
+
+// Instructions to run between checks for new requests while running
+const STEPS_PER_POLL: u32 = 1000;
+
+// Give up on step over / step out if the call never returns
+const MAX_STEP_OVER: u32 = 1_000_000;
+
+pub struct Session {
+    pub chip8: Chip8,
+    pub debugger: Debugger,
+    // The editor sets these two kinds separately, the debugger gets both
+    source_breakpoints: Vec<u16>,
+    instruction_breakpoints: Vec<u16>,
+    pub symbols: Option<Symbols>,
+    // --machine, --load-address and --rom-entry for the launched program
+    loader: RomLoader,
+    memory_map: MemoryMap,
+    // the fonts, quirks and speed the command line resolved
+    fonts: Fonts,
+    quirks: Quirks,
+    instructions_per_frame: u32,
+    // Source paths in the symbol file are relative to it
+    symbols_dir: PathBuf,
+    pub running: bool,
+    // the next slice of a run steps off a breakpoint at the pc
+    resume: bool,
+    stop_on_entry: bool,
+    seq: i64,
+    pub terminated: bool,
+}
+
+impl Session {
+    pub fn new(
+        loader: RomLoader,
+        memory_map: MemoryMap,
+        fonts: Fonts,
+        quirks: Quirks,
+        instructions_per_frame: u32,
+    ) -> Session {
+        Session {
+            chip8: Chip8::new(),
+            debugger: Debugger::new(),
+            source_breakpoints: Vec::new(),
+            instruction_breakpoints: Vec::new(),
+            symbols: None,
+            loader,
+            memory_map,
+            fonts,
+            quirks,
+            instructions_per_frame,
+            symbols_dir: PathBuf::new(),
+            running: false,
+            resume: false,
+            stop_on_entry: false,
+            seq: 0,
+            terminated: false,
+        }
+    }
+
+    fn next_seq(&mut self) -> i64 {
+        self.seq += 1;
+        self.seq
+    }
+
+    fn response(&mut self, request: &Value, success: bool, body: Value) -> Value {
+        let mut response = json!({
+            "seq": self.next_seq(),
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": success,
+            "body": body,
+        });
+        if !success {
+            response["message"] = body["error"].clone();
+        }
+        response
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> Value {
+        json!({
+            "seq": self.next_seq(),
+            "type": "event",
+            "event": event,
+            "body": body,
+        })
+    }
+
+    fn stopped(&mut self, reason: &str) -> Value {
+        self.running = false;
+        self.event(
+            "stopped",
+            json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true }),
+        )
+    }
+
+    fn error(&mut self, request: &Value, message: String) -> Vec<Value> {
+        vec![self.response(request, false, json!({ "error": message }))]
+    }
+
+    // Handle one request, returning the response followed by any events
+    pub fn handle(&mut self, request: &Value) -> Vec<Value> {
+        let args = &request["arguments"];
+        let command = request["command"].as_str().unwrap_or("");
+        match command {
+            "initialize" => {
+                let capabilities = json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsSetVariable": true,
+                    "supportsReadMemoryRequest": true,
+                    "supportsWriteMemoryRequest": true,
+                    "supportsInstructionBreakpoints": true,
+                });
+                let response = self.response(request, true, capabilities);
+                let initialized = self.event("initialized", json!({}));
+                vec![response, initialized]
+            }
+            "launch" => self.launch(request),
+            "setBreakpoints" => self.set_breakpoints(request),
+            "setInstructionBreakpoints" => self.set_instruction_breakpoints(request),
+            "setExceptionBreakpoints" => vec![self.response(request, true, json!({}))],
+            "configurationDone" => {
+                let response = self.response(request, true, json!({}));
+                if self.stop_on_entry {
+                    let stopped = self.stopped("entry");
+                    return vec![response, stopped];
+                }
+                self.running = true;
+                self.resume = true;
+                vec![response]
+            }
+            "threads" => {
+                let body = json!({ "threads": [{ "id": THREAD_ID, "name": "chip8" }] });
+                vec![self.response(request, true, body)]
+            }
+            "stackTrace" => {
+                let body = json!({ "stackFrames": self.stack_frames() });
+                vec![self.response(request, true, body)]
+            }
+            "scopes" => {
+                let body = json!({ "scopes": [
+                    { "name": "Registers", "variablesReference": SCOPE_REGISTERS, "expensive": false },
+                    { "name": "Timers", "variablesReference": SCOPE_TIMERS, "expensive": false },
+                    { "name": "Keypad", "variablesReference": SCOPE_KEYPAD, "expensive": false },
+                ]});
+                vec![self.response(request, true, body)]
+            }
+            "variables" => {
+                let reference = args["variablesReference"].as_i64().unwrap_or(0);
+                let body = json!({ "variables": self.variables(reference) });
+                vec![self.response(request, true, body)]
+            }
+            "setVariable" => self.set_variable(request),
+            "readMemory" => self.read_memory(request),
+            "writeMemory" => self.write_memory(request),
+            "continue" => {
+                self.running = true;
+                self.resume = true;
+                vec![self.response(request, true, json!({ "allThreadsContinued": true }))]
+            }
+            "pause" => {
+                let response = self.response(request, true, json!({}));
+                let stopped = self.stopped("pause");
+                vec![response, stopped]
+            }
+            "next" | "stepIn" | "stepOut" => {
+                let response = self.response(request, true, json!({}));
+                let reason = match command {
+                    "next" => self.step_over(),
+                    "stepOut" => self.step_out(),
+                    _ => self.debugger.step(&mut self.chip8),
+                };
+                let stopped = self.stopped(stop_reason_name(reason));
+                vec![response, stopped]
+            }
+            "disconnect" | "terminate" => {
+                self.terminated = true;
+                let response = self.response(request, true, json!({}));
+                let terminated = self.event("terminated", json!({}));
+                vec![response, terminated]
+            }
+            _ => self.error(request, format!("Unsupported request: {}", command)),
+        }
+    }
+
+    fn launch(&mut self, request: &Value) -> Vec<Value> {
+        let args = &request["arguments"];
+        let program = match args["program"].as_str() {
+            Some(x) => x,
+            None => return self.error(request, "launch needs a \"program\"".to_string()),
+        };
+        let rom = match self.loader.load(Path::new(program)) {
+            Ok(x) => x,
+            Err(e) => return self.error(request, e),
+        };
+        if let Some(path) = args["symbols"].as_str() {
+            match load_symbols(Path::new(path)) {
+                Ok(symbols) => self.symbols = Some(symbols),
+                Err(e) => return self.error(request, e),
+            }
+            self.symbols_dir = Path::new(path)
+                .parent()
+                .map(|p| p.to_path_buf())
+                .unwrap_or_default();
+        } else if rom.symbols.is_some() {
+            // compiled from source, the lines point next to the program
+            self.symbols = rom.symbols.clone();
+            self.symbols_dir = Path::new(program)
+                .parent()
+                .map(|p| p.to_path_buf())
+                .unwrap_or_default();
+        }
+        self.chip8 = machine(self.memory_map, &self.fonts, &rom, self.quirks, self.instructions_per_frame);
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+        vec![self.response(request, true, json!({}))]
+    }
+
+    // Breakpoints arrive per source file, replace the ones from that file
+    fn set_breakpoints(&mut self, request: &Value) -> Vec<Value> {
+        let args = &request["arguments"];
+        let source_path = args["source"]["path"].as_str().unwrap_or("").to_string();
+        let old = self.addresses_for_source(&source_path);
+        self.source_breakpoints.retain(|a| !old.contains(a));
+
+        let mut results = Vec::new();
+        let requested = args["breakpoints"].as_array().cloned().unwrap_or_default();
+        for bp in requested {
+            let line = bp["line"].as_u64().unwrap_or(0) as u32;
+            match self.address_for_line(&source_path, line) {
+                Some(address) => {
+                    self.source_breakpoints.push(address);
+                    results.push(json!({ "verified": true, "line": line }));
+                }
+                None => results.push(json!({
+                    "verified": false,
+                    "line": line,
+                    "message": "No code at this line",
+                })),
+            }
+        }
+        self.sync_breakpoints();
+        vec![self.response(request, true, json!({ "breakpoints": results }))]
+    }
+
+    // Instruction breakpoints come all at once, replace them all
+    fn set_instruction_breakpoints(&mut self, request: &Value) -> Vec<Value> {
+        self.instruction_breakpoints.clear();
+        let mut results = Vec::new();
+        let requested = request["arguments"]["breakpoints"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default();
+        for bp in requested {
+            let reference = bp["instructionReference"].as_str().and_then(|r| {
+                match &self.symbols {
+                    Some(symbols) => symbols.resolve(r),
+                    None => parse_reference(r),
+                }
+            });
+            let offset = bp["offset"].as_i64().unwrap_or(0);
+            match reference {
+                Some(address) => {
+                    self.instruction_breakpoints.push((address as i64 + offset) as u16);
+                    results.push(json!({ "verified": true }));
+                }
+                None => results.push(json!({ "verified": false })),
+            }
+        }
+        self.sync_breakpoints();
+        vec![self.response(request, true, json!({ "breakpoints": results }))]
+    }
+
+    fn sync_breakpoints(&mut self) {
+        self.debugger.breakpoints.clear();
+        for address in self.source_breakpoints.iter().chain(&self.instruction_breakpoints) {
+            self.debugger.add_breakpoint(*address);
+        }
+    }
+
+    fn same_source(&self, file: &str, source_path: &str) -> bool {
+        let full = self.symbols_dir.join(file);
+        Path::new(source_path) == full || Path::new(source_path).ends_with(file)
+    }
+
+    fn addresses_for_source(&self, source_path: &str) -> Vec<u16> {
+        match &self.symbols {
+            Some(symbols) => symbols
+                .lines
+                .iter()
+                .filter(|(_, s)| self.same_source(&s.file, source_path))
+                .map(|(a, _)| *a)
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    // First address generated by the line, so a breakpoint stops before it runs
+    fn address_for_line(&self, source_path: &str, line: u32) -> Option<u16> {
+        let symbols = self.symbols.as_ref()?;
+        symbols
+            .lines
+            .iter()
+            .filter(|(_, s)| s.line == line && self.same_source(&s.file, source_path))
+            .map(|(a, _)| *a)
+            .min()
+    }
+
+    fn frame(&self, id: i64, address: u16) -> Value {
+        let mut frame = json!({
+            "id": id,
+            "name": describe_address(self.symbols.as_ref(), address),
+            "line": 0,
+            "column": 0,
+            "instructionPointerReference": format!("{:#X}", address),
+        });
+        if let Some(line) = self.symbols.as_ref().and_then(|s| s.line_at(address)) {
+            let path = self.symbols_dir.join(&line.file);
+            frame["source"] = json!({
+                "name": line.file,
+                "path": path.to_str().unwrap_or(""),
+            });
+            frame["line"] = json!(line.line);
+            frame["column"] = json!(1);
+        }
+        frame
+    }
+
+    // Current pc followed by the callers saved on the call stack
+    fn stack_frames(&self) -> Vec<Value> {
+        let mut frames = vec![self.frame(0, self.chip8.pc)];
+        for ret in self.chip8.call_stack() {
+            // the call itself is the instruction before the return address
+            frames.push(self.frame(frames.len() as i64, ret.wrapping_sub(2)));
+        }
+        frames
+    }
+
+    fn variables(&self, reference: i64) -> Vec<Value> {
+        let c = &self.chip8;
+        let byte = |name: String, value: u8| {
+            json!({ "name": name, "value": format!("{:#04X}", value), "variablesReference": 0 })
+        };
+        let pointer = |name: &str, value: u16| {
+            json!({
+                "name": name,
+                "value": format!("{:#05X}", value),
+                "variablesReference": 0,
+                "memoryReference": format!("{:#X}", value),
+            })
+        };
+        match reference {
+            SCOPE_REGISTERS => {
+                let mut vars: Vec<Value> = (0..16).map(|n| byte(format!("v{:x}", n), c.v[n])).collect();
+                vars.push(pointer("i", c.i));
+                vars.push(pointer("pc", c.pc));
+                vars.push(byte("sp".to_string(), c.sp));
+                vars
+            }
+            SCOPE_TIMERS => vec![
+                byte("dt".to_string(), c.timer_delay),
+                byte("st".to_string(), c.timer_sound),
+            ],
+            SCOPE_KEYPAD => (0..16)
+                .map(|k| {
+                    let state = if c.is_key_down(k) { "down" } else { "up" };
+                    json!({ "name": format!("{:X}", k), "value": state, "variablesReference": 0 })
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn set_variable(&mut self, request: &Value) -> Vec<Value> {
+        let args = &request["arguments"];
+        let name = args["name"].as_str().unwrap_or("");
+        let value = match args["value"].as_str().and_then(parse_reference) {
+            Some(x) => x,
+            None => return self.error(request, "Expected a number".to_string()),
+        };
+        let c = &mut self.chip8;
+        match name {
+            "i" => c.i = value,
+            "pc" => c.pc = value,
+            "sp" => c.sp = value as u8,
+            "dt" => c.timer_delay = value as u8,
+            "st" => c.timer_sound = value as u8,
+            _ if name.starts_with('v') && name.len() == 2 => {
+                match usize::from_str_radix(&name[1..], 16) {
+                    Ok(n) => c.v[n] = value as u8,
+                    Err(_) => return self.error(request, format!("Unknown register {}", name)),
+                }
+            }
+            _ if name.len() == 1 => match u8::from_str_radix(name, 16) {
+                Ok(k) if value != 0 => c.press_key(k),
+                Ok(k) => c.release_key(k),
+                Err(_) => return self.error(request, format!("Unknown key {}", name)),
+            },
+            _ => return self.error(request, format!("Cannot set {}", name)),
+        }
+        let body = json!({ "value": format!("{:#X}", value) });
+        vec![self.response(request, true, body)]
+    }
+
+    fn read_memory(&mut self, request: &Value) -> Vec<Value> {
+        let args = &request["arguments"];
+        let base = args["memoryReference"].as_str().and_then(parse_reference);
+        let offset = args["offset"].as_i64().unwrap_or(0);
+        let count = args["count"].as_u64().unwrap_or(0) as usize;
+        let start = match base {
+            Some(base) => (base as i64).saturating_add(offset).clamp(0, MEMORY_SIZE as i64) as usize,
+            None => return self.error(request, "Bad memoryReference".to_string()),
+        };
+        let end = start.saturating_add(count).min(MEMORY_SIZE);
+        let body = json!({
+            "address": format!("{:#X}", start),
+            "unreadableBytes": count - (end - start),
+            "data": base64_encode(&self.chip8.memory[start..end]),
+        });
+        vec![self.response(request, true, body)]
+    }
+
+    fn write_memory(&mut self, request: &Value) -> Vec<Value> {
+        let args = &request["arguments"];
+        let base = args["memoryReference"].as_str().and_then(parse_reference);
+        let offset = args["offset"].as_i64().unwrap_or(0);
+        let data = args["data"].as_str().and_then(base64_decode);
+        match (base, data) {
+            (Some(base), Some(data)) => {
+                let start = (base as i64).saturating_add(offset).clamp(0, MEMORY_SIZE as i64) as usize;
+                let end = match start.checked_add(data.len()) {
+                    Some(end) if end <= MEMORY_SIZE => end,
+                    _ => return self.error(request, "Write past end of memory".to_string()),
+                };
+                self.chip8.memory[start..end].copy_from_slice(&data);
+                let body = json!({ "bytesWritten": data.len() });
+                vec![self.response(request, true, body)]
+            }
+            _ => self.error(request, "Bad writeMemory arguments".to_string()),
+        }
+    }
+
+    // Step, running a whole subroutine if the instruction is a call
+    fn step_over(&mut self) -> StopReason {
+        let pc = self.chip8.pc as usize;
+        if pc + 1 >= MEMORY_SIZE {
+            return StopReason::OutOfMemory;
+        }
+        if self.chip8.memory[pc] >> 4 != 2 {
+            return self.debugger.step(&mut self.chip8);
+        }
+        let return_to = self.chip8.pc + 2;
+        let sp = self.chip8.sp;
+        self.run_until(|c| c.pc == return_to && c.sp == sp)
+    }
+
+    fn step_out(&mut self) -> StopReason {
+        if self.chip8.sp == 0 {
+            return self.debugger.step(&mut self.chip8);
+        }
+        let sp = self.chip8.sp;
+        self.run_until(|c| c.sp < sp)
+    }
+
+    fn run_until<F: Fn(&Chip8) -> bool>(&mut self, done: F) -> StopReason {
+        for n in 0..MAX_STEP_OVER {
+            if n > 0 && self.debugger.breakpoints.contains(&self.chip8.pc) {
+                return StopReason::Breakpoint(self.chip8.pc);
+            }
+            match self.debugger.step(&mut self.chip8) {
+                StopReason::Step => {}
+                reason => return reason,
+            }
+            if done(&self.chip8) {
+                return StopReason::Step;
+            }
+        }
+        StopReason::Step
+    }
+
+    // Run a slice of instructions while the editor has us running
+    pub fn run_some(&mut self) -> Option<Value> {
+        let resume = self.resume;
+        self.resume = false;
+        match self.debugger.cont(&mut self.chip8, STEPS_PER_POLL, resume) {
+            StopReason::Limit => None,
+            reason => Some(self.stopped(stop_reason_name(reason))),
+        }
+    }
+}
+
+fn stop_reason_name(reason: StopReason) -> &'static str {
+    match reason {
+        StopReason::Breakpoint(_) => "breakpoint",
+        StopReason::Watchpoint(_, _) => "data breakpoint",
+        StopReason::OutOfMemory => "exception",
+        StopReason::Limit => "pause",
+        StopReason::Step => "step",
+    }
+}
+
+pub fn run_dap_server(
+    port: u16,
+    loader: RomLoader,
+    memory_map: MemoryMap,
+    fonts: Fonts,
+    quirks: Quirks,
+    instructions_per_frame: u32,
+) {
+    let listener = match TcpListener::bind(("127.0.0.1", port)) {
+        Ok(x) => x,
+        Err(e) => {
+            println!("Could not listen on port {}: {}", port, e);
+            return;
+        }
+    };
+    println!("Waiting for debug adapter client on 127.0.0.1:{}", port);
+    let stream = match listener.accept() {
+        Ok((stream, _)) => stream,
+        Err(e) => {
+            println!("Could not accept connection: {}", e);
+            return;
+        }
+    };
+    let mut writer = match stream.try_clone() {
+        Ok(x) => x,
+        Err(e) => {
+            println!("Could not clone connection: {}", e);
+            return;
+        }
+    };
+    let requests = spawn_reader(stream);
+
+    let mut session = Session::new(loader, memory_map, fonts, quirks, instructions_per_frame);
+    while !session.terminated {
+        let request = match session.running {
+            true => match requests.try_recv() {
+                Ok(request) => Some(request),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => break,
+            },
+            false => match requests.recv() {
+                Ok(request) => Some(request),
+                Err(_) => break,
+            },
+        };
+        let messages = match request {
+            Some(request) => session.handle(&request),
+            None => session.run_some().into_iter().collect(),
+        };
+        for message in messages {
+            if !send_message(&mut writer, &message) {
+                return;
+            }
+        }
+    }
+}
+
+// Requests are read on their own thread so the emulator can run in between
+fn spawn_reader(stream: TcpStream) -> Receiver<Value> {
+    let (sender, receiver) = channel();
+    std::thread::spawn(move || {
+        let mut reader = BufReader::new(stream);
+        while let Some(message) = read_message(&mut reader) {
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+fn read_message<R: BufRead>(reader: &mut R) -> Option<Value> {
+    let mut length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).ok()? == 0 {
+            return None;
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse().ok()?;
+        }
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).ok()?;
+    serde_json::from_slice(&body).ok()
+}
+
+fn send_message<W: Write>(writer: &mut W, message: &Value) -> bool {
+    let body = message.to_string();
+    let packet = format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+    writer.write_all(packet.as_bytes()).is_ok()
+}
+
+// "0x2A4" or decimal
+fn parse_reference(text: &str) -> Option<u16> {
+    let lower = text.to_lowercase();
+    match lower.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => lower.parse().ok(),
+    }
+}
+
+const BASE64: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut bits = 0u32;
+    let mut count = 0;
+    for c in text.bytes().filter(|c| *c != b'=') {
+        let value = BASE64.iter().position(|b| *b == c)? as u32;
+        bits = (bits << 6) | value;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            out.push((bits >> count) as u8);
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::symbols::parse_symbols;
+    use crate::chip8::INSTRUCTIONS_PER_FRAME;
+
+    fn session() -> Session {
+        Session::new(
+            RomLoader::new(),
+            MemoryMap::default(),
+            Fonts::default(),
+            Quirks::default(),
+            INSTRUCTIONS_PER_FRAME,
+        )
+    }
+
+    #[test]
+    fn base64_round_trip_test() {
+        assert_eq!("YWJj", base64_encode(b"abc"));
+        assert_eq!("YWI=", base64_encode(b"ab"));
+        assert_eq!(b"ab".to_vec(), base64_decode("YWI=").unwrap());
+    }
+
+    #[test]
+    fn breakpoint_on_poll_boundary_test() {
+        let mut session = session();
+        // STEPS_PER_POLL v0 := 0 in a row, then the breakpoint
+        let program: Vec<u8> = (0..STEPS_PER_POLL).flat_map(|_| vec![0x60, 0x00]).collect();
+        session.chip8.load_program_at(&program, 0x200);
+        let end = 0x200 + program.len() as u16;
+        session.debugger.add_breakpoint(end);
+        session.handle(&json!({ "seq": 1, "command": "continue" }));
+        assert_eq!(None, session.run_some());
+        let stopped = session.run_some().unwrap();
+        assert_eq!("breakpoint", stopped["body"]["reason"]);
+        assert_eq!(end, session.chip8.pc);
+    }
+
+    #[test]
+    fn memory_bounds_test() {
+        let mut session = session();
+        let read = json!({
+            "seq": 1,
+            "command": "readMemory",
+            "arguments": { "memoryReference": "0xFFF", "offset": i64::MAX, "count": u64::MAX },
+        });
+        assert_eq!(true, session.handle(&read)[0]["success"]);
+        let write = json!({
+            "seq": 2,
+            "command": "writeMemory",
+            "arguments": { "memoryReference": "0xFFF", "offset": i64::MAX, "data": "YWI=" },
+        });
+        assert_eq!(false, session.handle(&write)[0]["success"]);
+        session.chip8.pc = 0xFFF;
+        assert_eq!(StopReason::OutOfMemory, session.step_over());
+    }
+
+    #[test]
+    fn source_breakpoint_test() {
+        let mut session = session();
+        session.symbols = Some(parse_symbols("0x200 game.8o:3\n0x204 game.8o:5\n").unwrap());
+        let request = json!({
+            "seq": 1,
+            "command": "setBreakpoints",
+            "arguments": {
+                "source": { "path": "/src/game.8o" },
+                "breakpoints": [{ "line": 5 }, { "line": 4 }],
+            },
+        });
+        let response = &session.handle(&request)[0];
+        assert_eq!(true, response["body"]["breakpoints"][0]["verified"]);
+        assert_eq!(false, response["body"]["breakpoints"][1]["verified"]);
+        assert_eq!(vec![0x204], session.debugger.breakpoints);
+    }
+
+    #[test]
+    fn instruction_breakpoints_keep_source_ones_test() {
+        let mut session = session();
+        session.symbols = Some(parse_symbols("0x200 game.8o:3\n0x204 game.8o:5\n").unwrap());
+        let source = json!({
+            "seq": 1,
+            "command": "setBreakpoints",
+            "arguments": { "source": { "path": "game.8o" }, "breakpoints": [{ "line": 5 }] },
+        });
+        let instructions = |references: Value| {
+            json!({
+                "seq": 2,
+                "command": "setInstructionBreakpoints",
+                "arguments": { "breakpoints": references },
+            })
+        };
+        session.handle(&source);
+        session.handle(&instructions(json!([{ "instructionReference": "0x204" }, { "instructionReference": "0x2A0" }])));
+        assert_eq!(vec![0x204, 0x2A0], session.debugger.breakpoints);
+        // clearing the disassembly view's breakpoints leaves the source ones
+        session.handle(&instructions(json!([])));
+        assert_eq!(vec![0x204], session.debugger.breakpoints);
+        // and the other way around
+        session.handle(&instructions(json!([{ "instructionReference": "0x2A0", "offset": 2 }])));
+        let clear = json!({
+            "seq": 3,
+            "command": "setBreakpoints",
+            "arguments": { "source": { "path": "game.8o" }, "breakpoints": [] },
+        });
+        session.handle(&clear);
+        assert_eq!(vec![0x2A2], session.debugger.breakpoints);
+    }
+}
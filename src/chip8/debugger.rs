@@ -0,0 +1,117 @@
+use crate::chip8::Chip8;
+use crate::chip8::MEMORY_SIZE;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WatchKind {
+    Write,
+    Read,
+    Access,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Watchpoint {
+    pub address: usize,
+    pub len: usize,
+    pub kind: WatchKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StopReason {
+    Step,
+    Breakpoint(u16),
+    Watchpoint(WatchKind, usize),
+    // The pc ran off the end of memory
+    OutOfMemory,
+    // Ran out of steps before anything stopped us
+    Limit,
+}
+
+// Breakpoints and watchpoints shared by the debugger front ends
+#[derive(Default)]
+pub struct Debugger {
+    pub breakpoints: Vec<u16>,
+    pub watchpoints: Vec<Watchpoint>,
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger::default()
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) {
+        if !self.breakpoints.contains(&address) {
+            self.breakpoints.push(address);
+        }
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16) {
+        self.breakpoints.retain(|a| *a != address);
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        if !self.watchpoints.contains(&watchpoint) {
+            self.watchpoints.push(watchpoint);
+        }
+    }
+
+    pub fn remove_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.retain(|w| *w != watchpoint);
+    }
+
+    // Execute one instruction, ending the frame when its budget is spent.
+    // Returns the watchpoint it tripped, if any.
+    pub fn step(&self, chip8: &mut Chip8) -> StopReason {
+        let pc = chip8.pc as usize;
+        if pc + 1 >= MEMORY_SIZE {
+            return StopReason::OutOfMemory;
+        }
+        let hit = match self.watchpoints.is_empty() {
+            true => None,
+            false => self.check_watchpoints(chip8, chip8.memory[pc], chip8.memory[pc + 1]),
+        };
+        let (b0, b1) = chip8.fetch();
+        chip8.decode_execute(b0, b1);
+        // the debuggers run frame by frame too, so timers tick as they would
+        if chip8.frame_over() {
+            chip8.end_frame();
+        }
+        match hit {
+            Some((kind, address)) => StopReason::Watchpoint(kind, address),
+            None => StopReason::Step,
+        }
+    }
+
+    // Run until a breakpoint or watchpoint, at most max_steps instructions.
+    // When resuming after a stop the instruction at the current pc always runs
+    // so we can leave a breakpoint; later batches of the same continue pass false.
+    pub fn cont(&self, chip8: &mut Chip8, max_steps: u32, resume: bool) -> StopReason {
+        for n in 0..max_steps {
+            if (n > 0 || !resume) && self.breakpoints.contains(&chip8.pc) {
+                return StopReason::Breakpoint(chip8.pc);
+            }
+            match self.step(chip8) {
+                StopReason::Step => {}
+                reason => return reason,
+            }
+        }
+        StopReason::Limit
+    }
+
+    fn check_watchpoints(&self, chip8: &Chip8, b0: u8, b1: u8) -> Option<(WatchKind, usize)> {
+        let access = chip8.memory_accesses(b0, b1);
+        for w in &self.watchpoints {
+            let range = w.address..w.address + w.len;
+            let write = access.writes.iter().find(|a| range.contains(a));
+            let read = access.reads.iter().find(|a| range.contains(a));
+            let found = match w.kind {
+                WatchKind::Write => write,
+                WatchKind::Read => read,
+                WatchKind::Access => write.or(read),
+            };
+            if let Some(address) = found {
+                return Some((w.kind, *address));
+            }
+        }
+        None
+    }
+}
@@ -1,8 +1,6 @@
 use crate::chip8::Chip8;
-use crate::chip8::Font;
+use crate::chip8::fonts::{FontSet, GLYPHS};
 use crate::chip8::COLS;
-use crate::chip8::DISPLAY;
-use crate::chip8::FONT_SPRITES;
 use crate::chip8::MEMORY_SIZE;
 use crate::chip8::ROWS;
 use crate::chip8::ROW_LEN;
@@ -12,16 +10,16 @@ pub fn display_text(chip8: &mut Chip8, glyph: char) {
 
     // draw to (0,0) a "1"
     draw_font_to_buffer(chip8, 0, 0, 1);
-    display_render(&chip8, true, glyph);
+    display_render(chip8, true, glyph);
 
     draw_font_to_buffer(chip8, 8, 0, 3);
-    display_render(&chip8, true, glyph);
+    display_render(chip8, true, glyph);
 
     draw_font_to_buffer(chip8, 0, 6, 5);
-    display_render(&chip8, true, glyph);
+    display_render(chip8, true, glyph);
 
     draw_font_to_buffer(chip8, 62, 12, 7);
-    display_render(&chip8, true, glyph);
+    display_render(chip8, true, glyph);
 
     // mem_dump(chip8);
 
@@ -40,18 +38,18 @@ pub fn display_render(chip8: &Chip8, debug: bool, glyph: char) {
         if debug {
             print!("{:02}:", row_i);
         }
-        let row_start = DISPLAY + (row_i * ROW_LEN);
+        let row_start = chip8.memory_map.display + (row_i * ROW_LEN);
         // print each col for row
         for i in 0..(COLS / 8) {
             let byte: u8 = chip8.memory[row_start + i];
             for b in 0..8 {
-                let bit = byte & (0x1 << 7 - b);
+                let bit = byte & (0x1 << (7 - b));
                 let draw = if bit == 0 { ' ' } else { glyph };
                 print!("{}", draw);
                 // section >>= 1;
             }
         }
-        println!("");
+        println!();
     }
     if debug {
         print!("   "); // padding for 01:
@@ -59,7 +57,7 @@ pub fn display_render(chip8: &Chip8, debug: bool, glyph: char) {
     for _i in 0..COLS {
         print!("_");
     }
-    print!("\n");
+    println!();
 }
 
 fn draw_font_to_buffer(chip8: &mut Chip8, x: u8, y: u8, val: u8) {
@@ -71,18 +69,19 @@ fn draw_font_to_buffer(chip8: &mut Chip8, x: u8, y: u8, val: u8) {
     chip8.load_font(2);
     chip8.draw(0, 1, 5);
 }
-pub fn dump_fonts(glyph: char) {
-    for i in 0..16 {
-        let f = FONT_SPRITES[i];
-        debug_font(f, glyph);
-        println!("");
+pub fn dump_fonts(font: &FontSet, glyph: char) {
+    for i in 0..GLYPHS {
+        debug_font(font.glyph(i), font.width, glyph);
+        println!();
     }
 }
 
+// for debugging, see the commented out call in display_text
+#[allow(dead_code)]
 pub fn mem_dump(chip8: &Chip8) {
     for i in 0..MEMORY_SIZE {
         if i % 32 == 0 {
-            print!("\n")
+            println!()
         } else if i % 4 == 0 {
             print!(" ")
         }
@@ -120,13 +119,13 @@ fn bios_draw(chip8: &Chip8) {
 }
 */
 
-pub fn debug_font(font: Font, block: char) {
-    for part in &font {
-        for i in 0..4 {
-            let val = (part << i) & (0x80 as u8);
-            let glyph = if val != (0 as u8) { block } else { ' ' };
+pub fn debug_font(font: &[u8], width: usize, block: char) {
+    for part in font {
+        for i in 0..width {
+            let val = (part << i) & 0x80_u8;
+            let glyph = if val != 0_u8 { block } else { ' ' };
             print!("{}", glyph);
         }
-        print!("\n");
+        println!();
     }
 }
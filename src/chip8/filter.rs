@@ -0,0 +1,138 @@
+use std::collections::VecDeque;
+
+// Flicker reduction. draw XORs sprites, so games erase and redraw every frame
+// and the erased sprite shows up as a blank frame. These filters keep some of
+// the previous frames around before rendering.
+//   blend:N     average of the last N frames
+//   max2        a pixel lit in this frame or the one before is lit
+//   phosphor:D  lit pixels fade by D (0-1) every frame like a CRT
+
+// Text output has no shades, pixels at least this bright are drawn
+pub const LIT_THRESHOLD: f32 = 0.25;
+
+const DEFAULT_BLEND: usize = 3;
+const MAX_BLEND: usize = 8;
+const DEFAULT_DECAY: f32 = 0.5;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FilterKind {
+    Blend(usize),
+    MaxOfTwo,
+    Phosphor(f32),
+}
+
+impl FilterKind {
+    // None for "none", so the renderers can skip filtering entirely
+    pub fn from_name(name: &str) -> Result<Option<FilterKind>, String> {
+        let mut parts = name.splitn(2, ':');
+        let kind = parts.next().unwrap_or("");
+        let arg = parts.next();
+        match (kind, arg) {
+            ("none", None) => Ok(None),
+            ("max2", None) => Ok(Some(FilterKind::MaxOfTwo)),
+            ("blend", None) => Ok(Some(FilterKind::Blend(DEFAULT_BLEND))),
+            ("blend", Some(n)) => match n.parse::<usize>() {
+                Ok(n) if (2..=MAX_BLEND).contains(&n) => Ok(Some(FilterKind::Blend(n))),
+                _ => Err(format!("blend takes 2 to {} frames, not {}", MAX_BLEND, n)),
+            },
+            ("phosphor", None) => Ok(Some(FilterKind::Phosphor(DEFAULT_DECAY))),
+            ("phosphor", Some(d)) => match d.parse::<f32>() {
+                Ok(d) if d > 0.0 && d < 1.0 => Ok(Some(FilterKind::Phosphor(d))),
+                _ => Err(format!("phosphor decay must be between 0 and 1, not {}", d)),
+            },
+            _ => Err(format!("Unknown filter: {}", name)),
+        }
+    }
+}
+
+pub struct DisplayFilter {
+    pub kind: FilterKind,
+    // most recent frame first
+    history: VecDeque<Vec<u8>>,
+    glow: Vec<f32>,
+}
+
+impl DisplayFilter {
+    pub fn new(kind: FilterKind) -> DisplayFilter {
+        DisplayFilter {
+            kind,
+            history: VecDeque::new(),
+            glow: Vec::new(),
+        }
+    }
+
+    fn remember(&mut self, frame: &[u8], frames: usize) {
+        self.history.push_front(frame.to_vec());
+        self.history.truncate(frames);
+    }
+
+    // Takes a frame of pixel values from screenshot::framebuffer and returns
+    // the brightness of every pixel from 0.0 to 1.0
+    pub fn apply(&mut self, frame: &[u8]) -> Vec<f32> {
+        let lit = |p: &u8| if *p != 0 { 1.0 } else { 0.0 };
+        match self.kind {
+            FilterKind::Blend(n) => {
+                self.remember(frame, n);
+                let mut out = vec![0.0; frame.len()];
+                for old in &self.history {
+                    for (o, p) in out.iter_mut().zip(old.iter()) {
+                        *o += lit(p) / n as f32;
+                    }
+                }
+                out
+            }
+            FilterKind::MaxOfTwo => {
+                self.remember(frame, 2);
+                let mut out = vec![0.0; frame.len()];
+                for old in &self.history {
+                    for (o, p) in out.iter_mut().zip(old.iter()) {
+                        *o = f32::max(*o, lit(p));
+                    }
+                }
+                out
+            }
+            FilterKind::Phosphor(decay) => {
+                if self.glow.len() != frame.len() {
+                    self.glow = vec![0.0; frame.len()];
+                }
+                for (g, p) in self.glow.iter_mut().zip(frame.iter()) {
+                    *g = f32::max(lit(p), *g * decay);
+                }
+                self.glow.clone()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn max_of_two_test() {
+        let mut filter = DisplayFilter::new(FilterKind::MaxOfTwo);
+        assert_eq!(vec![1.0, 0.0], filter.apply(&[1, 0]));
+        // the erased pixel stays lit for one frame
+        assert_eq!(vec![1.0, 0.0], filter.apply(&[0, 0]));
+        assert_eq!(vec![0.0, 0.0], filter.apply(&[0, 0]));
+    }
+
+    #[test]
+    fn blend_and_phosphor_test() {
+        let mut blend = DisplayFilter::new(FilterKind::Blend(2));
+        blend.apply(&[1]);
+        assert_eq!(vec![0.5], blend.apply(&[0]));
+        let mut phosphor = DisplayFilter::new(FilterKind::Phosphor(0.5));
+        phosphor.apply(&[1]);
+        phosphor.apply(&[0]);
+        assert_eq!(vec![0.25], phosphor.apply(&[0]));
+    }
+
+    #[test]
+    fn from_name_test() {
+        assert_eq!(Ok(None), FilterKind::from_name("none"));
+        assert_eq!(Ok(Some(FilterKind::Blend(4))), FilterKind::from_name("blend:4"));
+        assert!(FilterKind::from_name("blend:1").is_err());
+        assert!(FilterKind::from_name("phosphor:2").is_err());
+    }
+}
@@ -0,0 +1,336 @@
+use crate::chip8::FONT_SPRITES;
+use std::fs;
+use std::path::Path;
+
+// The hex digit fonts the interpreter keeps below the program: a small
+// 5 row font for Fx29 and a big 10 row one for the SCHIP Fx30. Besides the
+// presets a font can come from a file, either the raw bytes (80 or 160 of
+// them) or text with a block per digit, # for a set pixel:
+//
+//   0
+//   ####
+//   #..#
+//   ...
+//
+// The digit line is optional, blocks are separated by blank lines.
+
+pub const SMALL_HEIGHT: usize = 5;
+pub const BIG_HEIGHT: usize = 10;
+pub const GLYPHS: usize = 16;
+
+// Small fonts of the original interpreters
+const SMALL_PRESETS: [(&str, [u8; GLYPHS * SMALL_HEIGHT]); 3] = [
+    (
+        "vip",
+        [
+            0xF0, 0x90, 0x90, 0x90, 0xF0, 0x60, 0x20, 0x20, 0x20, 0x70, 0xF0, 0x10, 0xF0, 0x80, 0xF0, 0xF0, 0x10,
+            0xF0, 0x10, 0xF0, 0xA0, 0xA0, 0xF0, 0x20, 0x20, 0xF0, 0x80, 0xF0, 0x10, 0xF0, 0xF0, 0x80, 0xF0, 0x90,
+            0xF0, 0xF0, 0x10, 0x10, 0x10, 0x10, 0xF0, 0x90, 0xF0, 0x90, 0xF0, 0xF0, 0x90, 0xF0, 0x10, 0xF0, 0xF0,
+            0x90, 0xF0, 0x90, 0x90, 0xF0, 0x50, 0x70, 0x50, 0xF0, 0xF0, 0x80, 0x80, 0x80, 0xF0, 0xF0, 0x50, 0x50,
+            0x50, 0xF0, 0xF0, 0x80, 0xF0, 0x80, 0xF0, 0xF0, 0x80, 0xF0, 0x80, 0x80,
+        ],
+    ),
+    (
+        "eti660",
+        [
+            0xE0, 0xA0, 0xA0, 0xA0, 0xE0, 0x20, 0x20, 0x20, 0x20, 0x20, 0xE0, 0x20, 0xE0, 0x80, 0xE0, 0xE0, 0x20,
+            0xE0, 0x20, 0xE0, 0xA0, 0xA0, 0xE0, 0x20, 0x20, 0xE0, 0x80, 0xE0, 0x20, 0xE0, 0xE0, 0x80, 0xE0, 0xA0,
+            0xE0, 0xE0, 0x20, 0x20, 0x20, 0x20, 0xE0, 0xA0, 0xE0, 0xA0, 0xE0, 0xE0, 0xA0, 0xE0, 0x20, 0xE0, 0xE0,
+            0xA0, 0xE0, 0xA0, 0xA0, 0x80, 0x80, 0xE0, 0xA0, 0xE0, 0xE0, 0x80, 0x80, 0x80, 0xE0, 0x20, 0x20, 0xE0,
+            0xA0, 0xE0, 0xE0, 0x80, 0xE0, 0x80, 0xE0, 0xE0, 0x80, 0xC0, 0x80, 0x80,
+        ],
+    ),
+    (
+        "dream6800",
+        [
+            0xE0, 0xA0, 0xA0, 0xA0, 0xE0, 0x40, 0x40, 0x40, 0x40, 0x40, 0xE0, 0x20, 0xE0, 0x80, 0xE0, 0xE0, 0x20,
+            0xE0, 0x20, 0xE0, 0x80, 0xA0, 0xA0, 0xE0, 0x20, 0xE0, 0x80, 0xE0, 0x20, 0xE0, 0xE0, 0x80, 0xE0, 0xA0,
+            0xE0, 0xE0, 0x20, 0x20, 0x20, 0x20, 0xE0, 0xA0, 0xE0, 0xA0, 0xE0, 0xE0, 0xA0, 0xE0, 0x20, 0xE0, 0xE0,
+            0xA0, 0xE0, 0xA0, 0xA0, 0xC0, 0xA0, 0xE0, 0xA0, 0xC0, 0xE0, 0x80, 0x80, 0x80, 0xE0, 0xC0, 0xA0, 0xA0,
+            0xA0, 0xC0, 0xE0, 0x80, 0xE0, 0x80, 0xE0, 0xE0, 0x80, 0xC0, 0x80, 0x80,
+        ],
+    ),
+];
+
+// SCHIP 1.1 had big digits 0-9 only, A-F are the ones Octo adds
+const SCHIP_BIG: [u8; GLYPHS * BIG_HEIGHT] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+pub const PRESET_NAMES: [&str; 5] = ["chip8", "vip", "eti660", "dream6800", "schip"];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FontSet {
+    pub name: String,
+    // pixels across, 4 for the small fonts
+    pub width: usize,
+    pub height: usize,
+    // GLYPHS glyphs of height bytes each
+    pub bytes: Vec<u8>,
+}
+
+// The fonts a machine is given
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fonts {
+    pub small: FontSet,
+    pub big: FontSet,
+}
+
+impl Default for Fonts {
+    fn default() -> Fonts {
+        Fonts {
+            small: FontSet::preset("chip8").unwrap(),
+            big: FontSet::preset("schip").unwrap(),
+        }
+    }
+}
+
+impl FontSet {
+    pub fn preset(name: &str) -> Option<FontSet> {
+        let (width, bytes) = match name {
+            "chip8" => (4, FONT_SPRITES.iter().flat_map(|f| f.iter().cloned()).collect()),
+            "schip" => (8, SCHIP_BIG.to_vec()),
+            _ => {
+                let (_, bytes) = SMALL_PRESETS.iter().find(|(n, _)| *n == name)?;
+                (4, bytes.to_vec())
+            }
+        };
+        Some(FontSet {
+            name: name.to_string(),
+            width,
+            height: bytes.len() / GLYPHS,
+            bytes,
+        })
+    }
+
+    pub fn glyph(&self, digit: usize) -> &[u8] {
+        &self.bytes[digit * self.height..(digit + 1) * self.height]
+    }
+
+    pub fn is_set(&self, digit: usize, x: usize, y: usize) -> bool {
+        self.glyph(digit)[y] & (0x80 >> x) != 0
+    }
+
+    pub fn toggle(&mut self, digit: usize, x: usize, y: usize) {
+        self.bytes[digit * self.height + y] ^= 0x80 >> x;
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        for digit in 0..GLYPHS {
+            if digit > 0 {
+                out.push('\n');
+            }
+            out.push_str(&format!("{:X}\n", digit));
+            for y in 0..self.height {
+                for x in 0..self.width {
+                    out.push(if self.is_set(digit, x, y) { '#' } else { '.' });
+                }
+                out.push('\n');
+            }
+        }
+        out
+    }
+}
+
+pub fn parse_font_text(name: &str, text: &str) -> Result<FontSet, String> {
+    let mut glyphs: Vec<Vec<u8>> = Vec::new();
+    let mut width = 0;
+    let mut block: Vec<u8> = Vec::new();
+    for line in text.lines().map(|l| l.trim()).chain(std::iter::once("")) {
+        if line.is_empty() {
+            if !block.is_empty() {
+                glyphs.push(block);
+                block = Vec::new();
+            }
+            continue;
+        }
+        // the digit a block is for
+        if block.is_empty() && line.len() == 1 && line.chars().all(|c| c.is_ascii_hexdigit()) {
+            continue;
+        }
+        if line.len() > 8 {
+            return Err(format!("Font rows are at most 8 pixels: {}", line));
+        }
+        width = width.max(line.len());
+        let row = line
+            .chars()
+            .enumerate()
+            .filter(|(_, c)| *c == '#')
+            .fold(0u8, |row, (x, _)| row | 0x80 >> x);
+        block.push(row);
+    }
+    if glyphs.len() != GLYPHS {
+        return Err(format!("A font has {} glyphs, found {}", GLYPHS, glyphs.len()));
+    }
+    let height = glyphs[0].len();
+    if glyphs.iter().any(|g| g.len() != height) {
+        return Err("Font glyphs should all be the same height".to_string());
+    }
+    Ok(FontSet {
+        name: name.to_string(),
+        width,
+        height,
+        bytes: glyphs.concat(),
+    })
+}
+
+pub fn load_font_file(path: &Path) -> Result<FontSet, String> {
+    let bytes = match fs::read(path) {
+        Ok(x) => x,
+        Err(e) => return Err(format!("Could not read font {}: {}", path.display(), e)),
+    };
+    let name = path.display().to_string();
+    match String::from_utf8(bytes.clone()) {
+        Ok(text) if text.contains('#') => parse_font_text(&name, &text),
+        _ if bytes.len() == GLYPHS * SMALL_HEIGHT || bytes.len() == GLYPHS * BIG_HEIGHT => {
+            let height = bytes.len() / GLYPHS;
+            Ok(FontSet {
+                name,
+                width: if height == SMALL_HEIGHT { 4 } else { 8 },
+                height,
+                bytes,
+            })
+        }
+        _ => Err(format!("Not a font file: {}", path.display())),
+    }
+}
+
+// A preset by name or a font file, with the rows the interpreter needs
+pub fn find_font(name: &str, height: usize) -> Result<FontSet, String> {
+    let font = match FontSet::preset(name) {
+        Some(x) => x,
+        None if Path::new(name).exists() => load_font_file(Path::new(name))?,
+        None => {
+            return Err(format!(
+                "Unknown font: {} ({} or a font file)",
+                name,
+                PRESET_NAMES.join(", ")
+            ))
+        }
+    };
+    if font.height != height {
+        return Err(format!("{} has {} rows, this font needs {}", name, font.height, height));
+    }
+    Ok(font)
+}
+
+// The font editor's state, drawn by the TUI
+pub struct FontEditor {
+    pub font: FontSet,
+    pub digit: usize,
+    pub x: usize,
+    pub y: usize,
+    pub modified: bool,
+}
+
+impl FontEditor {
+    pub fn new(font: FontSet) -> FontEditor {
+        FontEditor {
+            font,
+            digit: 0,
+            x: 0,
+            y: 0,
+            modified: false,
+        }
+    }
+
+    // The cursor stays on the glyph
+    pub fn move_cursor(&mut self, dx: i32, dy: i32) {
+        let x = (self.x as i32 + dx).max(0).min(self.font.width as i32 - 1);
+        let y = (self.y as i32 + dy).max(0).min(self.font.height as i32 - 1);
+        self.x = x as usize;
+        self.y = y as usize;
+    }
+
+    pub fn toggle(&mut self) {
+        self.font.toggle(self.digit, self.x, self.y);
+        self.modified = true;
+    }
+
+    pub fn next_glyph(&mut self, step: i32) {
+        self.digit = (self.digit as i32 + step).rem_euclid(GLYPHS as i32) as usize;
+    }
+
+    // debug_font's grid with the cursor in brackets, then every glyph small
+    pub fn lines(&self, block: char) -> Vec<String> {
+        let mut lines = vec![format!("{} digit {:X}", self.font.name, self.digit)];
+        for y in 0..self.font.height {
+            let mut line = String::new();
+            for x in 0..self.font.width {
+                let pixel = if self.font.is_set(self.digit, x, y) { block } else { '.' };
+                match (x, y) == (self.x, self.y) {
+                    true => line.push_str(&format!("[{}]", pixel)),
+                    false => line.push_str(&format!(" {} ", pixel)),
+                }
+            }
+            lines.push(line);
+        }
+        lines.push(String::new());
+        for y in 0..self.font.height {
+            let mut line = String::new();
+            for digit in 0..GLYPHS {
+                for x in 0..self.font.width {
+                    line.push(if self.font.is_set(digit, x, y) { block } else { ' ' });
+                }
+                line.push(' ');
+            }
+            lines.push(line);
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_test() {
+        for name in PRESET_NAMES.iter() {
+            let font = FontSet::preset(name).unwrap();
+            assert_eq!(GLYPHS * font.height, font.bytes.len());
+        }
+        assert_eq!(&FONT_SPRITES[7], FontSet::preset("chip8").unwrap().glyph(7));
+        assert!(find_font("schip", SMALL_HEIGHT).is_err());
+        assert!(find_font("nope", SMALL_HEIGHT).is_err());
+        assert_eq!(BIG_HEIGHT, find_font("schip", BIG_HEIGHT).unwrap().height);
+    }
+
+    #[test]
+    fn text_round_trip_test() {
+        let font = FontSet::preset("eti660").unwrap();
+        let text = font.to_text();
+        assert!(text.starts_with("0\n###.\n#.#.\n"));
+        assert_eq!(font.bytes, parse_font_text("eti660", &text).unwrap().bytes);
+        assert!(parse_font_text("short", "#\n\n#").is_err());
+    }
+
+    #[test]
+    fn editor_test() {
+        let mut editor = FontEditor::new(FontSet::preset("chip8").unwrap());
+        editor.move_cursor(10, 1);
+        assert_eq!((3, 1), (editor.x, editor.y));
+        editor.toggle();
+        assert_eq!(0x90 ^ 0x10, editor.font.glyph(0)[1]);
+        editor.next_glyph(-1);
+        assert_eq!(0xF, editor.digit);
+        assert_eq!("chip8 digit F", editor.lines('#')[0]);
+        assert_eq!(" #  .  . [.]", editor.lines('#')[2]);
+    }
+}
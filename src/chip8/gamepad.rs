@@ -0,0 +1,320 @@
+use crate::chip8::Chip8;
+use std::fs;
+use std::path::Path;
+
+// Gamepad buttons and the left stick mapped to hex keys. Input comes from an
+// InputSource so the mapping works the same with raylib or a test script.
+//
+// The keymap file takes a [gamepad] section, globally or per rom:
+//
+//   [gamepad]
+//   stick = "numpad"
+//   [gamepad.buttons]
+//   a = "5"
+//   start = "F"
+//   [roms."brix.ch8".gamepad]
+//   stick = "wasd"
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Button {
+    Up,
+    Down,
+    Left,
+    Right,
+    A,
+    B,
+    X,
+    Y,
+    L1,
+    R1,
+    Select,
+    Start,
+}
+
+pub const BUTTONS: [(&str, Button); 12] = [
+    ("up", Button::Up),
+    ("down", Button::Down),
+    ("left", Button::Left),
+    ("right", Button::Right),
+    ("a", Button::A),
+    ("b", Button::B),
+    ("x", Button::X),
+    ("y", Button::Y),
+    ("l1", Button::L1),
+    ("r1", Button::R1),
+    ("select", Button::Select),
+    ("start", Button::Start),
+];
+
+impl Button {
+    pub fn from_name(name: &str) -> Option<Button> {
+        BUTTONS.iter().find(|(n, _)| *n == name).map(|(_, b)| *b)
+    }
+
+    fn bit(self) -> u32 {
+        1 << (self as u32)
+    }
+}
+
+// Stick presses past this count as a direction
+const STICK_THRESHOLD: f32 = 0.5;
+
+// Hex keys for up, down, left and right
+pub const STICK_PRESETS: [(&str, [u8; 4]); 2] = [
+    // 2/8/4/6 around 5 on the hex pad
+    ("numpad", [0x2, 0x8, 0x4, 0x6]),
+    // the keys under w, s, a and d in the qwerty keymap
+    ("wasd", [0x5, 0x8, 0x7, 0x9]),
+];
+
+// What the pad looks like this frame
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PadState {
+    // Button bits
+    pub buttons: u32,
+    // Left stick, -1.0 to 1.0 with y down
+    pub stick_x: f32,
+    pub stick_y: f32,
+}
+
+impl PadState {
+    pub fn with(mut self, button: Button) -> PadState {
+        self.buttons |= button.bit();
+        self
+    }
+
+    pub fn is_down(&self, button: Button) -> bool {
+        self.buttons & button.bit() != 0
+    }
+}
+
+pub trait InputSource {
+    // None when no pad is connected
+    fn poll(&mut self) -> Option<PadState>;
+}
+
+// Plays back a list of states, one per poll, then reports no pad
+#[cfg(test)]
+pub struct ScriptedInput {
+    pub states: Vec<PadState>,
+    next: usize,
+}
+
+#[cfg(test)]
+impl ScriptedInput {
+    pub fn new(states: Vec<PadState>) -> ScriptedInput {
+        ScriptedInput {
+            states,
+            next: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+impl InputSource for ScriptedInput {
+    fn poll(&mut self) -> Option<PadState> {
+        let state = self.states.get(self.next).cloned();
+        self.next += 1;
+        state
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GamepadMap {
+    pub buttons: Vec<(Button, u8)>,
+    // up, down, left, right
+    pub stick: Option<[u8; 4]>,
+}
+
+impl Default for GamepadMap {
+    // d-pad and stick on 2/8/4/6, the face buttons near them
+    fn default() -> GamepadMap {
+        GamepadMap {
+            buttons: vec![
+                (Button::Up, 0x2),
+                (Button::Down, 0x8),
+                (Button::Left, 0x4),
+                (Button::Right, 0x6),
+                (Button::A, 0x5),
+                (Button::B, 0x0),
+                (Button::X, 0xA),
+                (Button::Y, 0xB),
+                (Button::Select, 0xC),
+                (Button::Start, 0xF),
+            ],
+            stick: Some(STICK_PRESETS[0].1),
+        }
+    }
+}
+
+impl GamepadMap {
+    // What the rom database says a rom uses its keys for, e.g. ("up", 5).
+    // Matching buttons get the key, the stick follows the directions.
+    pub fn apply_key_hints(&mut self, hints: &[(String, u8)]) {
+        for (name, hex) in hints {
+            if let Some(button) = Button::from_name(name) {
+                self.buttons.retain(|(b, _)| *b != button);
+                self.buttons.push((button, *hex));
+            }
+        }
+        if let Some(stick) = &mut self.stick {
+            for (i, direction) in ["up", "down", "left", "right"].iter().enumerate() {
+                if let Some((_, hex)) = hints.iter().find(|(n, _)| n == direction) {
+                    stick[i] = *hex;
+                }
+            }
+        }
+    }
+
+    // Bits of the hex keys held down
+    pub fn hex_keys(&self, state: &PadState) -> u16 {
+        let mut keys = 0u16;
+        for (button, hex) in &self.buttons {
+            if state.is_down(*button) {
+                keys |= 1 << hex;
+            }
+        }
+        if let Some([up, down, left, right]) = self.stick {
+            if state.stick_y <= -STICK_THRESHOLD {
+                keys |= 1 << up;
+            }
+            if state.stick_y >= STICK_THRESHOLD {
+                keys |= 1 << down;
+            }
+            if state.stick_x <= -STICK_THRESHOLD {
+                keys |= 1 << left;
+            }
+            if state.stick_x >= STICK_THRESHOLD {
+                keys |= 1 << right;
+            }
+        }
+        keys
+    }
+}
+
+// Turns pad states into key presses and releases
+pub struct Gamepad {
+    pub map: GamepadMap,
+    held: u16,
+}
+
+impl Gamepad {
+    pub fn new(map: GamepadMap) -> Gamepad {
+        Gamepad { map, held: 0 }
+    }
+
+    // Call once a frame. Only keys that changed are pressed or released, so
+    // the keyboard can hold the other keys.
+    pub fn update<S: InputSource>(&mut self, source: &mut S, chip8: &mut Chip8) {
+        let keys = match source.poll() {
+            Some(state) => self.map.hex_keys(&state),
+            None => 0,
+        };
+        for hex in 0..16u8 {
+            let bit = 1 << hex;
+            if keys & bit != 0 && self.held & bit == 0 {
+                chip8.press_key(hex);
+            }
+            if keys & bit == 0 && self.held & bit != 0 {
+                chip8.release_key(hex);
+            }
+        }
+        self.held = keys;
+    }
+}
+
+pub fn load_gamepad_map(path: &Path, rom: &Path, base: GamepadMap) -> Result<GamepadMap, String> {
+    let text = match fs::read_to_string(path) {
+        Ok(x) => x,
+        Err(e) => return Err(format!("Could not read keymap {}: {}", path.display(), e)),
+    };
+    let rom_name = rom.file_name().and_then(|n| n.to_str()).unwrap_or("");
+    parse_gamepad_map(&text, rom_name, base)
+}
+
+// The base map (the defaults with any rom database hints), then [gamepad],
+// then the rom's gamepad section
+pub fn parse_gamepad_map(text: &str, rom_name: &str, base: GamepadMap) -> Result<GamepadMap, String> {
+    let config: toml::Value = text.parse().map_err(|e| format!("Bad keymap config: {}", e))?;
+    let mut map = base;
+    if let Some(section) = config.get("gamepad") {
+        apply_section(&mut map, section)?;
+    }
+    let rom = config.get("roms").and_then(|r| r.get(rom_name));
+    if let Some(section) = rom.and_then(|r| r.get("gamepad")) {
+        apply_section(&mut map, section)?;
+    }
+    Ok(map)
+}
+
+fn apply_section(map: &mut GamepadMap, section: &toml::Value) -> Result<(), String> {
+    if let Some(stick) = section.get("stick").and_then(|s| s.as_str()) {
+        map.stick = match stick {
+            "none" => None,
+            name => match STICK_PRESETS.iter().find(|(n, _)| *n == name) {
+                Some((_, keys)) => Some(*keys),
+                None => return Err(format!("Unknown stick mapping: {}", name)),
+            },
+        };
+    }
+    if let Some(buttons) = section.get("buttons").and_then(|b| b.as_table()) {
+        for (name, hex) in buttons {
+            let button = match Button::from_name(name) {
+                Some(x) => x,
+                None => return Err(format!("Unknown gamepad button: {}", name)),
+            };
+            let hex = match hex.as_str().map(|h| u8::from_str_radix(h, 16)) {
+                Some(Ok(x)) if x < 16 => x,
+                _ => return Err(format!("Bad hex key for {}: {}", name, hex)),
+            };
+            map.buttons.retain(|(b, _)| *b != button);
+            map.buttons.push((button, hex));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scripted_input_test() {
+        let mut chip8 = Chip8::new();
+        let mut gamepad = Gamepad::new(GamepadMap::default());
+        let mut input = ScriptedInput::new(vec![
+            PadState::default().with(Button::A),
+            PadState {
+                stick_x: 0.9,
+                ..PadState::default()
+            },
+        ]);
+        gamepad.update(&mut input, &mut chip8);
+        assert!(chip8.is_key_down(0x5));
+        gamepad.update(&mut input, &mut chip8);
+        assert!(!chip8.is_key_down(0x5));
+        assert!(chip8.is_key_down(0x6));
+        // pad gone, everything released
+        gamepad.update(&mut input, &mut chip8);
+        assert_eq!(0, chip8.keyboard);
+    }
+
+    #[test]
+    fn rom_section_test() {
+        let text = "[gamepad.buttons]\na = \"E\"\n[roms.\"brix.ch8\".gamepad]\nstick = \"wasd\"\n";
+        let map = parse_gamepad_map(text, "brix.ch8", GamepadMap::default()).unwrap();
+        assert_eq!(Some([0x5, 0x8, 0x7, 0x9]), map.stick);
+        let state = PadState::default().with(Button::A);
+        assert_eq!(1 << 0xE, map.hex_keys(&state));
+        assert!(parse_gamepad_map("[gamepad]\nstick = \"tilt\"\n", "x", GamepadMap::default()).is_err());
+    }
+
+    #[test]
+    fn key_hints_test() {
+        let mut map = GamepadMap::default();
+        map.apply_key_hints(&[("up".to_string(), 0x3), ("a".to_string(), 0x7), ("p2up".to_string(), 0xD)]);
+        assert_eq!(Some([0x3, 0x8, 0x4, 0x6]), map.stick);
+        assert_eq!(1 << 0x7, map.hex_keys(&PadState::default().with(Button::A)));
+        assert_eq!(1 << 0x3, map.hex_keys(&PadState::default().with(Button::Up)));
+    }
+}
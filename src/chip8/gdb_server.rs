@@ -0,0 +1,562 @@
+use crate::chip8::debugger::{Debugger, StopReason, WatchKind, Watchpoint};
+use crate::chip8::palette::Palette;
+use crate::chip8::screenshot::{save_screenshot, DEFAULT_SCALE};
+use crate::chip8::Chip8;
+use crate::chip8::MEMORY_SIZE;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+
+// GDB remote serial protocol stub.
+// https://sourceware.org/gdb/onlinedocs/gdb/Remote-Protocol.html
+//
+// Register numbers as gdb sees them:
+//   0-15  v0..vf  8 bit
+//   16    i       16 bit, little endian
+//   17    pc      16 bit, little endian
+//   18    sp      8 bit
+//   19    dt      8 bit
+//   20    st      8 bit
+//
+// "monitor screenshot <file>" in gdb saves a .png, .pbm or .pgm of the display.
+
+const REGISTER_COUNT: usize = 21;
+
+// How many instructions to run between checks for a ctrl-c from gdb
+const STEPS_PER_POLL: u32 = 1000;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" regnum="0"/>
+    <reg name="v1" bitsize="8"/>
+    <reg name="v2" bitsize="8"/>
+    <reg name="v3" bitsize="8"/>
+    <reg name="v4" bitsize="8"/>
+    <reg name="v5" bitsize="8"/>
+    <reg name="v6" bitsize="8"/>
+    <reg name="v7" bitsize="8"/>
+    <reg name="v8" bitsize="8"/>
+    <reg name="v9" bitsize="8"/>
+    <reg name="va" bitsize="8"/>
+    <reg name="vb" bitsize="8"/>
+    <reg name="vc" bitsize="8"/>
+    <reg name="vd" bitsize="8"/>
+    <reg name="ve" bitsize="8"/>
+    <reg name="vf" bitsize="8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8"/>
+    <reg name="dt" bitsize="8"/>
+    <reg name="st" bitsize="8"/>
+  </feature>
+</target>
+"#;
+
+pub enum Action {
+    Reply(String),
+    Step,
+    Continue,
+    Detach,
+    Kill,
+}
+
+pub struct GdbStub {
+    pub chip8: Chip8,
+    pub debugger: Debugger,
+    pub no_ack: bool,
+}
+
+impl GdbStub {
+    pub fn new(chip8: Chip8) -> GdbStub {
+        GdbStub {
+            chip8,
+            debugger: Debugger::new(),
+            no_ack: false,
+        }
+    }
+
+    // Turn one packet body (without $ and checksum) into what to do next
+    pub fn handle(&mut self, packet: &str) -> Action {
+        let reply = |s: &str| Action::Reply(s.to_string());
+        let (command, args) = packet.split_at(packet.len().min(1));
+        match command {
+            "?" => reply("S05"),
+            "g" => Action::Reply(self.read_registers()),
+            "G" => match self.write_registers(args) {
+                true => reply("OK"),
+                false => reply("E01"),
+            },
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(n) if n < REGISTER_COUNT => Action::Reply(self.read_register(n)),
+                _ => reply("E01"),
+            },
+            "P" => match self.write_register_packet(args) {
+                true => reply("OK"),
+                false => reply("E01"),
+            },
+            "m" => match self.read_memory(args) {
+                Some(hex) => Action::Reply(hex),
+                None => reply("E01"),
+            },
+            "M" => match self.write_memory(args) {
+                true => reply("OK"),
+                false => reply("E01"),
+            },
+            "Z" | "z" => match self.breakpoint_packet(command == "Z", args) {
+                Some(true) => reply("OK"),
+                Some(false) => reply("E01"),
+                None => reply(""),
+            },
+            "s" => Action::Step,
+            "c" => {
+                if let Some(address) = parse_hex(args) {
+                    self.chip8.pc = address as u16;
+                }
+                Action::Continue
+            }
+            "H" => reply("OK"),
+            "k" => Action::Kill,
+            "D" => Action::Detach,
+            "q" | "Q" | "v" => self.query(packet),
+            _ => reply(""),
+        }
+    }
+
+    fn query(&mut self, packet: &str) -> Action {
+        let reply = |s: &str| Action::Reply(s.to_string());
+        if packet.starts_with("qSupported") {
+            return reply("PacketSize=1000;qXfer:features:read+;QStartNoAckMode+");
+        }
+        if packet == "QStartNoAckMode" {
+            self.no_ack = true;
+            return reply("OK");
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            // qXfer:features:read:annex:offset,length
+            let mut parts = range.split(',');
+            let offset = parts.next().and_then(parse_hex).unwrap_or(0);
+            let length = parts.next().and_then(parse_hex).unwrap_or(0);
+            if offset >= TARGET_XML.len() {
+                return reply("l");
+            }
+            let end = (offset + length).min(TARGET_XML.len());
+            let marker = if end == TARGET_XML.len() { "l" } else { "m" };
+            return Action::Reply(format!("{}{}", marker, &TARGET_XML[offset..end]));
+        }
+        if let Some(command) = packet.strip_prefix("qRcmd,") {
+            return Action::Reply(self.monitor_command(command));
+        }
+        match packet {
+            "qAttached" => reply("1"),
+            "qC" => reply("QC1"),
+            "qfThreadInfo" => reply("m1"),
+            "qsThreadInfo" => reply("l"),
+            "qOffsets" => reply("Text=0;Data=0;Bss=0"),
+            _ => reply(""),
+        }
+    }
+
+    // gdb "monitor" commands arrive hex encoded
+    fn monitor_command(&mut self, hex: &str) -> String {
+        let command = match from_hex(hex) {
+            Some(bytes) => String::from_utf8_lossy(&bytes).to_string(),
+            None => return "E01".to_string(),
+        };
+        let words: Vec<&str> = command.split_whitespace().collect();
+        match words.as_slice() {
+            ["screenshot", path] => {
+                let palette = Palette::mono();
+                match save_screenshot(Path::new(path), &self.chip8, DEFAULT_SCALE, &palette) {
+                    Ok(()) => "OK".to_string(),
+                    Err(e) => to_hex(format!("{}\n", e).as_bytes()),
+                }
+            }
+            _ => to_hex(b"commands: screenshot <file>\n"),
+        }
+    }
+
+    fn register_bytes(&self, n: usize) -> Vec<u8> {
+        let c = &self.chip8;
+        match n {
+            0..=15 => vec![c.v[n]],
+            16 => c.i.to_le_bytes().to_vec(),
+            17 => c.pc.to_le_bytes().to_vec(),
+            18 => vec![c.sp],
+            19 => vec![c.timer_delay],
+            _ => vec![c.timer_sound],
+        }
+    }
+
+    fn set_register_bytes(&mut self, n: usize, bytes: &[u8]) -> bool {
+        let width = if n == 16 || n == 17 { 2 } else { 1 };
+        if bytes.len() != width {
+            return false;
+        }
+        let c = &mut self.chip8;
+        match n {
+            0..=15 => c.v[n] = bytes[0],
+            16 => c.i = u16::from_le_bytes([bytes[0], bytes[1]]),
+            17 => c.pc = u16::from_le_bytes([bytes[0], bytes[1]]),
+            18 => c.sp = bytes[0],
+            19 => c.timer_delay = bytes[0],
+            20 => c.timer_sound = bytes[0],
+            _ => return false,
+        }
+        true
+    }
+
+    fn read_register(&self, n: usize) -> String {
+        to_hex(&self.register_bytes(n))
+    }
+
+    fn read_registers(&self) -> String {
+        (0..REGISTER_COUNT).map(|n| self.read_register(n)).collect()
+    }
+
+    fn write_registers(&mut self, args: &str) -> bool {
+        let bytes = match from_hex(args) {
+            Some(x) => x,
+            None => return false,
+        };
+        let mut offset = 0;
+        for n in 0..REGISTER_COUNT {
+            let width = self.register_bytes(n).len();
+            if offset + width > bytes.len() {
+                return false;
+            }
+            self.set_register_bytes(n, &bytes[offset..offset + width]);
+            offset += width;
+        }
+        true
+    }
+
+    // P n=value
+    fn write_register_packet(&mut self, args: &str) -> bool {
+        let mut parts = args.splitn(2, '=');
+        let n = parts.next().and_then(parse_hex);
+        let value = parts.next().and_then(from_hex);
+        match (n, value) {
+            (Some(n), Some(value)) => self.set_register_bytes(n, &value),
+            _ => false,
+        }
+    }
+
+    // m addr,length
+    fn read_memory(&self, args: &str) -> Option<String> {
+        let (address, length) = parse_address_length(args)?;
+        if address >= MEMORY_SIZE {
+            return None;
+        }
+        let end = address.saturating_add(length).min(MEMORY_SIZE);
+        Some(to_hex(&self.chip8.memory[address..end]))
+    }
+
+    // M addr,length:XX...
+    fn write_memory(&mut self, args: &str) -> bool {
+        let mut parts = args.splitn(2, ':');
+        let target = parts.next().and_then(parse_address_length);
+        let bytes = parts.next().and_then(from_hex);
+        match (target, bytes) {
+            (Some((address, length)), Some(bytes)) => {
+                if bytes.len() != length || !matches!(address.checked_add(length), Some(end) if end <= MEMORY_SIZE) {
+                    return false;
+                }
+                self.chip8.memory[address..address + length].copy_from_slice(&bytes);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Z type,addr,kind / z type,addr,kind. None for unsupported types.
+    fn breakpoint_packet(&mut self, insert: bool, args: &str) -> Option<bool> {
+        let parts: Vec<&str> = args.split(',').collect();
+        if parts.len() < 3 {
+            return Some(false);
+        }
+        let address = match parse_hex(parts[1]) {
+            Some(a) if a < MEMORY_SIZE => a,
+            _ => return Some(false),
+        };
+        let len = parse_hex(parts[2]).unwrap_or(1).max(1);
+        let kind = match parts[0] {
+            "0" => {
+                match insert {
+                    true => self.debugger.add_breakpoint(address as u16),
+                    false => self.debugger.remove_breakpoint(address as u16),
+                }
+                return Some(true);
+            }
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::Access,
+            _ => return None,
+        };
+        let watchpoint = Watchpoint {
+            address,
+            len,
+            kind,
+        };
+        match insert {
+            true => self.debugger.add_watchpoint(watchpoint),
+            false => self.debugger.remove_watchpoint(watchpoint),
+        }
+        Some(true)
+    }
+}
+
+pub fn stop_reply(reason: StopReason) -> String {
+    match reason {
+        StopReason::Watchpoint(kind, address) => {
+            let name = match kind {
+                WatchKind::Write => "watch",
+                WatchKind::Read => "rwatch",
+                WatchKind::Access => "awatch",
+            };
+            format!("T05{}:{:x};", name, address)
+        }
+        // interrupted by ctrl-c
+        StopReason::Limit => "S02".to_string(),
+        // SIGSEGV, the pc left memory
+        StopReason::OutOfMemory => "S0b".to_string(),
+        _ => "S05".to_string(),
+    }
+}
+
+pub fn run_gdb_server(chip8: Chip8, port: u16) {
+    let listener = match TcpListener::bind(("127.0.0.1", port)) {
+        Ok(x) => x,
+        Err(e) => {
+            println!("Could not listen on port {}: {}", port, e);
+            return;
+        }
+    };
+    println!("Waiting for gdb on 127.0.0.1:{}", port);
+    let stream = match listener.accept() {
+        Ok((stream, address)) => {
+            println!("gdb connected from {}", address);
+            stream
+        }
+        Err(e) => {
+            println!("Could not accept connection: {}", e);
+            return;
+        }
+    };
+
+    let mut stub = GdbStub::new(chip8);
+    let mut connection = Connection::new(stream);
+    while let Some(packet) = connection.read_packet(stub.no_ack) {
+        let reply = match stub.handle(&packet) {
+            Action::Reply(reply) => reply,
+            Action::Step => stop_reply(stub.debugger.step(&mut stub.chip8)),
+            Action::Continue => {
+                let mut resume = true;
+                loop {
+                    match stub.debugger.cont(&mut stub.chip8, STEPS_PER_POLL, resume) {
+                        StopReason::Limit => {
+                            if connection.interrupted() {
+                                break stop_reply(StopReason::Limit);
+                            }
+                        }
+                        reason => break stop_reply(reason),
+                    }
+                    resume = false;
+                }
+            }
+            Action::Detach => {
+                connection.write_packet("OK");
+                break;
+            }
+            Action::Kill => break,
+        };
+        if !connection.write_packet(&reply) {
+            break;
+        }
+    }
+    println!("gdb disconnected");
+}
+
+struct Connection {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> Connection {
+        Connection {
+            stream,
+            buffer: Vec::new(),
+        }
+    }
+
+    // Returns the next packet body, None when the connection is closed
+    fn read_packet(&mut self, no_ack: bool) -> Option<String> {
+        loop {
+            if let Some(start) = self.buffer.iter().position(|b| *b == b'$') {
+                if let Some(hash) = self.buffer[start..].iter().position(|b| *b == b'#') {
+                    let end = start + hash;
+                    if self.buffer.len() >= end + 3 {
+                        let body = String::from_utf8_lossy(&self.buffer[start + 1..end]).to_string();
+                        self.buffer.drain(..end + 3);
+                        if !no_ack {
+                            self.stream.write_all(b"+").ok()?;
+                        }
+                        return Some(body);
+                    }
+                }
+            }
+            let mut chunk = [0; 1024];
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return None,
+                Ok(n) => self.buffer.extend_from_slice(&chunk[..n]),
+                Err(_) => return None,
+            }
+        }
+    }
+
+    fn write_packet(&mut self, body: &str) -> bool {
+        let packet = format!("${}#{:02x}", body, checksum(body.as_bytes()));
+        self.stream.write_all(packet.as_bytes()).is_ok()
+    }
+
+    // Check for a ctrl-c (0x03) without blocking
+    fn interrupted(&mut self) -> bool {
+        if self.stream.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let mut chunk = [0; 1024];
+        let found = match self.stream.read(&mut chunk) {
+            Ok(n) => {
+                self.buffer.extend_from_slice(&chunk[..n]);
+                n == 0 || chunk[..n].contains(&0x03)
+            }
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => false,
+            Err(_) => true,
+        };
+        self.stream.set_nonblocking(false).ok();
+        if found {
+            self.buffer.retain(|b| *b != 0x03);
+        }
+        found
+    }
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if text.len() % 2 == 1 {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok())
+        .collect()
+}
+
+fn parse_hex(text: &str) -> Option<usize> {
+    usize::from_str_radix(text, 16).ok()
+}
+
+fn parse_address_length(text: &str) -> Option<(usize, usize)> {
+    let mut parts = text.split(',');
+    let address = parts.next().and_then(parse_hex)?;
+    let length = parts.next().and_then(parse_hex)?;
+    Some((address, length))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reply(action: Action) -> String {
+        match action {
+            Action::Reply(s) => s,
+            _ => panic!("expected a reply"),
+        }
+    }
+
+    #[test]
+    fn read_registers_test() {
+        let mut chip8 = Chip8::new();
+        chip8.v[0] = 0xAB;
+        chip8.i = 0x0123;
+        chip8.pc = 0x0200;
+        let mut stub = GdbStub::new(chip8);
+        let regs = reply(stub.handle("g"));
+        assert_eq!(2 * (16 + 2 + 2 + 3), regs.len());
+        assert!(regs.starts_with("ab00"));
+        assert_eq!("2301", reply(stub.handle("p10")));
+        assert_eq!("0002", reply(stub.handle("p11")));
+    }
+
+    #[test]
+    fn memory_packets_test() {
+        let mut stub = GdbStub::new(Chip8::new());
+        assert_eq!("OK", reply(stub.handle("M200,2:6005")));
+        assert_eq!("6005", reply(stub.handle("m200,2")));
+        assert_eq!("E01", reply(stub.handle("m1000,2")));
+        // lengths past the end stop at the end of memory
+        assert_eq!(2 * (MEMORY_SIZE - 0x200), reply(stub.handle("m200,ffffffffffffffff")).len());
+        assert_eq!("E01", reply(stub.handle("Mffffffffffffffff,2:6005")));
+    }
+
+    #[test]
+    fn watchpoint_stop_test() {
+        let mut chip8 = Chip8::new();
+        // LD I, 0x300; LD B, V0 (Fx33)
+        chip8.load_program_at(&[0xA3, 0x00, 0xF0, 0x33], 0x200);
+        let mut stub = GdbStub::new(chip8);
+        assert_eq!("OK", reply(stub.handle("Z2,301,1")));
+        let reason = stub.debugger.cont(&mut stub.chip8, 10, true);
+        assert_eq!("T05watch:301;", stop_reply(reason));
+        assert_eq!(0x204, stub.chip8.pc);
+    }
+
+    #[test]
+    fn breakpoint_between_batches_test() {
+        let mut chip8 = Chip8::new();
+        // jump to itself
+        chip8.load_program_at(&[0x12, 0x00], 0x200);
+        let mut stub = GdbStub::new(chip8);
+        assert_eq!("OK", reply(stub.handle("Z0,200,2")));
+        // the first batch steps off the breakpoint, the next one stops on it
+        assert_eq!(StopReason::Limit, stub.debugger.cont(&mut stub.chip8, 1, true));
+        assert_eq!(StopReason::Breakpoint(0x200), stub.debugger.cont(&mut stub.chip8, 1, false));
+    }
+
+    #[test]
+    fn step_past_memory_test() {
+        let mut chip8 = Chip8::new();
+        chip8.pc = 0xFFF;
+        let mut stub = GdbStub::new(chip8);
+        assert_eq!("S0b", stop_reply(stub.debugger.step(&mut stub.chip8)));
+        assert_eq!(StopReason::OutOfMemory, stub.debugger.cont(&mut stub.chip8, 10, true));
+        assert_eq!(0xFFF, stub.chip8.pc);
+    }
+
+    #[test]
+    fn continue_ends_frames_test() {
+        let mut chip8 = Chip8::new();
+        // delay timer := v0, then loop
+        chip8.load_program_at(&[0xF0, 0x15, 0x12, 0x02], 0x200);
+        chip8.v[0] = 3;
+        let mut stub = GdbStub::new(chip8);
+        stub.debugger.cont(&mut stub.chip8, 25, true);
+        assert_eq!(5, stub.chip8.frame_stats.instructions);
+        assert_eq!(10, stub.chip8.last_frame.instructions);
+        assert_eq!(1, stub.chip8.timer_delay);
+    }
+
+    #[test]
+    fn checksum_test() {
+        assert_eq!(0x9a, checksum(b"OK"));
+    }
+}
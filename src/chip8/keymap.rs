@@ -0,0 +1,153 @@
+use std::fs;
+use std::path::Path;
+
+// Host keys for the 16 key hex pad.
+//
+//   hex pad    qwerty
+//   1 2 3 C    1 2 3 4
+//   4 5 6 D    q w e r
+//   7 8 9 E    a s d f
+//   A 0 B F    z x c v
+//
+// A config file picks a preset and can override keys, for every rom or for
+// one rom by file name:
+//
+//   preset = "qwerty"
+//   [keys]
+//   C = "5"
+//   [roms."brix.ch8"]
+//   preset = "azerty"
+//   [roms."brix.ch8".keys]
+//   4 = "j"
+//   6 = "l"
+
+// Hex keys in the order the pad is laid out, row by row
+const PAD_ORDER: [u8; 16] = [
+    0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF,
+];
+
+// Host keys for PAD_ORDER
+pub const PRESETS: [(&str, &str); 5] = [
+    ("qwerty", "1234qwerasdfzxcv"),
+    ("azerty", "1234azerqsdfwxcv"),
+    ("qwertz", "1234qwerasdfyxcv"),
+    ("dvorak", "1234',.paoeu;qjk"),
+    // the hex digit keys themselves
+    ("hex", "123c456d789ea0bf"),
+];
+
+// A hex key without a host key
+const UNBOUND: char = '\0';
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Keymap {
+    pub name: String,
+    // host key for each hex key
+    pub keys: [char; 16],
+}
+
+impl Keymap {
+    pub fn preset(name: &str) -> Option<Keymap> {
+        let (name, layout) = PRESETS.iter().find(|(n, _)| *n == name)?;
+        let mut keys = [UNBOUND; 16];
+        for (hex, host) in PAD_ORDER.iter().zip(layout.chars()) {
+            keys[*hex as usize] = host;
+        }
+        Some(Keymap {
+            name: name.to_string(),
+            keys,
+        })
+    }
+
+    // (hex key, host key) for every bound key
+    pub fn bindings(&self) -> Vec<(u8, char)> {
+        (0..16u8)
+            .zip(self.keys.iter().cloned())
+            .filter(|(_, host)| *host != UNBOUND)
+            .collect()
+    }
+}
+
+// The keymap for a rom: the preset, then the config file's [keys], then the
+// rom's own section
+pub fn load_keymap(path: &Path, preset: &str, rom: &Path) -> Result<Keymap, String> {
+    let text = match fs::read_to_string(path) {
+        Ok(x) => x,
+        Err(e) => return Err(format!("Could not read keymap {}: {}", path.display(), e)),
+    };
+    let rom_name = rom.file_name().and_then(|n| n.to_str()).unwrap_or("");
+    parse_keymap(&text, preset, rom_name)
+}
+
+pub fn parse_keymap(text: &str, preset: &str, rom_name: &str) -> Result<Keymap, String> {
+    let config: toml::Value = text.parse().map_err(|e| format!("Bad keymap config: {}", e))?;
+    let mut keymap = match Keymap::preset(preset) {
+        Some(x) => x,
+        None => return Err(format!("Unknown keymap preset: {}", preset)),
+    };
+    apply_section(&mut keymap, &config)?;
+    if let Some(rom) = config.get("roms").and_then(|r| r.get(rom_name)) {
+        apply_section(&mut keymap, rom)?;
+    }
+    Ok(keymap)
+}
+
+// A preset in the section replaces the keymap, then its keys override single keys
+pub fn apply_section(keymap: &mut Keymap, section: &toml::Value) -> Result<(), String> {
+    if let Some(name) = section.get("preset").and_then(|p| p.as_str()) {
+        *keymap = match Keymap::preset(name) {
+            Some(x) => x,
+            None => return Err(format!("Unknown keymap preset: {}", name)),
+        };
+    }
+    if let Some(keys) = section.get("keys").and_then(|k| k.as_table()) {
+        for (hex, host) in keys {
+            let hex = match u8::from_str_radix(hex, 16) {
+                Ok(x) if x < 16 => x as usize,
+                _ => return Err(format!("Bad hex key in keymap: {}", hex)),
+            };
+            let host = match host.as_str().map(|h| h.chars().collect::<Vec<char>>()) {
+                Some(chars) if chars.len() == 1 => chars[0].to_ascii_lowercase(),
+                _ => return Err(format!("Keymap keys must be a single character: {}", host)),
+            };
+            // the host key now belongs to this hex key only
+            for key in keymap.keys.iter_mut() {
+                if *key == host {
+                    *key = UNBOUND;
+                }
+            }
+            keymap.keys[hex] = host;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn qwerty_test() {
+        let keymap = Keymap::preset("qwerty").unwrap();
+        assert_eq!('1', keymap.keys[0x1]);
+        assert_eq!('4', keymap.keys[0xC]);
+        assert_eq!('x', keymap.keys[0x0]);
+        assert_eq!('v', keymap.keys[0xF]);
+        assert_eq!(16, keymap.bindings().len());
+        assert!(Keymap::preset("colemak").is_none());
+    }
+
+    #[test]
+    fn rom_override_test() {
+        let text = "preset = \"hex\"\n[keys]\nC = \"5\"\n[roms.\"brix.ch8\"]\npreset = \"azerty\"\n[roms.\"brix.ch8\".keys]\n4 = \"j\"\n";
+        let other = parse_keymap(text, "qwerty", "pong.ch8").unwrap();
+        assert_eq!("hex", other.name);
+        assert_eq!('5', other.keys[0xC]);
+        assert_eq!(UNBOUND, other.keys[0x5]);
+        assert_eq!(15, other.bindings().len());
+        let brix = parse_keymap(text, "qwerty", "brix.ch8").unwrap();
+        assert_eq!("azerty", brix.name);
+        assert_eq!('j', brix.keys[0x4]);
+        assert_eq!('z', brix.keys[0x5]);
+    }
+}
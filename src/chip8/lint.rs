@@ -0,0 +1,610 @@
+use crate::chip8::memory_map::MemoryMap;
+use crate::chip8::MEMORY_SIZE;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+
+// chip8 lint: walk the program's control flow from its load address without
+// running it and report what looks wrong. Skips follow both ways, calls are
+// assumed to return, and Bnnn follows nnn plus any jump table right after it.
+// Along the way the value of I is tracked where Annn makes it known, so
+// Fx55/Fx33 writes can be checked against the machine's memory map.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Note => write!(f, "note"),
+        }
+    }
+}
+
+// Machines in order, each runs the instructions of the ones before it
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Target {
+    Chip8,
+    Schip,
+    XoChip,
+}
+
+impl Target {
+    pub fn from_name(name: &str) -> Result<Target, String> {
+        match name {
+            "chip8" => Ok(Target::Chip8),
+            "schip" => Ok(Target::Schip),
+            "xochip" => Ok(Target::XoChip),
+            _ => Err(format!("Unknown target: {} (chip8, schip or xochip)", name)),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Target::Chip8 => "CHIP-8",
+            Target::Schip => "SCHIP",
+            Target::XoChip => "XO-CHIP",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Finding {
+    pub address: u16,
+    pub severity: Severity,
+    pub message: String,
+}
+
+// A Dxyn the walk reached with I known
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Draw {
+    pub pc: u16,
+    pub address: u16,
+    pub rows: u8,
+}
+
+// What is known about I on the way into an instruction
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum IReg {
+    Known(u16),
+    Unknown,
+    // after Fx55/Fx65, where I points depends on the load/store quirk
+    Advanced,
+}
+
+fn join(a: IReg, b: IReg) -> IReg {
+    if a == b {
+        a
+    } else if a == IReg::Advanced || b == IReg::Advanced {
+        IReg::Advanced
+    } else {
+        IReg::Unknown
+    }
+}
+
+struct Linter<'a> {
+    bytes: &'a [u8],
+    start: usize,
+    end: usize,
+    target: Target,
+    map: MemoryMap,
+    findings: Vec<Finding>,
+    // reached instructions and I on the way in
+    reached: BTreeMap<usize, IReg>,
+    queue: VecDeque<usize>,
+    // where Annn pointed, the start of data
+    data: Vec<usize>,
+    draws: Vec<Draw>,
+}
+
+pub fn lint(bytes: &[u8], address: usize, target: Target, map: &MemoryMap) -> Vec<Finding> {
+    let mut linter = walk(bytes, address, target, map);
+    linter.find_loops();
+    linter.find_unreachable();
+    let mut findings = linter.findings;
+    findings.sort_by_key(|f| (f.address, f.severity));
+    findings
+}
+
+// Sprites the program draws where the walk can tell I
+pub fn draws(bytes: &[u8], address: usize) -> Vec<Draw> {
+    walk(bytes, address, Target::XoChip, &MemoryMap::default()).draws
+}
+
+fn walk<'a>(bytes: &'a [u8], address: usize, target: Target, map: &MemoryMap) -> Linter<'a> {
+    let mut linter = Linter {
+        bytes,
+        start: address,
+        end: address + bytes.len(),
+        target,
+        map: *map,
+        findings: Vec::new(),
+        reached: BTreeMap::new(),
+        queue: VecDeque::new(),
+        data: Vec::new(),
+        draws: Vec::new(),
+    };
+    linter.visit(address, IReg::Unknown);
+    while let Some(pc) = linter.queue.pop_front() {
+        let i = linter.reached[&pc];
+        linter.step(pc, i);
+    }
+    linter
+}
+
+impl<'a> Linter<'a> {
+    fn report(&mut self, pc: usize, severity: Severity, message: String) {
+        let address = pc as u16;
+        if !self.findings.iter().any(|f| f.address == address && f.message == message) {
+            self.findings.push(Finding {
+                address,
+                severity,
+                message,
+            });
+        }
+    }
+
+    fn word(&self, pc: usize) -> Option<(u8, u8)> {
+        if pc < self.start || pc + 1 >= self.end {
+            return None;
+        }
+        Some((self.bytes[pc - self.start], self.bytes[pc + 1 - self.start]))
+    }
+
+    // Size of the instruction at pc, F000 NNNN is four bytes
+    fn size(&self, pc: usize) -> usize {
+        if self.word(pc) == Some((0xF0, 0x00)) {
+            4
+        } else {
+            2
+        }
+    }
+
+    fn visit(&mut self, pc: usize, i: IReg) {
+        match self.reached.get(&pc) {
+            Some(old) => {
+                let joined = join(*old, i);
+                if joined != *old {
+                    self.reached.insert(pc, joined);
+                    self.queue.push_back(pc);
+                }
+            }
+            None => {
+                self.reached.insert(pc, i);
+                self.queue.push_back(pc);
+            }
+        }
+    }
+
+    // Whether a jump or call target can be followed
+    fn check_target(&mut self, pc: usize, kind: &str, target: usize) -> bool {
+        if target < self.start {
+            let message = format!("{} to {:#05X}, below the program at {:#05X}", kind, target, self.start);
+            self.report(pc, Severity::Error, message);
+            return false;
+        }
+        if target + 1 >= self.end {
+            let message = format!("{} to {:#05X}, past the end of the program at {:#05X}", kind, target, self.end);
+            self.report(pc, Severity::Error, message);
+            return false;
+        }
+        if target % 2 == 1 {
+            let message = format!("{} to odd address {:#05X}", kind, target);
+            self.report(pc, Severity::Warning, message);
+        }
+        true
+    }
+
+    fn needs(&mut self, pc: usize, target: Target, opcode: String) {
+        if target > self.target {
+            let message = format!("{} is {} only, the target is {}", opcode, target.name(), self.target.name());
+            self.report(pc, Severity::Error, message);
+        }
+    }
+
+    fn quirk(&mut self, pc: usize, message: &str) {
+        self.report(pc, Severity::Warning, format!("depends on the {}", message));
+    }
+
+    // Memory written or read through I, checked against the memory map
+    fn check_access(&mut self, pc: usize, i: IReg, len: usize, write: bool) {
+        let first = match i {
+            IReg::Known(x) => x as usize,
+            _ => return,
+        };
+        let last = first + len - 1;
+        let what = match write {
+            true => "writes",
+            false => "reads",
+        };
+        let range = format!("{:#05X}-{:#05X}", first, last);
+        let severity = match write {
+            true => Severity::Error,
+            false => Severity::Warning,
+        };
+        if write && first < self.map.program {
+            let message = format!("{} {}, over the font and interpreter area", what, range);
+            self.report(pc, Severity::Error, message);
+        } else if !write && first < self.map.program && last >= self.map.fonts_end() {
+            let message = format!("{} {}, interpreter memory other machines don't share", what, range);
+            self.report(pc, Severity::Warning, message);
+        }
+        if last >= MEMORY_SIZE {
+            let message = format!("{} {}, past the end of memory", what, range);
+            self.report(pc, severity, message);
+        } else if (first..=last).any(|a| self.map.in_program_memory(a)) {
+            let message = format!("{} {}, the call stack and display memory", what, range);
+            self.report(pc, severity, message);
+        }
+    }
+
+    fn uses_i(&mut self, pc: usize, i: IReg) {
+        if i == IReg::Advanced {
+            self.quirk(pc, "load/store quirk, I after Fx55/Fx65 differs between machines");
+        }
+    }
+
+    fn unknown(&mut self, pc: usize, b0: u8, b1: u8) {
+        let message = format!("unknown instruction {:02X}{:02X}", b0, b1);
+        self.report(pc, Severity::Error, message);
+    }
+
+    // Skips step over the whole next instruction
+    fn skip(&mut self, pc: usize, i: IReg) {
+        self.visit(pc + 2, i);
+        let next = pc + 2;
+        if self.word(next).is_some() {
+            self.visit(next + self.size(next), i);
+        } else {
+            self.visit(next + 2, i);
+        }
+    }
+
+    fn step(&mut self, pc: usize, i: IReg) {
+        let (b0, b1) = match self.word(pc) {
+            Some(x) => x,
+            None => {
+                let message = "runs past the end of the program".to_string();
+                self.report(pc, Severity::Error, message);
+                return;
+            }
+        };
+        let opcode = b0 >> 4;
+        let x = b0 & 0x0F;
+        let y = b1 >> 4;
+        let n = b1 & 0x0F;
+        let nnn = ((x as usize) << 8) | b1 as usize;
+        let next = pc + 2;
+        match opcode {
+            0x0 => match (x, b1) {
+                (0, 0xE0) => self.visit(next, i),
+                (0, 0xEE) => {}
+                (0, 0xFD) => self.needs(pc, Target::Schip, "00FD (exit)".to_string()),
+                (0, 0xFB) | (0, 0xFC) | (0, 0xFE) | (0, 0xFF) => {
+                    self.needs(pc, Target::Schip, format!("00{:02X}", b1));
+                    self.visit(next, i);
+                }
+                (0, _) if y == 0xC => {
+                    self.needs(pc, Target::Schip, format!("00C{:X} (scroll down)", n));
+                    self.visit(next, i);
+                }
+                (0, _) if y == 0xD => {
+                    self.needs(pc, Target::XoChip, format!("00D{:X} (scroll up)", n));
+                    self.visit(next, i);
+                }
+                _ if nnn != 0 => {
+                    let message = format!("machine code call to {:#05X} isn't emulated", nnn);
+                    self.report(pc, Severity::Error, message);
+                }
+                _ => self.unknown(pc, b0, b1),
+            },
+            0x1 => {
+                if self.check_target(pc, "jump", nnn) {
+                    self.visit(nnn, i);
+                }
+            }
+            0x2 => {
+                if self.check_target(pc, "call", nnn) {
+                    self.visit(nnn, i);
+                }
+                // the subroutine may have changed I
+                self.visit(next, IReg::Unknown);
+            }
+            0x3 | 0x4 => self.skip(pc, i),
+            0x5 | 0x9 => match (opcode, n) {
+                (_, 0) => self.skip(pc, i),
+                (0x5, 2) | (0x5, 3) => {
+                    let name = if n == 2 { "save" } else { "load" };
+                    self.needs(pc, Target::XoChip, format!("5{:X}{:X}{:X} ({} range)", x, y, n, name));
+                    self.uses_i(pc, i);
+                    self.visit(next, i);
+                }
+                _ => self.unknown(pc, b0, b1),
+            },
+            0x6 | 0x7 | 0xC => self.visit(next, i),
+            0x8 => match n {
+                0x0..=0x5 | 0x7 => self.visit(next, i),
+                0x6 | 0xE => {
+                    if x != y {
+                        self.quirk(pc, "shift quirk, x and y differ");
+                    }
+                    self.visit(next, i);
+                }
+                _ => self.unknown(pc, b0, b1),
+            },
+            0xA => {
+                self.data.push(nnn);
+                self.visit(next, IReg::Known(nnn as u16));
+            }
+            0xB => {
+                if x != 0 {
+                    self.quirk(pc, "jump quirk, SCHIP adds vX instead of v0");
+                }
+                if self.check_target(pc, "computed jump", nnn) {
+                    self.visit(nnn, i);
+                    // a table of jumps right after the first one
+                    let mut entry = nnn;
+                    while let Some((e0, _)) = self.word(entry) {
+                        if e0 >> 4 != 0x1 {
+                            break;
+                        }
+                        self.visit(entry, i);
+                        entry += 2;
+                    }
+                }
+            }
+            0xD => {
+                if n == 0 {
+                    self.needs(pc, Target::Schip, format!("D{:X}{:X}0 (16x16 sprite)", x, y));
+                }
+                self.uses_i(pc, i);
+                if let IReg::Known(address) = i {
+                    let draw = Draw {
+                        pc: pc as u16,
+                        address,
+                        rows: n,
+                    };
+                    if !self.draws.contains(&draw) {
+                        self.draws.push(draw);
+                    }
+                }
+                self.visit(next, i);
+            }
+            0xE => match b1 {
+                0x9E | 0xA1 => self.skip(pc, i),
+                _ => self.unknown(pc, b0, b1),
+            },
+            _ => match b1 {
+                0x00 if x == 0 => {
+                    self.needs(pc, Target::XoChip, "F000 (long I)".to_string());
+                    match self.word(next) {
+                        Some((a0, a1)) => {
+                            let address = u16::from_be_bytes([a0, a1]);
+                            self.data.push(address as usize);
+                            self.visit(pc + 4, IReg::Known(address));
+                        }
+                        None => self.report(pc, Severity::Error, "F000 without an address".to_string()),
+                    }
+                }
+                0x01 => {
+                    self.needs(pc, Target::XoChip, format!("F{:X}01 (plane)", x));
+                    self.visit(next, i);
+                }
+                0x02 if x == 0 => {
+                    self.needs(pc, Target::XoChip, "F002 (audio)".to_string());
+                    self.uses_i(pc, i);
+                    self.visit(next, i);
+                }
+                0x07 | 0x0A | 0x15 | 0x18 => self.visit(next, i),
+                0x1E => {
+                    self.uses_i(pc, i);
+                    self.visit(next, IReg::Unknown);
+                }
+                0x29 => self.visit(next, IReg::Unknown),
+                0x30 => {
+                    self.needs(pc, Target::Schip, format!("F{:X}30 (big font)", x));
+                    self.visit(next, IReg::Unknown);
+                }
+                0x33 => {
+                    self.uses_i(pc, i);
+                    self.check_access(pc, i, 3, true);
+                    self.visit(next, i);
+                }
+                0x3A => {
+                    self.needs(pc, Target::XoChip, format!("F{:X}3A (pitch)", x));
+                    self.visit(next, i);
+                }
+                0x55 | 0x65 => {
+                    self.uses_i(pc, i);
+                    self.check_access(pc, i, x as usize + 1, b1 == 0x55);
+                    self.visit(next, IReg::Advanced);
+                }
+                0x75 | 0x85 => {
+                    self.needs(pc, Target::Schip, format!("F{:X}{:02X} (flags)", x, b1));
+                    self.visit(next, i);
+                }
+                _ => self.unknown(pc, b0, b1),
+            },
+        }
+    }
+
+    // Instructions a loop can end or wait on
+    fn can_leave(&self, pc: usize) -> bool {
+        let (b0, b1) = match self.word(pc) {
+            Some(x) => x,
+            None => return true,
+        };
+        match b0 >> 4 {
+            0x0 => b0 == 0 && (b1 == 0xEE || b1 == 0xFD),
+            // calls may wait, jumps elsewhere leave
+            0x1 | 0x2 | 0xB => true,
+            0x3 | 0x4 | 0x5 | 0x9 | 0xE => true,
+            0xF => b1 == 0x0A || b1 == 0x07,
+            _ => false,
+        }
+    }
+
+    // A jump back over straight line code that never waits or leaves
+    fn find_loops(&mut self) {
+        let mut loops = Vec::new();
+        for pc in self.reached.keys() {
+            let (b0, b1) = match self.word(*pc) {
+                Some(x) => x,
+                None => continue,
+            };
+            let target = ((b0 as usize & 0x0F) << 8) | b1 as usize;
+            if b0 >> 4 != 0x1 || target > *pc || !self.reached.contains_key(&target) {
+                continue;
+            }
+            let mut at = target;
+            let mut endless = true;
+            while at < *pc {
+                if !self.reached.contains_key(&at) || self.can_leave(at) {
+                    endless = false;
+                    break;
+                }
+                at += self.size(at);
+            }
+            if endless && at == *pc {
+                loops.push((target, *pc));
+            }
+        }
+        for (target, pc) in loops {
+            if target == pc {
+                let message = "jumps to itself forever, without a key or timer wait".to_string();
+                self.report(pc, Severity::Note, message);
+            } else {
+                let message = format!("endless loop back to {:#05X}, without a key or timer wait", target);
+                self.report(pc, Severity::Warning, message);
+            }
+        }
+    }
+
+    // Bytes no instruction covers, up to the first data Annn points at
+    fn find_unreachable(&mut self) {
+        let mut covered = vec![false; self.bytes.len()];
+        for pc in self.reached.keys() {
+            for a in *pc..(*pc + self.size(*pc)).min(self.end) {
+                covered[a - self.start] = true;
+            }
+        }
+        let mut gaps = Vec::new();
+        let mut a = 0;
+        while a < covered.len() {
+            if covered[a] {
+                a += 1;
+                continue;
+            }
+            let gap_start = a;
+            while a < covered.len() && !covered[a] {
+                a += 1;
+            }
+            gaps.push((self.start + gap_start, self.start + a));
+        }
+        for (gap_start, gap_end) in gaps {
+            let code_end = self
+                .data
+                .iter()
+                .filter(|d| **d >= gap_start && **d < gap_end)
+                .min()
+                .cloned()
+                .unwrap_or(gap_end);
+            let code = &self.bytes[gap_start - self.start..code_end - self.start];
+            // padding isn't code
+            if code.len() < 2 || code.iter().all(|b| *b == 0) {
+                continue;
+            }
+            let message = format!("unreachable code at {:#05X}-{:#05X}", gap_start, code_end - 1);
+            self.report(gap_start, Severity::Warning, message);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::DATA;
+
+    fn messages(bytes: &[u8], target: Target) -> Vec<String> {
+        lint(bytes, DATA, target, &MemoryMap::default())
+            .iter()
+            .map(|f| format!("{:#05X} {}: {}", f.address, f.severity, f.message))
+            .collect()
+    }
+
+    #[test]
+    fn control_flow_test() {
+        let rom = [
+            0x22, 0x08, // call 0x208
+            0x13, 0x00, // jump 0x300, past the end
+            0x12, 0x00, // unreachable
+            0x12, 0x07, // unreachable, jump to odd address
+            0xA2, 0x0E, // i := sprite
+            0xD0, 0x15, // sprite v0 v1 5
+            0x00, 0xEE, // return
+            0xF0, 0x90, // sprite data
+        ];
+        assert_eq!(
+            vec![
+                "0x202 error: jump to 0x300, past the end of the program at 0x210",
+                "0x204 warning: unreachable code at 0x204-0x207",
+            ],
+            messages(&rom, Target::Chip8)
+        );
+
+        // halt by spinning, and a loop that never waits
+        let rom = [0x60, 0x00, 0x70, 0x01, 0x12, 0x02];
+        assert_eq!(
+            vec!["0x204 warning: endless loop back to 0x202, without a key or timer wait"],
+            messages(&rom, Target::Chip8)
+        );
+        let rom = [0xF0, 0x0A, 0x12, 0x00];
+        assert_eq!(Vec::<String>::new(), messages(&rom, Target::Chip8));
+    }
+
+    #[test]
+    fn memory_and_quirks_test() {
+        let rom = [
+            0xA1, 0x00, // i := 0x100
+            0xF2, 0x55, // save v2, over the interpreter
+            0xF2, 0x65, // load v2 with I after the quirk
+            0x81, 0x26, // v1 >>= v2
+            0x80, 0x06, // v0 >>= v0 is fine either way
+            0xAE, 0xFE, // i := 0xEFE
+            0xF0, 0x33, // bcd into the call stack
+            0x12, 0x0E, // halt
+        ];
+        assert_eq!(
+            vec![
+                "0x202 error: writes 0x100-0x102, over the font and interpreter area",
+                "0x204 warning: depends on the load/store quirk, I after Fx55/Fx65 differs between machines",
+                "0x206 warning: depends on the shift quirk, x and y differ",
+                "0x20C error: writes 0xEFE-0xF00, the call stack and display memory",
+                "0x20E note: jumps to itself forever, without a key or timer wait",
+            ],
+            messages(&rom, Target::Chip8)
+        );
+    }
+
+    #[test]
+    fn target_test() {
+        // hires, long I, then exit
+        let rom = [0x00, 0xFF, 0xF0, 0x00, 0x02, 0x08, 0x00, 0xFD, 0xFF, 0x00];
+        assert_eq!(
+            vec![
+                "0x200 error: 00FF is SCHIP only, the target is CHIP-8",
+                "0x202 error: F000 (long I) is XO-CHIP only, the target is CHIP-8",
+                "0x206 error: 00FD (exit) is SCHIP only, the target is CHIP-8",
+            ],
+            messages(&rom, Target::Chip8)
+        );
+        assert_eq!(1, messages(&rom, Target::Schip).len());
+        assert!(messages(&rom, Target::XoChip).is_empty());
+        assert!(Target::from_name("megachip").is_err());
+    }
+}
@@ -0,0 +1,111 @@
+use crate::chip8::fonts::{BIG_HEIGHT, GLYPHS};
+use crate::chip8::MEMORY_SIZE;
+
+// Where a machine keeps its fonts, program, call stack and display. The
+// COSMAC VIP kept the stack and display refresh in the top of its 4K, so a
+// program can run over them. The HP48 interpreters and Octo keep them out of
+// the program's memory, here that is the interpreter RAM above 0xFFF. I only
+// addresses 12 bits and wraps, so no instruction reaches it. The ETI-660
+// loads programs at 0x600.
+//
+// XO-CHIP's 64K isn't emulated, the memory is 4K on every machine.
+
+// Call stack slots, 2 bytes each
+pub const STACK_SIZE: usize = 0x60;
+// 64x32, a bit per pixel
+pub const DISPLAY_SIZE: usize = 0x100;
+// Beyond MEMORY_SIZE for the machines that keep the stack and display there
+pub const INTERPRETER_RAM: usize = STACK_SIZE + DISPLAY_SIZE;
+pub const MACHINE_MEMORY: usize = MEMORY_SIZE + INTERPRETER_RAM;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MemoryMap {
+    pub name: &'static str,
+    pub font: usize,
+    pub big_font: usize,
+    // where programs load and start
+    pub program: usize,
+    pub callstack: usize,
+    pub display: usize,
+}
+
+pub const PRESETS: [MemoryMap; 4] = [
+    MemoryMap {
+        name: "vip",
+        font: 0x000,
+        big_font: 0x050,
+        program: 0x200,
+        callstack: 0xEA0,
+        display: 0xF00,
+    },
+    MemoryMap {
+        name: "schip",
+        font: 0x000,
+        big_font: 0x050,
+        program: 0x200,
+        callstack: MEMORY_SIZE,
+        display: MEMORY_SIZE + STACK_SIZE,
+    },
+    MemoryMap {
+        name: "xochip",
+        font: 0x000,
+        big_font: 0x050,
+        program: 0x200,
+        callstack: MEMORY_SIZE,
+        display: MEMORY_SIZE + STACK_SIZE,
+    },
+    MemoryMap {
+        name: "eti660",
+        font: 0x000,
+        big_font: 0x050,
+        program: 0x600,
+        callstack: 0xEA0,
+        display: 0xF00,
+    },
+];
+
+impl Default for MemoryMap {
+    fn default() -> MemoryMap {
+        PRESETS[0]
+    }
+}
+
+impl MemoryMap {
+    pub fn preset(name: &str) -> Result<MemoryMap, String> {
+        match PRESETS.iter().find(|m| m.name == name) {
+            Some(x) => Ok(*x),
+            None => {
+                let names: Vec<&str> = PRESETS.iter().map(|m| m.name).collect();
+                Err(format!("Unknown machine: {} ({})", name, names.join(", ")))
+            }
+        }
+    }
+
+    // Whether the interpreter's own memory sits where a program can reach it
+    pub fn in_program_memory(&self, address: usize) -> bool {
+        address >= self.callstack.min(self.display) && address < MEMORY_SIZE
+    }
+
+    // First byte past the fonts, the rest up to the program is free
+    pub fn fonts_end(&self) -> usize {
+        self.big_font + GLYPHS * BIG_HEIGHT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_test() {
+        for map in PRESETS.iter() {
+            assert!(map.fonts_end() <= map.program);
+            assert!(map.callstack + STACK_SIZE <= map.display);
+            assert!(map.display + DISPLAY_SIZE <= MACHINE_MEMORY);
+        }
+        assert_eq!(0x600, MemoryMap::preset("eti660").unwrap().program);
+        assert!(MemoryMap::default().in_program_memory(0xEA0));
+        assert!(!MemoryMap::preset("schip").unwrap().in_program_memory(0xEA0));
+        assert!(MemoryMap::preset("megachip").is_err());
+    }
+}
@@ -1,27 +1,60 @@
+pub mod audio;
+pub mod cartridge;
+pub mod config;
+pub mod coverage;
 pub mod cursive_renderer;
+pub mod dap_server;
+pub mod debugger;
 pub mod emu_utils;
+pub mod filter;
+pub mod fonts;
+pub mod gamepad;
+pub mod gdb_server;
+pub mod keymap;
+pub mod lint;
+pub mod memory_map;
+pub mod monitor;
+pub mod octo;
+pub mod palette;
+pub mod quirks;
 pub mod raylib_renderer;
+pub mod recorder;
+pub mod rom_loader;
+pub mod romdb;
+pub mod screenshot;
+pub mod scripting;
+pub mod sprites;
+pub mod state;
+pub mod symbols;
+pub mod terminal_renderer;
+pub mod watch;
 extern crate rand;
 
+use crate::chip8::fonts::Fonts;
+use crate::chip8::memory_map::{MemoryMap, MACHINE_MEMORY, STACK_SIZE};
+use crate::chip8::quirks::Quirks;
+use crate::chip8::rom_loader::Rom;
+
 // http://devernay.free.fr/hacks/chip8/C8TECH10.HTM#2.1
 
 pub const MEMORY_SIZE: usize = 4096; // 4k memory
-pub const FONT_DATA: usize = 0x000; // first 512 for font data
-pub const DATA: usize = 0x200; // start at 512 0x200
-pub const DISPLAY: usize = 0xF00; // upper 256 0xF00 - 0xFFF display refresh
-pub const CALLSTACK: usize = 0xEA0; // 96 below that are call stack 0xEA0 - 0xEFF
+pub const DATA: usize = 0x200; // programs usually start at 512 0x200, see memory_map
 
 pub const ROWS: usize = 32;
 pub const COLS: usize = 64;
 pub const ROW_LEN: usize = COLS / 8;
 
-pub const ROW_SIZE_BYTE: usize = ROWS / 8;
 pub const COL_SIZE_BYTE: usize = COLS / 8;
 
 pub const ECHO_SOUND: char = 7 as char;
 
+// Timers run at 60hz, about 600 instructions a second
+pub const FRAMES_PER_SECOND: u32 = 60;
+pub const INSTRUCTIONS_PER_FRAME: u32 = 10;
+
 const FONT_SIZE: usize = 5;
 pub type Font = [u8; FONT_SIZE];
+const BIG_FONT_SIZE: usize = 10;
 
 pub const FONT_SPRITES: [Font; 16] = [
     [0xF0, 0x90, 0x90, 0x90, 0xF0],
@@ -42,14 +75,29 @@ pub const FONT_SPRITES: [Font; 16] = [
     [0xF0, 0x80, 0xF0, 0x80, 0x80],
 ];
 
+// Memory touched by a single instruction, other than the fetch itself.
+#[derive(Debug, Default, PartialEq)]
+pub struct MemoryAccess {
+    pub reads: Vec<usize>,
+    pub writes: Vec<usize>,
+}
+
+// Work done in one 60hz frame
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameStats {
+    pub instructions: u32,
+    pub draws: u32,
+}
+
 #[derive(Clone, Copy)]
 pub struct Chip8 {
-    pub memory: [u8; MEMORY_SIZE],
+    // MEMORY_SIZE the program can address, then interpreter RAM
+    pub memory: [u8; MACHINE_MEMORY],
+    // where the fonts, program, stack and display are
+    pub memory_map: MemoryMap,
 
     pub v: [u8; 16], // 16 8bit registers V0-VF // VF is a flag, do not use
 
-    pub address: u16, // Address Register I is 16 bis
-
     // Stack used for return address
     // 48 bytes for 24 levels of nesting
 
@@ -66,38 +114,104 @@ pub struct Chip8 {
     //pub should_sound: bool, // Custom regster to know if drw has been invoked.
     pub wait_key: bool,
     pub wait_key_v_x: usize,
+
+    pub quirks: Quirks,
+    // Instructions per 60hz frame, the speed setting divided by 60
+    pub instructions_per_frame: u32,
+    // Counters for the frame in progress and the last finished one
+    pub frame_stats: FrameStats,
+    pub last_frame: FrameStats,
 }
 
 impl Chip8 {
+    pub fn new() -> Chip8 {
+        Chip8 {
+            memory: [0; MACHINE_MEMORY],
+            memory_map: MemoryMap::default(),
+            v: [0; 16],
+            timer_delay: 0,
+            timer_sound: 0,
+            pc: 0,
+            sp: 0,
+            i: 0,
+            keyboard: 0,
+            should_draw: false,
+            wait_key: false,
+            wait_key_v_x: 0,
+            quirks: Quirks::default(),
+            instructions_per_frame: INSTRUCTIONS_PER_FRAME,
+            frame_stats: FrameStats::default(),
+            last_frame: FrameStats::default(),
+        }
+    }
+
+    // ETI-660 programs start at 0x600
+    pub fn load_program_at(&mut self, bytes: &[u8], address: usize) {
+        self.memory[address..address + bytes.len()].copy_from_slice(bytes);
+        self.pc = address as u16;
+    }
+
     pub fn fetch(&mut self) -> (u8, u8) {
         // fetch
-        let b0 = self.memory[(self.pc + 0) as usize];
+        let b0 = self.memory[self.pc as usize];
         let b1 = self.memory[(self.pc + 1) as usize];
         // increment the pc
         self.pc += 2;
-        return (b0, b1);
+        self.frame_stats.instructions += 1;
+        (b0, b1)
+    }
+
+    // Count both timers down, call once per frame
+    pub fn tick_timers(&mut self) {
+        self.timer_delay = self.timer_delay.saturating_sub(1);
+        self.timer_sound = self.timer_sound.saturating_sub(1);
+    }
+
+    // The frame's instruction budget is spent, or with the vblank quirk
+    // the rom has drawn and is waiting for the display
+    pub fn frame_over(&self) -> bool {
+        self.frame_stats.instructions >= self.instructions_per_frame
+            || (self.quirks.vblank && self.frame_stats.draws > 0)
+    }
+
+    // Tick the timers and start counting a new frame
+    pub fn end_frame(&mut self) -> FrameStats {
+        self.tick_timers();
+        self.last_frame = self.frame_stats;
+        self.frame_stats = FrameStats::default();
+        self.last_frame
     }
 
     pub fn load_fonts(&mut self) {
-        let mut i = 0;
-        for font in &FONT_SPRITES {
-            for font_byte in &*font {
-                self.memory[FONT_DATA + i] = *font_byte;
-                i += 1;
-            }
-        }
+        self.install_fonts(&Fonts::default());
+    }
+
+    pub fn install_fonts(&mut self, fonts: &Fonts) {
+        let small = &fonts.small.bytes;
+        let big = &fonts.big.bytes;
+        let map = self.memory_map;
+        self.memory[map.font..map.font + small.len()].copy_from_slice(small);
+        self.memory[map.big_font..map.big_font + big.len()].copy_from_slice(big);
+    }
+
+    // Framebuffer accessor, x and y wrap around the screen
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        let x = x % COLS;
+        let y = y % ROWS;
+        let byte = self.memory[self.memory_map.display + (y * ROW_LEN) + (x / 8)];
+        bit_value(byte, x % 8)
     }
 
     // 00E0
     pub fn clear_screen(&mut self) {
         for i in 0..(COLS * ROWS / 8) {
-            self.memory[DISPLAY + i] = 0
+            self.memory[self.memory_map.display + i] = 0
         }
     }
     // Not an instruction but for debugging
     pub fn fill_screen(&mut self) {
         for i in 0..(COLS * ROWS / 8) {
-            self.memory[DISPLAY + i] = 0xFF
+            self.memory[self.memory_map.display + i] = 0xFF
         }
     }
     pub fn fill_screen_other_row(&mut self) {
@@ -106,22 +220,35 @@ impl Chip8 {
                 continue;
             };
             for x in 0..COL_SIZE_BYTE {
-                self.memory[DISPLAY + (y * COL_SIZE_BYTE) + x] = 0xFF
+                self.memory[self.memory_map.display + (y * COL_SIZE_BYTE) + x] = 0xFF
             }
         }
     }
     pub fn fill_screen_other_col(&mut self) {
         for y in 0..ROWS {
             for x in 0..COL_SIZE_BYTE {
-                self.memory[DISPLAY + (y * COL_SIZE_BYTE) + x] = 0xAA
+                self.memory[self.memory_map.display + (y * COL_SIZE_BYTE) + x] = 0xAA
             }
         }
     }
 
+    // Return addresses saved by call, most recent first
+    pub fn call_stack(&self) -> Vec<u16> {
+        let mut stack = Vec::new();
+        for level in (1..=self.sp as usize).rev() {
+            let slot = self.memory_map.callstack + level * 2;
+            if slot + 1 >= self.memory_map.callstack + STACK_SIZE {
+                continue;
+            }
+            stack.push(((self.memory[slot] as u16) << 8) | self.memory[slot + 1] as u16);
+        }
+        stack
+    }
+
     // 00EE
     pub fn ret(&mut self) {
-        let pc0 = self.memory[CALLSTACK + (self.sp as usize * 2)];
-        let pc1 = self.memory[CALLSTACK + (self.sp as usize * 2 + 1)];
+        let pc0 = self.memory[self.memory_map.callstack + (self.sp as usize * 2)];
+        let pc1 = self.memory[self.memory_map.callstack + (self.sp as usize * 2 + 1)];
         self.pc = ((pc0 as u16) << 8) | pc1 as u16;
         self.sp -= 1;
     }
@@ -134,8 +261,8 @@ impl Chip8 {
         self.sp += 1;
         let pc0 = (self.pc >> 8) as u8;
         let pc1 = (self.pc & 0xFF) as u8;
-        self.memory[CALLSTACK + (self.sp as usize * 2)] = pc0;
-        self.memory[CALLSTACK + (self.sp as usize * 2 + 1)] = pc1;
+        self.memory[self.memory_map.callstack + (self.sp as usize * 2)] = pc0;
+        self.memory[self.memory_map.callstack + (self.sp as usize * 2 + 1)] = pc1;
         self.pc = nnn
     }
     // 3xkk
@@ -171,14 +298,22 @@ impl Chip8 {
     // 8xy1
     pub fn or(&mut self, v_x: usize, v_y: usize) {
         self.v[v_x] |= self.v[v_y];
+        self.logic_quirk();
     }
     // 8xy2
     pub fn and(&mut self, v_x: usize, v_y: usize) {
         self.v[v_x] &= self.v[v_y];
+        self.logic_quirk();
     }
     // 8xy3
     pub fn xor(&mut self, v_x: usize, v_y: usize) {
         self.v[v_x] ^= self.v[v_y];
+        self.logic_quirk();
+    }
+    fn logic_quirk(&mut self) {
+        if self.quirks.logic {
+            self.v[0xF] = 0;
+        }
     }
     // 8xy4
     pub fn add_with_carry(&mut self, v_x: usize, v_y: usize) {
@@ -195,37 +330,34 @@ impl Chip8 {
     }
     // 8xy5
     pub fn sub_with_borrow(&mut self, v_x: usize, v_y: usize) {
-        match self.v[v_x].checked_sub(self.v[v_y]) {
-            None => {
-                self.v[v_x] = self.v[v_x].wrapping_sub(self.v[v_y]);
-                self.v[0xF] = 1;
-            }
-            Some(x) => {
-                self.v[v_x] = x;
-                self.v[0xF] = 0;
-            }
-        }
+        // vf is 1 when there is no borrow, set after the result so it wins for x = f
+        let no_borrow = self.v[v_x] >= self.v[v_y];
+        self.v[v_x] = self.v[v_x].wrapping_sub(self.v[v_y]);
+        self.v[0xF] = no_borrow as u8;
     }
     // 8xy6
     pub fn shr(&mut self, v_x: usize, v_y: usize) {
-        let x = self.v[v_x];
+        let x = self.shift_source(v_x, v_y);
+        self.v[v_x] = x >> 1;
         self.v[0xF] = x & 0x1; // lsb underflow
-        self.v[v_x] >>= 1;
     }
     // 8xy7
     pub fn subn(&mut self, v_x: usize, v_y: usize) {
-        let carry = match self.v[v_y] > self.v[v_x] {
-            false => 0,
-            true => 1,
-        };
-        self.v[0xF] = carry;
-        self.v[v_x] -= self.v[v_y];
+        let no_borrow = self.v[v_y] >= self.v[v_x];
+        self.v[v_x] = self.v[v_y].wrapping_sub(self.v[v_x]);
+        self.v[0xF] = no_borrow as u8;
     }
     // 8xyE
-    pub fn shl(&mut self, v_x: usize) {
-        let x = self.v[v_x];
+    pub fn shl(&mut self, v_x: usize, v_y: usize) {
+        let x = self.shift_source(v_x, v_y);
+        self.v[v_x] = x << 1;
         self.v[0xF] = x >> 7; // msb overflow
-        self.v[v_x] <<= 1;
+    }
+    fn shift_source(&self, v_x: usize, v_y: usize) -> u8 {
+        match self.quirks.shift {
+            true => self.v[v_x],
+            false => self.v[v_y],
+        }
     }
     // 9xy0
     pub fn sne_v(&mut self, v_x: usize, v_y: usize) {
@@ -249,7 +381,7 @@ impl Chip8 {
 
     // X and Y must be inside the screen bounds.
     fn screen_bit_write(&mut self, x: usize, y: usize, set: bool) -> bool {
-        let byte_offset = DISPLAY + (y * COL_SIZE_BYTE) + (x / 8);
+        let byte_offset = self.memory_map.display + (y * COL_SIZE_BYTE) + (x / 8);
         let byte_sector = self.memory[byte_offset];
         let bit_offset = x % 8;
         let old_bit = bit_value(byte_sector, bit_offset);
@@ -259,7 +391,7 @@ impl Chip8 {
         self.memory[byte_offset] = new_byte_sector;
 
         // Need to return if any bits erased (old set to unset)
-        return !old_bit & new_value;
+        !old_bit & new_value
     }
 
     //Dxyn
@@ -276,7 +408,7 @@ impl Chip8 {
         // Read one byte up to n-times. This is the vertical position.
         for y_i in 0..n {
             // read a byte of data from I location
-            let byte_read = self.memory[(self.i as usize) + y_i];
+            let byte_read = self.memory[self.i_address(y_i)];
 
             // Lets go bit by bit
             for bit_i in 0..8 {
@@ -286,6 +418,11 @@ impl Chip8 {
                 // if its not set we can safely ignore xor-ing it
                 // if it is set we must write the bit to memory
 
+                // without the wrap quirk sprites are clipped at the edges
+                if !self.quirks.wrap && ((x % COLS) + bit_i >= COLS || (y % ROWS) + y_i >= ROWS) {
+                    continue;
+                }
+
                 if is_set {
                     // Need to adjust actual x/y position if outside bounds
                     let adj_x = (x + bit_i) % COLS;
@@ -304,12 +441,25 @@ impl Chip8 {
             false => 0,
         };
         self.should_draw = true;
+        self.frame_stats.draws += 1;
     }
 
     pub fn is_key_down(&self, value: u8) -> bool {
         self.keyboard & (1 << value) != 0
     }
 
+    // Keypad input from the frontends. A press also answers a pending Fx0A.
+    pub fn press_key(&mut self, key: u8) {
+        self.keyboard |= 1 << (key & 0xF);
+        if self.wait_key {
+            self.respond_to_wait_key(key & 0xF);
+        }
+    }
+
+    pub fn release_key(&mut self, key: u8) {
+        self.keyboard &= !(1 << (key & 0xF));
+    }
+
     // Ex9E
     pub fn skip_if_key_pressed(&mut self, v_x: usize) {
         if self.is_key_down(self.v[v_x]) {
@@ -339,9 +489,9 @@ impl Chip8 {
         //self.v[v_x] = get_key();
     }
 
-    // TODO: need to call from main code
     pub fn respond_to_wait_key(&mut self, key: u8) {
         self.v[self.wait_key_v_x] = key;
+        self.wait_key = false;
     }
 
     // Fx15
@@ -352,16 +502,26 @@ impl Chip8 {
     pub fn set_sound_timer(&mut self, v_x: usize) {
         self.timer_sound = self.v[v_x];
     }
+    // I reaches 12 bits of address and wraps, so the interpreter RAM
+    // above 0xFFF stays out of its reach
+    fn i_address(&self, offset: usize) -> usize {
+        (self.i as usize + offset) % MEMORY_SIZE
+    }
     // Fx1E
     pub fn add_i(&mut self, v_x: usize) {
-        self.i += self.v[v_x] as u16;
+        self.i = self.i.wrapping_add(self.v[v_x] as u16);
     }
     // Fx29
     pub fn load_font(&mut self, v_x: usize) {
         // Fx29 - LD F, Vx
         // Set I = location of sprite for digit Vx.
         let val = self.v[v_x];
-        self.i = (FONT_DATA + (val as usize * FONT_SIZE)) as u16;
+        self.i = (self.memory_map.font + (val as usize * FONT_SIZE)) as u16;
+    }
+    // Fx30, SCHIP
+    pub fn load_big_font(&mut self, v_x: usize) {
+        let val = self.v[v_x] & 0xF;
+        self.i = (self.memory_map.big_font + (val as usize * BIG_FONT_SIZE)) as u16;
     }
     // Fx33
     pub fn load_bcd(&mut self, v_x: usize) {
@@ -369,23 +529,63 @@ impl Chip8 {
         let hundreds = val / 100;
         let tens = val % 100 / 10;
         let ones = val % 10;
-        self.memory[(self.i + 0) as usize] = hundreds;
-        self.memory[(self.i + 1) as usize] = tens;
-        self.memory[(self.i + 2) as usize] = ones;
+        self.memory[self.i_address(0)] = hundreds;
+        self.memory[self.i_address(1)] = tens;
+        self.memory[self.i_address(2)] = ones;
     }
     // Fx55
     pub fn store_registers(&mut self) {
         for i in 0..16 {
-            self.memory[self.i as usize + i] = self.v[i as usize]
+            self.memory[self.i_address(i)] = self.v[i]
         }
     }
     // Fx65
     pub fn recall_registers(&mut self) {
         for i in 0..16 {
-            self.v[i as usize] = self.memory[self.i as usize + i]
+            self.v[i] = self.memory[self.i_address(i)]
         }
     }
 
+    // Work out which bytes the instruction b0 b1 will read or write if it
+    // is executed now. Must be called before decode_execute.
+    pub fn memory_accesses(&self, b0: u8, b1: u8) -> MemoryAccess {
+        let mut access = MemoryAccess::default();
+        let opcode = b0 >> 4;
+        let x = (b0 & 0x0F) as usize;
+        if b0 == 0x00 && b1 == 0xEE {
+            let slot = self.memory_map.callstack + (self.sp as usize * 2);
+            access.reads.push(slot);
+            access.reads.push(slot + 1);
+        } else if opcode == 2 {
+            let slot = self.memory_map.callstack + ((self.sp as usize + 1) * 2);
+            access.writes.push(slot);
+            access.writes.push(slot + 1);
+        } else if opcode == 0xD {
+            let n = (b1 & 0x0F) as usize;
+            let col = self.v[x] as usize % COLS;
+            let row = self.v[(b1 >> 4) as usize] as usize;
+            for y_i in 0..n {
+                access.reads.push(self.i_address(y_i));
+                // a sprite row spans at most two display bytes
+                let row_start = self.memory_map.display + ((row + y_i) % ROWS) * COL_SIZE_BYTE;
+                access.writes.push(row_start + col / 8);
+                let shift = col % 8;
+                if shift != 0 {
+                    access.writes.push(row_start + ((col / 8) + 1) % COL_SIZE_BYTE);
+                }
+            }
+        } else if opcode == 0xF && b1 == 0x33 {
+            access.writes.extend((0..3).map(|n| self.i_address(n)));
+        } else if opcode == 0xF && b1 == 0x55 {
+            access.writes.extend((0..16).map(|n| self.i_address(n)));
+        } else if opcode == 0xF && b1 == 0x65 {
+            access.reads.extend((0..16).map(|n| self.i_address(n)));
+        }
+        access.reads.retain(|a| *a < MEMORY_SIZE);
+        access.writes.retain(|a| *a < MEMORY_SIZE);
+        access
+    }
+
     // Given a fetched instruction, decode and execute the function
     pub fn decode_execute(&mut self, b0: u8, b1: u8) {
         let opcode = b0 >> 4;
@@ -440,7 +640,7 @@ impl Chip8 {
             } else if arg2 == 7 {
                 self.subn(x, y)
             } else if arg2 == 0xE {
-                self.shl(x)
+                self.shl(x, y)
             } else {
                 println!("MATH???")
             }
@@ -473,6 +673,8 @@ impl Chip8 {
                 self.add_i(x)
             } else if b1 == 0x29 {
                 self.load_font(x)
+            } else if b1 == 0x30 {
+                self.load_big_font(x)
             } else if b1 == 0x33 {
                 self.load_bcd(x)
             } else if b1 == 0x55 {
@@ -488,13 +690,24 @@ impl Chip8 {
     }
 }
 
+// A machine with the fonts and the rom in place, set up the same way for every mode
+pub fn machine(memory_map: MemoryMap, fonts: &Fonts, rom: &Rom, quirks: Quirks, instructions_per_frame: u32) -> Chip8 {
+    let mut chip8 = Chip8::new();
+    chip8.memory_map = memory_map;
+    chip8.install_fonts(fonts);
+    chip8.load_program_at(&rom.bytes, rom.address);
+    chip8.quirks = quirks;
+    chip8.instructions_per_frame = instructions_per_frame;
+    chip8
+}
+
 fn arg3(b0: u8, b1: u8) -> u16 {
-    return (((b0 & 0x0F) as u16) << 8) | b1 as u16;
+    (((b0 & 0x0F) as u16) << 8) | b1 as u16
 }
 
 fn bit_value(byte: u8, bit_index_ltr: usize) -> bool {
     // Shift it over up to 7 positions and mask the right most bit
-    return byte >> (7 - bit_index_ltr) & 0x01 == 1;
+    byte >> (7 - bit_index_ltr) & 0x01 == 1
 }
 
 fn byte_with_replaced_bit(byte: u8, bit_offset_ltr: usize, set: bool) -> u8 {
@@ -510,6 +723,7 @@ mod tests {
     use super::*;
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn bit_value_test() {
         let five: u8 = 5;
         assert_eq!(false, bit_value(five, 0));
@@ -522,6 +736,112 @@ mod tests {
         assert_eq!(true, bit_value(five, 7));
     }
 
+    #[test]
+    fn vblank_quirk_test() {
+        // D005 then jump back to it
+        let mut chip8 = Chip8::new();
+        chip8.load_program_at(&[0xD0, 0x05, 0x12, 0x00], 0x200);
+        chip8.quirks.vblank = true;
+        while !chip8.frame_over() {
+            let (b0, b1) = chip8.fetch();
+            chip8.decode_execute(b0, b1);
+        }
+        let stats = chip8.end_frame();
+        assert_eq!(FrameStats { instructions: 1, draws: 1 }, stats);
+        assert_eq!(stats, chip8.last_frame);
+        chip8.quirks.vblank = false;
+        while !chip8.frame_over() {
+            let (b0, b1) = chip8.fetch();
+            chip8.decode_execute(b0, b1);
+        }
+        assert_eq!(INSTRUCTIONS_PER_FRAME, chip8.frame_stats.instructions);
+        assert_eq!(5, chip8.frame_stats.draws);
+    }
+
+    #[test]
+    fn memory_map_test() {
+        let mut chip8 = Chip8::new();
+        chip8.memory_map = MemoryMap::preset("schip").unwrap();
+        chip8.load_program_at(&[0x22, 0x04, 0x00, 0x00, 0x00, 0xEE], 0x200);
+        let (b0, b1) = chip8.fetch();
+        chip8.decode_execute(b0, b1);
+        assert_eq!(vec![0x202], chip8.call_stack());
+        // the stack is out of the program's reach
+        assert!(chip8.memory[..MEMORY_SIZE].iter().skip(0x206).all(|b| *b == 0));
+        let (b0, b1) = chip8.fetch();
+        chip8.decode_execute(b0, b1);
+        assert_eq!(0x202, chip8.pc);
+
+        let mut eti = Chip8::new();
+        eti.memory_map = MemoryMap::preset("eti660").unwrap();
+        eti.load_program_at(&[0x00, 0xE0], eti.memory_map.program);
+        assert_eq!(0x600, eti.pc);
+    }
+
+    #[test]
+    fn i_wraps_test() {
+        let mut chip8 = Chip8::new();
+        chip8.memory_map = MemoryMap::preset("schip").unwrap();
+        chip8.i = 0xFF0;
+        chip8.v[0] = 0x0F;
+        chip8.v[1] = 0xAB;
+        chip8.decode_execute(0xF0, 0x1E);
+        assert_eq!(0xFFF, chip8.i);
+        chip8.decode_execute(0xF1, 0x55);
+        assert_eq!([0x0F, 0xAB], [chip8.memory[0xFFF], chip8.memory[0x000]]);
+        // the stack and display above 0xFFF are left alone
+        assert!(chip8.memory[MEMORY_SIZE..].iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn big_font_test() {
+        let mut chip8 = Chip8::new();
+        chip8.load_fonts();
+        chip8.v[3] = 2;
+        chip8.decode_execute(0xF3, 0x30);
+        assert_eq!((chip8.memory_map.big_font + 20) as u16, chip8.i);
+        assert_eq!([0xFF, 0xFF, 0x03], chip8.memory[chip8.i as usize..chip8.i as usize + 3]);
+        assert_eq!(FONT_SPRITES[0][..], chip8.memory[0..5]);
+    }
+
+    #[test]
+    fn shift_and_logic_quirks_test() {
+        let mut chip8 = Chip8::new();
+        chip8.v[0] = 0x01;
+        chip8.v[1] = 0x81;
+        chip8.shl(0, 1);
+        assert_eq!((0x02, 0), (chip8.v[0], chip8.v[0xF]));
+        chip8.quirks.shift = false;
+        chip8.shl(0, 1);
+        assert_eq!((0x02, 1), (chip8.v[0], chip8.v[0xF]));
+        chip8.quirks.logic = true;
+        chip8.or(0, 1);
+        assert_eq!((0x83, 0), (chip8.v[0], chip8.v[0xF]));
+    }
+
+    #[test]
+    fn subtract_flags_test() {
+        let mut chip8 = Chip8::new();
+        chip8.v[0] = 5;
+        chip8.v[1] = 2;
+        chip8.sub_with_borrow(0, 1);
+        assert_eq!((3, 1), (chip8.v[0], chip8.v[0xF]));
+        chip8.sub_with_borrow(1, 0);
+        assert_eq!((0xFF, 0), (chip8.v[1], chip8.v[0xF]));
+        chip8.v[0] = 5;
+        chip8.v[1] = 2;
+        chip8.subn(0, 1);
+        assert_eq!((0xFD, 0), (chip8.v[0], chip8.v[0xF]));
+        chip8.v[0] = 5;
+        chip8.subn(1, 0);
+        assert_eq!((3, 1), (chip8.v[1], chip8.v[0xF]));
+        // the flag wins over the result when x is f
+        chip8.v[0xF] = 9;
+        chip8.v[2] = 4;
+        chip8.sub_with_borrow(0xF, 2);
+        assert_eq!(1, chip8.v[0xF]);
+    }
+
     #[test]
     fn byte_with_replaced_bit_set_test() {
         let five: u8 = 5;
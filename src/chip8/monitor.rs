@@ -0,0 +1,475 @@
+use crate::chip8::debugger::{Debugger, StopReason};
+use crate::chip8::emu_utils::display_render;
+use crate::chip8::palette::Palette;
+use crate::chip8::screenshot::{parse_scale, save_screenshot};
+use crate::chip8::state::{load_state, save_state};
+use crate::chip8::symbols::{describe_address, parse_address, Symbols};
+use crate::chip8::Chip8;
+use crate::chip8::MEMORY_SIZE;
+use c8_disasm_lib::decode;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+
+// Line oriented monitor for when the cursive tui is not an option,
+// e.g. over a serial console.
+
+// cont stops after this many instructions so a runaway rom hands back the prompt
+const CONT_LIMIT: u32 = 1_000_000;
+
+const HELP: &str = "\
+step [n]              run n instructions (default 1)
+cont                  run until a breakpoint
+break <addr>          set a breakpoint, addr may be a label
+delete <addr>         remove a breakpoint
+breaks                list breakpoints
+regs                  show registers
+mem <addr> [len]      hex dump memory
+set <reg> <value>     set v0-vf, i, pc, sp, dt or st
+poke <addr> <b> ...   write bytes to memory
+disasm [addr] [n]     disassemble n instructions from addr (default pc)
+screen                draw the display
+shot <file> [scale]   save a .png, .pbm or .pgm screenshot
+key <k> down|up       press or release hex key k
+save <file>           save state
+load <file>           load state
+trace on|off          print each instruction as it runs
+quit                  leave the monitor";
+
+pub struct Monitor {
+    pub chip8: Chip8,
+    pub debugger: Debugger,
+    pub symbols: Option<Symbols>,
+    pub trace: bool,
+    glyph: char,
+    palette: Palette,
+    scale: usize,
+}
+
+impl Monitor {
+    pub fn new(chip8: Chip8, symbols: Option<Symbols>, glyph: char, palette: Palette, scale: usize) -> Monitor {
+        Monitor {
+            chip8,
+            debugger: Debugger::new(),
+            symbols,
+            trace: false,
+            glyph,
+            palette,
+            scale,
+        }
+    }
+
+    fn describe(&self, address: u16) -> String {
+        describe_address(self.symbols.as_ref(), address)
+    }
+
+    fn resolve(&self, text: &str) -> Option<u16> {
+        if text == "pc" {
+            return Some(self.chip8.pc);
+        }
+        if text == "i" {
+            return Some(self.chip8.i);
+        }
+        match &self.symbols {
+            Some(symbols) => symbols.resolve(text),
+            None => parse_address(text),
+        }
+    }
+
+    // Run one command line. Returns false when the user wants to leave.
+    pub fn execute_line(&mut self, line: &str) -> bool {
+        match parse_command(line) {
+            Ok(Some(command)) => self.execute(command),
+            Ok(None) => true,
+            Err(e) => {
+                println!("{}", e);
+                true
+            }
+        }
+    }
+
+    pub fn execute(&mut self, command: Command) -> bool {
+        match command {
+            Command::Step(n) => {
+                self.run(n);
+                self.print_location();
+            }
+            Command::Cont => self.run(CONT_LIMIT),
+            Command::Break(text) => match self.resolve(&text) {
+                Some(address) => {
+                    self.debugger.add_breakpoint(address);
+                    println!("breakpoint at {}", self.describe(address));
+                }
+                None => println!("Unknown address {}", text),
+            },
+            Command::Delete(text) => match self.resolve(&text) {
+                Some(address) => self.debugger.remove_breakpoint(address),
+                None => println!("Unknown address {}", text),
+            },
+            Command::Breaks => {
+                for address in &self.debugger.breakpoints {
+                    println!("{:#05X} {}", address, self.describe(*address));
+                }
+            }
+            Command::Regs => self.print_registers(),
+            Command::Mem(text, len) => match self.resolve(&text) {
+                Some(address) => self.dump_memory(address as usize, len),
+                None => println!("Unknown address {}", text),
+            },
+            Command::Set(register, value) => {
+                if let Err(e) = self.set_register(&register, value) {
+                    println!("{}", e);
+                }
+            }
+            Command::Poke(text, bytes) => {
+                if let Err(e) = self.poke(&text, &bytes) {
+                    println!("{}", e);
+                }
+            }
+            Command::Disasm(text, count) => {
+                let address = match text {
+                    Some(text) => match self.resolve(&text) {
+                        Some(x) => x,
+                        None => {
+                            println!("Unknown address {}", text);
+                            return true;
+                        }
+                    },
+                    None => self.chip8.pc,
+                };
+                self.disassemble(address as usize, count);
+            }
+            Command::Screen => display_render(&self.chip8, false, self.glyph),
+            Command::Shot(path, scale) => {
+                let scale = scale.unwrap_or(self.scale);
+                match save_screenshot(&path, &self.chip8, scale, &self.palette) {
+                    Ok(()) => println!("saved {}", path.display()),
+                    Err(e) => println!("{}", e),
+                }
+            }
+            Command::Key(key, true) => self.chip8.press_key(key),
+            Command::Key(key, false) => self.chip8.release_key(key),
+            Command::Save(path) => match save_state(&self.chip8, &path) {
+                Ok(()) => println!("saved {}", path.display()),
+                Err(e) => println!("{}", e),
+            },
+            Command::Load(path) => match load_state(&mut self.chip8, &path) {
+                Ok(()) => self.print_location(),
+                Err(e) => println!("{}", e),
+            },
+            Command::Trace(Some(on)) => self.trace = on,
+            Command::Trace(None) => println!("trace is {}", if self.trace { "on" } else { "off" }),
+            Command::Help => println!("{}", HELP),
+            Command::Quit => return false,
+        }
+        true
+    }
+
+    fn run(&mut self, max_steps: u32) {
+        for n in 0..max_steps {
+            let pc = self.chip8.pc;
+            if n > 0 && self.debugger.breakpoints.contains(&pc) {
+                println!("breakpoint at {}", self.describe(pc));
+                return;
+            }
+            if pc as usize + 1 >= MEMORY_SIZE {
+                println!("pc ran off the end of memory at {:#05X}", pc);
+                return;
+            }
+            if self.trace {
+                let b0 = self.chip8.memory[pc as usize];
+                let b1 = self.chip8.memory[pc as usize + 1];
+                println!("{:#05X} {:<16} {}", pc, self.describe(pc), decode(b0, b1));
+            }
+            if let StopReason::Watchpoint(_, address) = self.debugger.step(&mut self.chip8) {
+                println!("watchpoint {:#05X} hit at {}", address, self.describe(pc));
+                return;
+            }
+        }
+        if max_steps == CONT_LIMIT {
+            println!("stopped after {} instructions", CONT_LIMIT);
+        }
+    }
+
+    fn print_location(&self) {
+        let pc = self.chip8.pc as usize;
+        if pc + 1 < MEMORY_SIZE {
+            let b0 = self.chip8.memory[pc];
+            let b1 = self.chip8.memory[pc + 1];
+            println!("{:#05X} {:<16} {}", pc, self.describe(pc as u16), decode(b0, b1));
+        }
+    }
+
+    fn print_registers(&self) {
+        let c = &self.chip8;
+        for n in 0..16 {
+            print!("v{:x}={:02X} ", n, c.v[n]);
+            if n % 8 == 7 {
+                println!();
+            }
+        }
+        println!(
+            "i={:03X} pc={:03X} ({}) sp={:02X} dt={:02X} st={:02X} keys={:04X}",
+            c.i,
+            c.pc,
+            self.describe(c.pc),
+            c.sp,
+            c.timer_delay,
+            c.timer_sound,
+            c.keyboard
+        );
+    }
+
+    fn dump_memory(&self, address: usize, len: usize) {
+        let end = (address + len).min(MEMORY_SIZE);
+        for row in (address..end).step_by(16) {
+            let bytes = &self.chip8.memory[row..(row + 16).min(end)];
+            let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+            let ascii: String = bytes
+                .iter()
+                .map(|b| match b.is_ascii_graphic() {
+                    true => *b as char,
+                    false => '.',
+                })
+                .collect();
+            println!("{:03X}: {:<48} {}", row, hex.join(" "), ascii);
+        }
+    }
+
+    fn disassemble(&self, address: usize, count: usize) {
+        for n in 0..count {
+            let at = address + n * 2;
+            if at + 1 >= MEMORY_SIZE {
+                break;
+            }
+            let b0 = self.chip8.memory[at];
+            let b1 = self.chip8.memory[at + 1];
+            if let Some(label) = self.symbols.as_ref().and_then(|s| s.label_at(at as u16)) {
+                println!("{}:", label);
+            }
+            let marker = if at == self.chip8.pc as usize { '>' } else { ' ' };
+            println!("{} {:03X}  {:02X}{:02X}  {}", marker, at, b0, b1, decode(b0, b1));
+        }
+    }
+
+    fn set_register(&mut self, register: &str, value: u16) -> Result<(), String> {
+        let c = &mut self.chip8;
+        match register {
+            "i" => c.i = value,
+            "pc" => c.pc = value,
+            "sp" => c.sp = value as u8,
+            "dt" => c.timer_delay = value as u8,
+            "st" => c.timer_sound = value as u8,
+            reg if reg.len() == 2 && reg.starts_with('v') => match usize::from_str_radix(&reg[1..], 16) {
+                Ok(n) => c.v[n] = value as u8,
+                Err(_) => return Err(format!("Unknown register {}", reg)),
+            },
+            reg => return Err(format!("Unknown register {}", reg)),
+        }
+        Ok(())
+    }
+
+    fn poke(&mut self, text: &str, bytes: &[u8]) -> Result<(), String> {
+        let address = match self.resolve(text) {
+            Some(x) => x as usize,
+            None => return Err(format!("Unknown address {}", text)),
+        };
+        if address + bytes.len() > MEMORY_SIZE {
+            return Err(format!("{} bytes at {:#05X} run past the end of memory", bytes.len(), address));
+        }
+        self.chip8.memory[address..address + bytes.len()].copy_from_slice(bytes);
+        Ok(())
+    }
+}
+
+// A monitor command, addresses are kept as text until there are symbols
+// to resolve them against
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Step(u32),
+    Cont,
+    Break(String),
+    Delete(String),
+    Breaks,
+    Regs,
+    Mem(String, usize),
+    Set(String, u16),
+    Poke(String, Vec<u8>),
+    Disasm(Option<String>, usize),
+    Screen,
+    Shot(PathBuf, Option<usize>),
+    Key(u8, bool),
+    Save(PathBuf),
+    Load(PathBuf),
+    Trace(Option<bool>),
+    Help,
+    Quit,
+}
+
+// None for a blank line, the usage on a bad one
+pub fn parse_command(line: &str) -> Result<Option<Command>, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    if words.is_empty() {
+        return Ok(None);
+    }
+    let args = &words[1..];
+    let arg = |n: usize| args.get(n).map(|a| a.to_string());
+    let number = |n: usize, usage: &str| match args.get(n) {
+        Some(text) => parse_address(text).map(Some).ok_or(format!("usage: {}", usage)),
+        None => Ok(None),
+    };
+    let command = match words[0] {
+        "step" | "s" => Command::Step(number(0, "step [n]")?.unwrap_or(1) as u32),
+        "cont" | "c" => Command::Cont,
+        "break" | "b" => Command::Break(arg(0).ok_or("usage: break <addr>")?),
+        "delete" => Command::Delete(arg(0).ok_or("usage: delete <addr>")?),
+        "breaks" => Command::Breaks,
+        "regs" | "r" => Command::Regs,
+        "mem" | "m" => {
+            let address = arg(0).ok_or("usage: mem <addr> [len]")?;
+            Command::Mem(address, number(1, "mem <addr> [len]")?.unwrap_or(16) as usize)
+        }
+        "set" => match (arg(0), number(1, "set <reg> <value>")?) {
+            (Some(register), Some(value)) => Command::Set(register.to_lowercase(), value),
+            _ => return Err("usage: set <reg> <value>".to_string()),
+        },
+        "poke" => {
+            let address = arg(0).ok_or("usage: poke <addr> <byte> ...")?;
+            let mut bytes = Vec::new();
+            for text in args.iter().skip(1) {
+                match parse_address(text) {
+                    Some(byte) if byte <= 0xFF => bytes.push(byte as u8),
+                    _ => return Err(format!("Bad byte {}", text)),
+                }
+            }
+            Command::Poke(address, bytes)
+        }
+        "disasm" | "d" => Command::Disasm(arg(0), number(1, "disasm [addr] [n]")?.unwrap_or(10) as usize),
+        "screen" => Command::Screen,
+        "shot" => {
+            let path = arg(0).ok_or("usage: shot <file> [scale]")?;
+            let scale = match args.get(1) {
+                Some(text) => Some(parse_scale(text)?),
+                None => None,
+            };
+            Command::Shot(PathBuf::from(path), scale)
+        }
+        "key" => {
+            let key = args.first().and_then(|k| u8::from_str_radix(k, 16).ok()).filter(|k| *k < 16);
+            match (key, args.get(1)) {
+                (Some(k), Some(&"down")) => Command::Key(k, true),
+                (Some(k), Some(&"up")) => Command::Key(k, false),
+                _ => return Err("usage: key <0-F> down|up".to_string()),
+            }
+        }
+        "save" => Command::Save(PathBuf::from(arg(0).ok_or("usage: save <file>")?)),
+        "load" => Command::Load(PathBuf::from(arg(0).ok_or("usage: load <file>")?)),
+        "trace" => match args.first() {
+            Some(&"on") => Command::Trace(Some(true)),
+            Some(&"off") => Command::Trace(Some(false)),
+            _ => Command::Trace(None),
+        },
+        "help" | "?" => Command::Help,
+        "quit" | "q" | "exit" => Command::Quit,
+        other => return Err(format!("Unknown command {}, try help", other)),
+    };
+    Ok(Some(command))
+}
+
+pub fn run_monitor(chip8: Chip8, symbols: Option<Symbols>, glyph: char, palette: Palette, scale: usize) {
+    let mut monitor = Monitor::new(chip8, symbols, glyph, palette, scale);
+    println!("CHIP-8 monitor, type help for commands");
+    monitor.print_location();
+    let stdin = io::stdin();
+    loop {
+        print!("chip8> ");
+        io::stdout().flush().ok();
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+        if !monitor.execute_line(&line) {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::symbols::parse_symbols;
+    use std::env;
+    use std::fs;
+
+    fn monitor() -> Monitor {
+        let mut chip8 = Chip8::new();
+        // v0 := 5, v0 += 1, halt
+        chip8.load_program_at(&[0x60, 0x05, 0x70, 0x01, 0x12, 0x04], 0x200);
+        let symbols = parse_symbols("main 0x200\nhalt 0x204\n").unwrap();
+        Monitor::new(chip8, Some(symbols), '#', Palette::mono(), 1)
+    }
+
+    #[test]
+    fn parse_command_test() {
+        assert_eq!(Ok(None), parse_command("   "));
+        assert_eq!(Ok(Some(Command::Step(1))), parse_command("s"));
+        assert_eq!(Ok(Some(Command::Step(0x10))), parse_command("step 0x10"));
+        assert_eq!(Ok(Some(Command::Mem("main".to_string(), 16))), parse_command("m main"));
+        assert_eq!(Ok(Some(Command::Set("va".to_string(), 0x2A))), parse_command("set VA 2Ah"));
+        assert_eq!(
+            Ok(Some(Command::Poke("0x300".to_string(), vec![1, 0xFF]))),
+            parse_command("poke 0x300 1 0xff")
+        );
+        assert_eq!(Ok(Some(Command::Key(0xA, true))), parse_command("key a down"));
+        assert_eq!(Ok(Some(Command::Trace(Some(false)))), parse_command("trace off"));
+        assert_eq!(Ok(Some(Command::Quit)), parse_command("exit\n"));
+        assert_eq!(Err("usage: break <addr>".to_string()), parse_command("break"));
+        assert_eq!(Err("Bad byte 0x100".to_string()), parse_command("poke 0x300 0x100"));
+        assert_eq!(Err("usage: key <0-F> down|up".to_string()), parse_command("key 10 down"));
+        assert_eq!(Err("usage: set <reg> <value>".to_string()), parse_command("set v0"));
+        assert_eq!(Ok(Some(Command::Shot(PathBuf::from("a.png"), Some(2)))), parse_command("shot a.png 2"));
+        assert!(parse_command("shot a.png 0").is_err());
+        assert!(parse_command("jump 0x200").is_err());
+    }
+
+    #[test]
+    fn edit_registers_and_memory_test() {
+        let mut monitor = monitor();
+        for line in &["set v3 0x12", "set dt 60", "set st 3", "set i halt", "poke halt 0xAB 0xCD"] {
+            assert!(monitor.execute_line(line));
+        }
+        assert_eq!((0x12, 60, 3), (monitor.chip8.v[3], monitor.chip8.timer_delay, monitor.chip8.timer_sound));
+        // set takes numbers, not labels
+        assert_eq!(0, monitor.chip8.i);
+        assert_eq!([0xAB, 0xCD], monitor.chip8.memory[0x204..0x206]);
+        assert!(monitor.set_register("vg", 1).is_err());
+        assert!(monitor.poke("0xFFF", &[1, 2]).is_err());
+        assert!(!monitor.execute_line("quit"));
+    }
+
+    #[test]
+    fn step_and_break_test() {
+        let mut monitor = monitor();
+        monitor.execute_line("break halt");
+        assert_eq!(vec![0x204], monitor.debugger.breakpoints);
+        monitor.execute_line("cont");
+        assert_eq!((0x204, 6), (monitor.chip8.pc, monitor.chip8.v[0]));
+        monitor.execute_line("delete halt");
+        monitor.execute_line("step 3");
+        assert_eq!(0x204, monitor.chip8.pc);
+        assert!(monitor.debugger.breakpoints.is_empty());
+    }
+
+    #[test]
+    fn save_and_load_test() {
+        let path = env::temp_dir().join(format!("chip8-monitor-{}.state", std::process::id()));
+        let mut monitor = monitor();
+        monitor.execute_line("step 2");
+        monitor.execute(Command::Save(path.clone()));
+        monitor.execute_line("set v0 0");
+        monitor.execute_line("set pc 0x200");
+        monitor.execute(Command::Load(path.clone()));
+        assert_eq!((0x204, 6), (monitor.chip8.pc, monitor.chip8.v[0]));
+        fs::remove_file(&path).unwrap();
+    }
+}
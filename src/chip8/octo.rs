@@ -0,0 +1,878 @@
+use crate::chip8::symbols::{SourceLine, Symbols};
+use crate::chip8::{DATA, MEMORY_SIZE};
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::Path;
+
+// Compiler for Octo (https://github.com/JohnEarnest/Octo) sources, .8o files.
+// Tokens are separated by whitespace, '#' starts a comment.
+//
+//   : name              label, a bare label name calls it
+//   :const name 10      :alias name vX
+//   :macro name a b { ... a ... b ... }
+//   :calc name { HERE + 2 * 3 }    evaluated right to left, no precedence
+//   :org 0x300          :byte 7    :call name    :unpack 0xA name
+//   v0 := 5  v0 += v1  v0 -= 1  v0 =- v1  |= &= ^= >>= <<=
+//   v0 := random 0xFF  v0 := key  v0 := delay  delay := v0  buzzer := v0
+//   i := name  i := hex v0  i += v0  bcd v0  save v3  load v3
+//   sprite v0 v1 5  clear  return (or ;)  jump name  jump0 name  native name
+//   if v0 == 3 then ...   if v0 key begin ... else ... end
+//   loop ... while v0 != 0 ... again
+//   if v0 < v1 then ...   < > <= >= go through vf, so vf can't be compared
+//
+// Execution starts at main, a jump to it is placed at 0x200.
+
+// Expansions allowed before a macro is taken to be recursive
+const MAX_EXPANSIONS: usize = 100_000;
+
+#[derive(Clone, Debug, Default)]
+pub struct Compiled {
+    pub bytes: Vec<u8>,
+    pub symbols: Symbols,
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    text: String,
+    line: u32,
+}
+
+struct Macro {
+    args: Vec<String>,
+    body: Vec<Token>,
+}
+
+// What to write once a label's address is known
+#[derive(Clone, Copy, Debug)]
+enum Patch {
+    // the low 12 bits of the instruction
+    Address,
+    // :unpack's v0 := nibble and high bits
+    UnpackHigh(u8),
+    // :unpack's v1 := low byte
+    UnpackLow,
+}
+
+struct Fixup {
+    address: usize,
+    name: String,
+    patch: Patch,
+    line: u32,
+}
+
+enum Flow {
+    Loop { start: usize, exits: Vec<usize> },
+    // address of the jump to patch at else or end
+    Begin(usize),
+    Else(usize),
+}
+
+struct Compiler<'a> {
+    file: &'a str,
+    tokens: VecDeque<Token>,
+    line: u32,
+    rom: Vec<u8>,
+    here: usize,
+    labels: Vec<(String, u16)>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    flow: Vec<Flow>,
+    lines: Vec<(u16, SourceLine)>,
+    expansions: usize,
+}
+
+pub fn compile_file(path: &Path) -> Result<Compiled, String> {
+    let source = match fs::read_to_string(path) {
+        Ok(x) => x,
+        Err(e) => return Err(format!("Could not read {}: {}", path.display(), e)),
+    };
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("program.8o");
+    compile(&source, name)
+}
+
+pub fn compile(source: &str, file: &str) -> Result<Compiled, String> {
+    let mut tokens = VecDeque::new();
+    for (i, line) in source.lines().enumerate() {
+        let code = line.split('#').next().unwrap_or("");
+        for word in code.split_whitespace() {
+            tokens.push_back(Token {
+                text: word.to_string(),
+                line: i as u32 + 1,
+            });
+        }
+    }
+    let mut compiler = Compiler {
+        file,
+        tokens,
+        line: 0,
+        rom: Vec::new(),
+        here: DATA,
+        labels: Vec::new(),
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        fixups: Vec::new(),
+        flow: Vec::new(),
+        lines: Vec::new(),
+        expansions: 0,
+    };
+    compiler.run().map_err(|e| format!("{}:{}: {}", file, compiler.line, e))?;
+    Ok(Compiled {
+        bytes: compiler.rom,
+        symbols: Symbols {
+            labels: compiler.labels,
+            lines: compiler.lines,
+        },
+    })
+}
+
+fn register(text: &str) -> Option<u8> {
+    let lower = text.to_lowercase();
+    if lower.len() == 2 && lower.starts_with('v') {
+        return u8::from_str_radix(&lower[1..], 16).ok();
+    }
+    None
+}
+
+fn number(text: &str) -> Option<f64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()? as f64
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()? as f64
+    } else {
+        digits.parse::<i64>().ok()? as f64
+    };
+    Some(if negative { -value } else { value })
+}
+
+impl<'a> Compiler<'a> {
+    fn run(&mut self) -> Result<(), String> {
+        // jump main, patched at the end. Not from a source line, so it
+        // isn't in the line map.
+        self.fixups.push(Fixup {
+            address: self.here,
+            name: "main".to_string(),
+            patch: Patch::Address,
+            line: 1,
+        });
+        self.emit_byte(0x10)?;
+        self.emit_byte(0x00)?;
+        while let Some(token) = self.tokens.pop_front() {
+            self.line = token.line;
+            self.statement(&token.text)?;
+        }
+        if let Some(flow) = self.flow.last() {
+            return Err(match flow {
+                Flow::Loop { .. } => "loop without again".to_string(),
+                _ => "begin without end".to_string(),
+            });
+        }
+        for fixup in std::mem::take(&mut self.fixups) {
+            self.line = fixup.line;
+            let target = match self.label(&fixup.name) {
+                Some(x) => x as usize,
+                None => return Err(format!("Undefined name: {}", fixup.name)),
+            };
+            let i = fixup.address - DATA;
+            match fixup.patch {
+                Patch::Address => {
+                    self.rom[i] |= ((target >> 8) & 0xF) as u8;
+                    self.rom[i + 1] = target as u8;
+                }
+                Patch::UnpackHigh(nibble) => self.rom[i + 1] = nibble << 4 | ((target >> 8) & 0xF) as u8,
+                Patch::UnpackLow => self.rom[i + 1] = target as u8,
+            }
+        }
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<String, String> {
+        match self.tokens.pop_front() {
+            Some(token) => {
+                self.line = token.line;
+                Ok(token.text)
+            }
+            None => Err("Unexpected end of file".to_string()),
+        }
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), String> {
+        let token = self.next()?;
+        match token == text {
+            true => Ok(()),
+            false => Err(format!("Expected {}, found {}", text, token)),
+        }
+    }
+
+    fn label(&self, name: &str) -> Option<u16> {
+        self.labels.iter().find(|(n, _)| n == name).map(|(_, a)| *a)
+    }
+
+    fn register(&self, text: &str) -> Option<u8> {
+        register(text).or_else(|| self.aliases.get(text).cloned())
+    }
+
+    fn expect_register(&mut self) -> Result<u8, String> {
+        let token = self.next()?;
+        self.register(&token)
+            .ok_or(format!("Expected a register, found {}", token))
+    }
+
+    fn is_name(&self, text: &str) -> bool {
+        self.constants.contains_key(text)
+            || self.aliases.contains_key(text)
+            || self.macros.contains_key(text)
+            || self.label(text).is_some()
+    }
+
+    fn define(&mut self, name: &str) -> Result<(), String> {
+        if self.is_name(name) || register(name).is_some() || number(name).is_some() {
+            return Err(format!("{} is already defined", name));
+        }
+        Ok(())
+    }
+
+    // A number or constant
+    fn constant(&self, text: &str) -> Option<f64> {
+        number(text).or_else(|| self.constants.get(text).cloned())
+    }
+
+    fn byte_value(&mut self) -> Result<u8, String> {
+        let token = self.next()?;
+        let value = match self.constant(&token) {
+            Some(x) => x as i64,
+            None => return Err(format!("Expected a number, found {}", token)),
+        };
+        match value {
+            -128..=255 => Ok(value as u8),
+            _ => Err(format!("{} does not fit in a byte", value)),
+        }
+    }
+
+    fn nibble_value(&mut self) -> Result<u8, String> {
+        let token = self.next()?;
+        match self.constant(&token).map(|x| x as i64) {
+            Some(x) if (0..16).contains(&x) => Ok(x as u8),
+            _ => Err(format!("Expected a number from 0 to 15, found {}", token)),
+        }
+    }
+
+    // Emit op | address, leaving a fixup for labels not seen yet
+    fn emit_address(&mut self, op: u16) -> Result<(), String> {
+        let token = self.next()?;
+        let address = match self.constant(&token).or_else(|| self.label(&token).map(|a| a as f64)) {
+            Some(x) => x as i64,
+            None => {
+                self.fixups.push(Fixup {
+                    address: self.here,
+                    name: token,
+                    patch: Patch::Address,
+                    line: self.line,
+                });
+                0
+            }
+        };
+        if !(0..=0xFFF).contains(&address) {
+            return Err(format!("Address {:#x} is out of range", address));
+        }
+        self.emit(op | address as u16)
+    }
+
+    fn write(&mut self, address: usize, byte: u8) -> Result<(), String> {
+        if address >= MEMORY_SIZE {
+            return Err("Program does not fit in memory".to_string());
+        }
+        let i = address - DATA;
+        if self.rom.len() <= i {
+            self.rom.resize(i + 1, 0);
+        }
+        self.rom[i] = byte;
+        Ok(())
+    }
+
+    fn emit_byte(&mut self, byte: u8) -> Result<(), String> {
+        self.write(self.here, byte)?;
+        self.here += 1;
+        Ok(())
+    }
+
+    fn emit(&mut self, word: u16) -> Result<(), String> {
+        self.lines.push((
+            self.here as u16,
+            SourceLine {
+                file: self.file.to_string(),
+                line: self.line,
+            },
+        ));
+        self.emit_byte((word >> 8) as u8)?;
+        self.emit_byte(word as u8)
+    }
+
+    // A jump to fill in later, returns its address
+    fn emit_jump_placeholder(&mut self) -> Result<usize, String> {
+        let address = self.here;
+        self.emit(0x1000)?;
+        Ok(address)
+    }
+
+    fn patch_jump(&mut self, address: usize, target: usize) {
+        let i = address - DATA;
+        self.rom[i] = 0x10 | ((target >> 8) & 0xF) as u8;
+        self.rom[i + 1] = target as u8;
+    }
+
+    fn statement(&mut self, token: &str) -> Result<(), String> {
+        match token {
+            ":" => {
+                let name = self.next()?;
+                self.define(&name)?;
+                self.labels.push((name, self.here as u16));
+            }
+            ":const" => {
+                let name = self.next()?;
+                self.define(&name)?;
+                let token = self.next()?;
+                let value = match self.constant(&token).or_else(|| self.label(&token).map(|a| a as f64)) {
+                    Some(x) => x,
+                    None => return Err(format!("Expected a number, found {}", token)),
+                };
+                self.constants.insert(name, value);
+            }
+            ":alias" => {
+                let name = self.next()?;
+                if self.constants.contains_key(&name) || self.label(&name).is_some() {
+                    return Err(format!("{} is already defined", name));
+                }
+                let register = self.expect_register()?;
+                self.aliases.insert(name, register);
+            }
+            ":macro" => {
+                let name = self.next()?;
+                self.define(&name)?;
+                let mut args = Vec::new();
+                loop {
+                    let token = self.next()?;
+                    if token == "{" {
+                        break;
+                    }
+                    args.push(token);
+                }
+                let body = self.block()?;
+                self.macros.insert(name, Macro { args, body });
+            }
+            ":calc" => {
+                let name = self.next()?;
+                if self.label(&name).is_some() || self.macros.contains_key(&name) {
+                    return Err(format!("{} is already defined", name));
+                }
+                self.expect("{")?;
+                let value = self.calc_block()?;
+                self.constants.insert(name, value);
+            }
+            ":org" => {
+                let token = self.next()?;
+                let address = match token.as_str() {
+                    "{" => self.calc_block()?,
+                    _ => self
+                        .constant(&token)
+                        .ok_or(format!("Expected an address, found {}", token))?,
+                } as usize;
+                if !(DATA..MEMORY_SIZE).contains(&address) {
+                    return Err(format!(":org {:#x} is outside the program area", address));
+                }
+                self.here = address;
+            }
+            ":byte" => {
+                let value = match self.tokens.front().map(|t| t.text == "{") {
+                    Some(true) => {
+                        self.next()?;
+                        match self.calc_block()? as i64 {
+                            value @ -128..=255 => value as u8,
+                            value => return Err(format!("{} does not fit in a byte", value)),
+                        }
+                    }
+                    _ => self.byte_value()?,
+                };
+                self.emit_byte(value)?;
+            }
+            ":call" => self.emit_address(0x2000)?,
+            ":unpack" => {
+                let nibble = self.nibble_value()?;
+                let name = self.next()?;
+                let address = match self.constant(&name).or_else(|| self.label(&name).map(|a| a as f64)) {
+                    Some(x) => x as usize,
+                    None => {
+                        for (offset, patch) in [(0, Patch::UnpackHigh(nibble)), (2, Patch::UnpackLow)].iter() {
+                            self.fixups.push(Fixup {
+                                address: self.here + offset,
+                                name: name.clone(),
+                                patch: *patch,
+                                line: self.line,
+                            });
+                        }
+                        0
+                    }
+                };
+                self.emit(0x6000 | (nibble as u16) << 4 | ((address >> 8) & 0xF) as u16)?;
+                self.emit(0x6100 | (address & 0xFF) as u16)?;
+            }
+            ":breakpoint" | ":monitor" => {
+                self.next()?;
+            }
+            "return" | ";" => self.emit(0x00EE)?,
+            "clear" => self.emit(0x00E0)?,
+            "bcd" => {
+                let x = self.expect_register()?;
+                self.emit(0xF033 | (x as u16) << 8)?;
+            }
+            "save" | "load" => {
+                let x = self.expect_register()?;
+                let op = if token == "save" { 0xF055 } else { 0xF065 };
+                self.emit(op | (x as u16) << 8)?;
+            }
+            "sprite" => {
+                let x = self.expect_register()?;
+                let y = self.expect_register()?;
+                let n = self.nibble_value()?;
+                self.emit(0xD000 | (x as u16) << 8 | (y as u16) << 4 | n as u16)?;
+            }
+            "jump" => self.emit_address(0x1000)?,
+            "jump0" => self.emit_address(0xB000)?,
+            "native" => self.emit_address(0x0000)?,
+            "delay" | "buzzer" => {
+                self.expect(":=")?;
+                let x = self.expect_register()?;
+                let op = if token == "delay" { 0xF015 } else { 0xF018 };
+                self.emit(op | (x as u16) << 8)?;
+            }
+            "i" => {
+                let op = self.next()?;
+                match op.as_str() {
+                    ":=" => match self.tokens.front().map(|t| t.text.as_str()) {
+                        Some("hex") => {
+                            self.next()?;
+                            let x = self.expect_register()?;
+                            self.emit(0xF029 | (x as u16) << 8)?;
+                        }
+                        _ => self.emit_address(0xA000)?,
+                    },
+                    "+=" => {
+                        let x = self.expect_register()?;
+                        self.emit(0xF01E | (x as u16) << 8)?;
+                    }
+                    _ => return Err(format!("Unknown i operation: {}", op)),
+                }
+            }
+            "loop" => self.flow.push(Flow::Loop {
+                start: self.here,
+                exits: Vec::new(),
+            }),
+            "while" => {
+                let (skip_if_true, _) = self.condition()?;
+                self.emit(skip_if_true)?;
+                let jump = self.emit_jump_placeholder()?;
+                match self.flow.iter_mut().rev().find(|f| matches!(f, Flow::Loop { .. })) {
+                    Some(Flow::Loop { exits, .. }) => exits.push(jump),
+                    _ => return Err("while outside a loop".to_string()),
+                }
+            }
+            "again" => match self.flow.pop() {
+                Some(Flow::Loop { start, exits }) => {
+                    self.emit(0x1000 | start as u16)?;
+                    for exit in exits {
+                        self.patch_jump(exit, self.here);
+                    }
+                }
+                _ => return Err("again without loop".to_string()),
+            },
+            "if" => {
+                let (skip_if_true, skip_if_false) = self.condition()?;
+                let keyword = self.next()?;
+                match keyword.as_str() {
+                    "then" => self.emit(skip_if_false)?,
+                    "begin" => {
+                        self.emit(skip_if_true)?;
+                        let jump = self.emit_jump_placeholder()?;
+                        self.flow.push(Flow::Begin(jump));
+                    }
+                    _ => return Err(format!("Expected then or begin, found {}", keyword)),
+                }
+            }
+            "else" => match self.flow.pop() {
+                Some(Flow::Begin(jump)) => {
+                    let skip_else = self.emit_jump_placeholder()?;
+                    self.patch_jump(jump, self.here);
+                    self.flow.push(Flow::Else(skip_else));
+                }
+                _ => return Err("else without begin".to_string()),
+            },
+            "end" => match self.flow.pop() {
+                Some(Flow::Begin(jump)) | Some(Flow::Else(jump)) => self.patch_jump(jump, self.here),
+                _ => return Err("end without begin".to_string()),
+            },
+            _ => return self.other(token),
+        }
+        Ok(())
+    }
+
+    // Register operations, macros, calls and data
+    fn other(&mut self, token: &str) -> Result<(), String> {
+        if let Some(x) = self.register(token) {
+            return self.register_op(x);
+        }
+        if self.macros.contains_key(token) {
+            return self.expand(token);
+        }
+        if let Some(value) = self.constant(token) {
+            return match value as i64 {
+                v @ -128..=255 => self.emit_byte(v as u8),
+                v => Err(format!("{} does not fit in a byte", v)),
+            };
+        }
+        if token.starts_with(':') || token.starts_with('{') || token.starts_with('}') {
+            return Err(format!("Unknown directive: {}", token));
+        }
+        // a call, maybe to a label further down
+        self.tokens.push_front(Token {
+            text: token.to_string(),
+            line: self.line,
+        });
+        self.emit_address(0x2000)
+    }
+
+    fn register_op(&mut self, x: u8) -> Result<(), String> {
+        let x = (x as u16) << 8;
+        let op = self.next()?;
+        let rhs = self.next()?;
+        if let Some(y) = self.register(&rhs) {
+            let y = (y as u16) << 4;
+            let code = match op.as_str() {
+                ":=" => 0x8000,
+                "|=" => 0x8001,
+                "&=" => 0x8002,
+                "^=" => 0x8003,
+                "+=" => 0x8004,
+                "-=" => 0x8005,
+                ">>=" => 0x8006,
+                "=-" => 0x8007,
+                "<<=" => 0x800E,
+                _ => return Err(format!("Unknown register operation: {}", op)),
+            };
+            return self.emit(code | x | y);
+        }
+        match (op.as_str(), rhs.as_str()) {
+            (":=", "key") => self.emit(0xF00A | x),
+            (":=", "delay") => self.emit(0xF007 | x),
+            (":=", "random") => {
+                let nn = self.byte_value()?;
+                self.emit(0xC000 | x | nn as u16)
+            }
+            (":=", _) | ("+=", _) | ("-=", _) => {
+                self.tokens.push_front(Token {
+                    text: rhs,
+                    line: self.line,
+                });
+                let nn = self.byte_value()?;
+                match op.as_str() {
+                    ":=" => self.emit(0x6000 | x | nn as u16),
+                    "+=" => self.emit(0x7000 | x | nn as u16),
+                    _ => self.emit(0x7000 | x | nn.wrapping_neg() as u16),
+                }
+            }
+            _ => Err(format!("Unknown register operation: {} {}", op, rhs)),
+        }
+    }
+
+    // The instructions that skip the next one when the condition is true,
+    // and when it is false
+    fn condition(&mut self) -> Result<(u16, u16), String> {
+        let x = (self.expect_register()? as u16) << 8;
+        let op = self.next()?;
+        match op.as_str() {
+            "key" => return Ok((0xE09E | x, 0xE0A1 | x)),
+            "-key" => return Ok((0xE0A1 | x, 0xE09E | x)),
+            "==" | "!=" | "<" | ">" | "<=" | ">=" => {}
+            _ => return Err(format!("Unknown condition: {}", op)),
+        }
+        let rhs = self.next()?;
+        let (equal, not_equal) = match self.register(&rhs) {
+            Some(y) => (0x5000 | x | (y as u16) << 4, 0x9000 | x | (y as u16) << 4),
+            None => {
+                self.tokens.push_front(Token {
+                    text: rhs,
+                    line: self.line,
+                });
+                let nn = self.byte_value()? as u16;
+                (0x3000 | x | nn, 0x4000 | x | nn)
+            }
+        };
+        match op.as_str() {
+            "==" => Ok((equal, not_equal)),
+            "!=" => Ok((not_equal, equal)),
+            _ => self.compare(x, &op, equal),
+        }
+    }
+
+    // Like Octo: vf := the right hand side, subtract so the no borrow flag in
+    // vf answers the comparison, then test vf
+    fn compare(&mut self, x: u16, op: &str, equal: u16) -> Result<(u16, u16), String> {
+        if x == 0xF00 {
+            return Err(format!("vf is overwritten by {}, compare another register", op));
+        }
+        // vf := vy or vf := nn
+        let load = match equal & 0xF000 {
+            0x5000 => 0x8F00 | (equal & 0x00F0),
+            _ => 0x6F00 | (equal & 0x00FF),
+        };
+        self.emit(load)?;
+        let vx = x >> 4;
+        let flag = match op {
+            // vf -= vx, vf is 1 when rhs >= vx
+            ">" | "<=" => {
+                self.emit(0x8F05 | vx)?;
+                if op == "<=" { 1 } else { 0 }
+            }
+            // vf =- vx, vf is 1 when vx >= rhs
+            _ => {
+                self.emit(0x8F07 | vx)?;
+                if op == ">=" { 1 } else { 0 }
+            }
+        };
+        Ok((0x3F00 | flag, 0x4F00 | flag))
+    }
+
+    // Tokens up to the matching }
+    fn block(&mut self) -> Result<Vec<Token>, String> {
+        let mut depth = 1;
+        let mut body = Vec::new();
+        loop {
+            let token = match self.tokens.pop_front() {
+                Some(x) => x,
+                None => return Err("Missing }".to_string()),
+            };
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                return Ok(body);
+            }
+            body.push(token);
+        }
+    }
+
+    fn expand(&mut self, name: &str) -> Result<(), String> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(format!("Macro {} expands forever", name));
+        }
+        let count = self.macros[name].args.len();
+        let mut values = Vec::new();
+        for _ in 0..count {
+            values.push(self.next()?);
+        }
+        let line = self.line;
+        let definition = &self.macros[name];
+        let body: Vec<Token> = definition
+            .body
+            .iter()
+            .map(|t| Token {
+                text: match definition.args.iter().position(|a| *a == t.text) {
+                    Some(i) => values[i].clone(),
+                    None => t.text.clone(),
+                },
+                line,
+            })
+            .collect();
+        for token in body.into_iter().rev() {
+            self.tokens.push_front(token);
+        }
+        Ok(())
+    }
+
+    fn calc_block(&mut self) -> Result<f64, String> {
+        let tokens: Vec<String> = self.block()?.into_iter().map(|t| t.text).collect();
+        let mut pos = 0;
+        let value = self.calc_expr(&tokens, &mut pos)?;
+        match tokens.get(pos) {
+            None => Ok(value),
+            Some(token) => Err(format!("Unexpected {} in :calc", token)),
+        }
+    }
+
+    // Right to left: a - b - c is a - (b - c)
+    fn calc_expr(&self, tokens: &[String], pos: &mut usize) -> Result<f64, String> {
+        let left = self.calc_term(tokens, pos)?;
+        let op = match tokens.get(*pos) {
+            Some(x) if x != ")" => x.clone(),
+            _ => return Ok(left),
+        };
+        *pos += 1;
+        let right = self.calc_expr(tokens, pos)?;
+        let (a, b) = (left as i64, right as i64);
+        Ok(match op.as_str() {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" if right == 0.0 => return Err("Division by zero in :calc".to_string()),
+            "/" => left / right,
+            "%" if b == 0 => return Err("Division by zero in :calc".to_string()),
+            "%" => (a % b) as f64,
+            "&" => (a & b) as f64,
+            "|" => (a | b) as f64,
+            "^" => (a ^ b) as f64,
+            "<<" => (a << (b & 63)) as f64,
+            ">>" => (a >> (b & 63)) as f64,
+            "pow" => left.powf(right),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "<" => (left < right) as i64 as f64,
+            ">" => (left > right) as i64 as f64,
+            "<=" => (left <= right) as i64 as f64,
+            ">=" => (left >= right) as i64 as f64,
+            "==" => (left == right) as i64 as f64,
+            "!=" => (left != right) as i64 as f64,
+            _ => return Err(format!("Unknown operator in :calc: {}", op)),
+        })
+    }
+
+    fn calc_term(&self, tokens: &[String], pos: &mut usize) -> Result<f64, String> {
+        let token = match tokens.get(*pos) {
+            Some(x) => x.as_str(),
+            None => return Err("Missing value in :calc".to_string()),
+        };
+        *pos += 1;
+        let unary = |f: fn(f64) -> f64, pos: &mut usize| self.calc_term(tokens, pos).map(f);
+        match token {
+            "(" => {
+                let value = self.calc_expr(tokens, pos)?;
+                match tokens.get(*pos).map(|t| t.as_str()) {
+                    Some(")") => {
+                        *pos += 1;
+                        Ok(value)
+                    }
+                    _ => Err("Missing ) in :calc".to_string()),
+                }
+            }
+            "-" => unary(|x| -x, pos),
+            "~" => unary(|x| !(x as i64) as f64, pos),
+            "!" => unary(|x| (x == 0.0) as i64 as f64, pos),
+            "abs" => unary(f64::abs, pos),
+            "sqrt" => unary(f64::sqrt, pos),
+            "floor" => unary(f64::floor, pos),
+            "ceil" => unary(f64::ceil, pos),
+            "sin" => unary(f64::sin, pos),
+            "cos" => unary(f64::cos, pos),
+            "HERE" => Ok(self.here as f64),
+            name => self
+                .constant(name)
+                .or_else(|| self.label(name).map(|a| a as f64))
+                .ok_or(format!("Unknown name in :calc: {}", name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Chip8;
+
+    #[test]
+    fn instructions_test() {
+        let source = ": main\n  clear\n  v0 := 5 v1 += v0 v2 -= 1\n  i := sprite\n  sprite v0 v1 3\n  jump main\n: sprite 0x80 0x80 0x80\n";
+        let compiled = compile(source, "test.8o").unwrap();
+        let expected = [
+            0x12, 0x02, 0x00, 0xE0, 0x60, 0x05, 0x81, 0x04, 0x72, 0xFF, 0xA2, 0x10, 0xD0, 0x13, 0x12,
+            0x02, 0x80, 0x80, 0x80,
+        ];
+        assert_eq!(expected.to_vec(), compiled.bytes);
+        assert_eq!(Some(0x210), compiled.symbols.address_of("sprite"));
+        let line = compiled.symbols.line_at(0x206).unwrap();
+        assert_eq!(("test.8o", 3), (line.file.as_str(), line.line));
+    }
+
+    #[test]
+    fn control_flow_test() {
+        let source = ": main\nloop\n  while v0 != 3\n  if v1 key then v0 += 1\n  if v0 == v1 begin clear else return end\nagain\n";
+        let bytes = compile(source, "flow.8o").unwrap().bytes;
+        let words: Vec<u16> = bytes.chunks(2).map(|w| (w[0] as u16) << 8 | w[1] as u16).collect();
+        assert_eq!(
+            vec![
+                0x1202, // jump main
+                0x4003, 0x1216, // while v0 != 3
+                0xE1A1, 0x7001, // if v1 key then v0 += 1
+                0x5010, 0x1212, 0x00E0, 0x1214, 0x00EE, // if begin else end
+                0x1202, // again
+            ],
+            words
+        );
+    }
+
+    #[test]
+    fn comparisons_test() {
+        let source = ": main\n  if v1 < v2 then clear\n  if v1 >= 7 then clear\n  if v3 > v4 begin return end\n  loop while v0 <= 9 again\n";
+        let bytes = compile(source, "cmp.8o").unwrap().bytes;
+        let words: Vec<u16> = bytes.chunks(2).map(|w| (w[0] as u16) << 8 | w[1] as u16).collect();
+        assert_eq!(
+            vec![
+                0x1202, // jump main
+                0x8F20, 0x8F17, 0x4F00, 0x00E0, // vf := v2 vf =- v1, then unless vf == 0
+                0x6F07, 0x8F17, 0x4F01, 0x00E0, // vf := 7 vf =- v1, then unless vf == 1
+                0x8F40, 0x8F35, 0x3F00, 0x121C, 0x00EE, // vf := v4 vf -= v3, begin
+                0x6F09, 0x8F05, 0x3F01, 0x1226, 0x121C, // vf := 9 vf -= v0 each time round
+            ],
+            words
+        );
+    }
+
+    #[test]
+    fn run_comparisons_test() {
+        for (a, op, b, taken) in &[
+            (2, "<", "v2", true),
+            (5, "<", "v2", false),
+            (5, ">", "v2", false),
+            (7, ">", "v2", true),
+            (5, "<=", "v2", true),
+            (6, "<=", "v2", false),
+            (5, ">=", "v2", true),
+            (4, ">=", "5", false),
+            (9, ">", "5", true),
+        ] {
+            let source = format!(": main v1 := {} v2 := 5 v0 := 0 if v1 {} {} then v0 := 1 loop again\n", a, op, b);
+            let mut chip8 = Chip8::new();
+            chip8.load_program_at(&compile(&source, "run.8o").unwrap().bytes, 0x200);
+            for _ in 0..10 {
+                let (b0, b1) = chip8.fetch();
+                chip8.decode_execute(b0, b1);
+            }
+            assert_eq!(*taken as u8, chip8.v[0], "{}", source);
+        }
+    }
+
+    #[test]
+    fn directives_test() {
+        let source = ":const SIZE 3\n:alias x v4\n:macro twice op { op op }\n:calc AREA { SIZE * ( SIZE + 1 ) }\n: main\n  x := AREA\n  twice clear\n  :unpack 0xA data\n:org 0x300\n: data :byte { SIZE - 1 - 1 }\n";
+        let compiled = compile(source, "d.8o").unwrap();
+        assert_eq!(&[0x64, 12, 0x00, 0xE0, 0x00, 0xE0, 0x60, 0xA3, 0x61, 0x00], &compiled.bytes[2..12]);
+        // right to left: 3 - (1 - 1)
+        assert_eq!(3, compiled.bytes[0x100]);
+    }
+
+    #[test]
+    fn errors_test() {
+        assert_eq!("e.8o:2: Undefined name: nowhere", compile(": main\njump nowhere\n", "e.8o").unwrap_err());
+        assert!(compile("clear\n", "e.8o").is_err());
+        assert!(compile(": main\nloop\n", "e.8o").is_err());
+        assert!(compile(": main\nv0 := 300\n", "e.8o").is_err());
+        assert!(compile(":macro m { m }\n: main m\n", "e.8o").is_err());
+        assert!(compile(": main\n:byte { 200 + 100 }\n", "e.8o").is_err());
+        assert!(compile(": main\nif vf < 3 then clear\n", "e.8o").is_err());
+    }
+}
@@ -0,0 +1,145 @@
+use std::fs;
+use std::path::Path;
+
+// Colors for each pixel value. Plain CHIP-8 only uses 0 (off) and 1 (on),
+// XO-CHIP's two bitplanes give 2 (plane 2 only) and 3 (both planes).
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    pub name: String,
+    pub colors: [Rgb; 4],
+}
+
+const fn rgb(hex: u32) -> Rgb {
+    Rgb {
+        r: (hex >> 16) as u8,
+        g: (hex >> 8) as u8,
+        b: hex as u8,
+    }
+}
+
+pub const BUILTIN_PALETTES: [(&str, [u32; 4]); 6] = [
+    ("mono", [0x000000, 0xFFFFFF, 0xAAAAAA, 0x555555]),
+    ("green", [0x001100, 0x33FF33, 0x1E9E1E, 0x0F5F0F]),
+    ("amber", [0x1A0F00, 0xFFB000, 0xCC8400, 0x664200]),
+    ("lcd", [0x9BBC0F, 0x0F380F, 0x306230, 0x8BAC0F]),
+    ("high-contrast", [0x000000, 0xFFFF00, 0x00FFFF, 0xFF00FF]),
+    ("octo", [0x996600, 0xFFCC00, 0xFF6600, 0x662200]),
+];
+
+impl Palette {
+    pub fn builtin(name: &str) -> Option<Palette> {
+        BUILTIN_PALETTES
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(n, colors)| Palette {
+                name: n.to_string(),
+                colors: [rgb(colors[0]), rgb(colors[1]), rgb(colors[2]), rgb(colors[3])],
+            })
+    }
+
+    pub fn mono() -> Palette {
+        Palette::builtin("mono").unwrap()
+    }
+
+    pub fn color(&self, index: u8) -> Rgb {
+        self.colors[(index & 0x3) as usize]
+    }
+}
+
+// A built in name, colors inline ("#000000 #ffcc00", as the rom database
+// gives them), otherwise a palette file
+pub fn find_palette(name: &str) -> Result<Palette, String> {
+    match Palette::builtin(name) {
+        Some(palette) => Ok(palette),
+        None if name.starts_with('#') => parse_palette("rom", name),
+        None => load_palette(Path::new(name)),
+    }
+}
+
+pub fn load_palette(path: &Path) -> Result<Palette, String> {
+    let text = match fs::read_to_string(path) {
+        Ok(x) => x,
+        Err(e) => return Err(format!("Could not read palette {}: {}", path.display(), e)),
+    };
+    let name = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("custom");
+    parse_palette(name, &text)
+}
+
+// Two to four hex colors (#RRGGBB or RRGGBB), ';' starts a comment.
+// With only two colors the XO-CHIP plane colors reuse the foreground.
+pub fn parse_palette(name: &str, text: &str) -> Result<Palette, String> {
+    let mut colors = Vec::new();
+    for line in text.lines() {
+        let line = line.split(';').next().unwrap_or("");
+        for word in line.split(|c: char| c.is_whitespace() || c == ',') {
+            if word.is_empty() {
+                continue;
+            }
+            match parse_hex_color(word) {
+                Some(color) => colors.push(color),
+                None => return Err(format!("Bad color in palette: {}", word)),
+            }
+        }
+    }
+    if colors.len() < 2 || colors.len() > 4 {
+        return Err(format!("A palette needs 2 to 4 colors, found {}", colors.len()));
+    }
+    while colors.len() < 4 {
+        colors.push(colors[1]);
+    }
+    Ok(Palette {
+        name: name.to_string(),
+        colors: [colors[0], colors[1], colors[2], colors[3]],
+    })
+}
+
+pub fn parse_hex_color(text: &str) -> Option<Rgb> {
+    let hex = text.trim_start_matches('#');
+    if hex.len() != 6 {
+        return None;
+    }
+    u32::from_str_radix(hex, 16).ok().map(rgb)
+}
+
+// ANSI truecolor escapes
+pub fn ansi_fg(color: Rgb) -> String {
+    format!("\x1b[38;2;{};{};{}m", color.r, color.g, color.b)
+}
+
+pub fn ansi_bg(color: Rgb) -> String {
+    format!("\x1b[48;2;{};{};{}m", color.r, color.g, color.b)
+}
+
+pub const ANSI_RESET: &str = "\x1b[0m";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_palette_test() {
+        let amber = Palette::builtin("amber").unwrap();
+        assert_eq!(Rgb { r: 0xFF, g: 0xB0, b: 0x00 }, amber.color(1));
+        assert_eq!(None, Palette::builtin("plaid"));
+    }
+
+    #[test]
+    fn parse_palette_test() {
+        let palette = parse_palette("mine", "; bg fg\n#102030 405060\n").unwrap();
+        assert_eq!(Rgb { r: 0x10, g: 0x20, b: 0x30 }, palette.color(0));
+        assert_eq!(palette.color(1), palette.color(3));
+        assert!(parse_palette("bad", "#102030").is_err());
+        assert!(parse_palette("bad", "#102030 #zz0000").is_err());
+    }
+}
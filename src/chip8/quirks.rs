@@ -0,0 +1,25 @@
+// Behaviours that differ between CHIP-8 interpreters. The defaults keep what
+// this emulator has always done. Names follow the chip-8-database platforms.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quirks {
+    // 8xy6/8xyE shift vx in place, otherwise vx = vy shifted (COSMAC VIP)
+    pub shift: bool,
+    // 8xy1/8xy2/8xy3 reset vf to 0
+    pub logic: bool,
+    // Sprites wrap around the screen edges instead of being clipped
+    pub wrap: bool,
+    // COSMAC VIP: Dxyn waits for vertical blank, so a draw ends the frame's
+    // instruction budget and a rom can draw at most 60 times a second
+    pub vblank: bool,
+}
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks {
+            shift: true,
+            logic: false,
+            wrap: true,
+            vblank: false,
+        }
+    }
+}
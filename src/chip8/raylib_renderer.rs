@@ -1,19 +1,172 @@
+use crate::chip8::filter::{DisplayFilter, FilterKind};
+use crate::chip8::gamepad::{Button, Gamepad, GamepadMap, InputSource, PadState, BUTTONS};
+use crate::chip8::keymap::Keymap;
+use crate::chip8::palette::{Palette, Rgb};
+use crate::chip8::recorder::Recorder;
+use crate::chip8::screenshot::{framebuffer, save_screenshot, DEFAULT_SCALE};
+use crate::chip8::watch::RomWatcher;
+use crate::chip8::Chip8;
+use crate::chip8::COLS;
+use crate::chip8::FRAMES_PER_SECOND;
+use crate::chip8::ROWS;
+use raylib::core::input::key_from_i32;
 use raylib::prelude::*;
+use std::path::PathBuf;
 
-pub fn run() {
+// Screen pixels per chip8 pixel
+const SCALE: i32 = 10;
+
+fn to_color(rgb: Rgb) -> Color {
+    Color::new(rgb.r, rgb.g, rgb.b, 255)
+}
+
+// The first gamepad
+const PAD: GamepadNumber = GamepadNumber::GAMEPAD_PLAYER1;
+
+fn raylib_button(button: Button) -> GamepadButton {
+    match button {
+        Button::Up => GamepadButton::GAMEPAD_BUTTON_LEFT_FACE_UP,
+        Button::Down => GamepadButton::GAMEPAD_BUTTON_LEFT_FACE_DOWN,
+        Button::Left => GamepadButton::GAMEPAD_BUTTON_LEFT_FACE_LEFT,
+        Button::Right => GamepadButton::GAMEPAD_BUTTON_LEFT_FACE_RIGHT,
+        Button::A => GamepadButton::GAMEPAD_BUTTON_RIGHT_FACE_DOWN,
+        Button::B => GamepadButton::GAMEPAD_BUTTON_RIGHT_FACE_RIGHT,
+        Button::X => GamepadButton::GAMEPAD_BUTTON_RIGHT_FACE_LEFT,
+        Button::Y => GamepadButton::GAMEPAD_BUTTON_RIGHT_FACE_UP,
+        Button::L1 => GamepadButton::GAMEPAD_BUTTON_LEFT_TRIGGER_1,
+        Button::R1 => GamepadButton::GAMEPAD_BUTTON_RIGHT_TRIGGER_1,
+        Button::Select => GamepadButton::GAMEPAD_BUTTON_MIDDLE_LEFT,
+        Button::Start => GamepadButton::GAMEPAD_BUTTON_MIDDLE_RIGHT,
+    }
+}
+
+struct RaylibPad<'a> {
+    rl: &'a RaylibHandle,
+}
+
+impl<'a> InputSource for RaylibPad<'a> {
+    fn poll(&mut self) -> Option<PadState> {
+        if !self.rl.is_gamepad_available(PAD) {
+            return None;
+        }
+        let mut state = PadState {
+            stick_x: self.rl.get_gamepad_axis_movement(PAD, GamepadAxis::GAMEPAD_AXIS_LEFT_X),
+            stick_y: self.rl.get_gamepad_axis_movement(PAD, GamepadAxis::GAMEPAD_AXIS_LEFT_Y),
+            ..PadState::default()
+        };
+        for (_, button) in BUTTONS.iter() {
+            if self.rl.is_gamepad_button_down(PAD, raylib_button(*button)) {
+                state = state.with(*button);
+            }
+        }
+        Some(state)
+    }
+}
+
+// Shade between the background and foreground for filtered pixels
+fn mix(background: Rgb, foreground: Rgb, amount: f32) -> Color {
+    let channel = |b: u8, f: u8| (b as f32 + (f as f32 - b as f32) * amount) as u8;
+    Color::new(
+        channel(background.r, foreground.r),
+        channel(background.g, foreground.g),
+        channel(background.b, foreground.b),
+        255,
+    )
+}
+
+pub fn run(
+    mut chip8: Chip8,
+    palette: &Palette,
+    filter: Option<FilterKind>,
+    keymap: &Keymap,
+    gamepad: GamepadMap,
+    mut recorder: Option<&mut Recorder>,
+    mut watcher: Option<&mut RomWatcher>,
+) {
     let (mut rl, thread) = raylib::init()
-        .size(640, 480)
+        .size(COLS as i32 * SCALE, ROWS as i32 * SCALE)
         .title("CHIP-8 EMULATOR")
         .build();
+    rl.set_target_fps(FRAMES_PER_SECOND);
+
+    let background = to_color(palette.color(0));
+    let foreground = to_color(palette.color(1));
 
+    let mut filter = filter.map(DisplayFilter::new);
+    let mut gamepad = Gamepad::new(gamepad);
+    // raylib key codes are the ascii codes of the upper case keys
+    let keys: Vec<(u8, KeyboardKey)> = keymap
+        .bindings()
+        .into_iter()
+        .filter_map(|(hex, host)| key_from_i32(host.to_ascii_uppercase() as i32).map(|k| (hex, k)))
+        .collect();
+    let mut frame: u32 = 0;
     while !rl.window_should_close() {
-        let coord = rl.get_mouse_position();
+        // F12 saves the display as chip8-<frame>.png
+        if rl.is_key_pressed(KeyboardKey::KEY_F12) {
+            let path = PathBuf::from(format!("chip8-{}.png", frame));
+            match save_screenshot(&path, &chip8, DEFAULT_SCALE, palette) {
+                Ok(()) => println!("Saved screenshot {}", path.display()),
+                Err(e) => println!("{}", e),
+            }
+        }
+        frame += 1;
+        for (hex, key) in &keys {
+            if rl.is_key_pressed(*key) {
+                chip8.press_key(*hex);
+            }
+            if rl.is_key_released(*key) {
+                chip8.release_key(*hex);
+            }
+        }
+        gamepad.update(&mut RaylibPad { rl: &rl }, &mut chip8);
+        if let Some(watcher) = watcher.as_mut() {
+            match watcher.poll(&mut chip8) {
+                Some(Ok(rom)) => println!("Reloaded {}", rom.name),
+                Some(Err(e)) => println!("{}", e),
+                None => {}
+            }
+        }
+        while !chip8.frame_over() {
+            let (b0, b1) = chip8.fetch();
+            chip8.decode_execute(b0, b1);
+        }
+        chip8.end_frame();
+        if let Some(recorder) = recorder.as_mut() {
+            if let Err(e) = recorder.frame(&chip8) {
+                println!("{}", e);
+            }
+        }
+
         let mut d = rl.begin_drawing(&thread);
-        d.clear_background(Color::WHITE);
-        d.draw_rectangle(0, 0, 640, 50, Color::GREEN);
-        d.draw_circle(coord.x as i32, coord.y as i32, 50.0, Color::BLUE);
-        d.draw_text(
-            format!("Hello {}, {}", coord.x, coord.y).as_str(),
-             12, 12, 20, Color::BLACK);
+        d.clear_background(background);
+        match filter.as_mut() {
+            Some(filter) => {
+                let glow = filter.apply(&framebuffer(&chip8));
+                for y in 0..ROWS {
+                    for x in 0..COLS {
+                        let amount = glow[y * COLS + x];
+                        if amount > 0.0 {
+                            let color = mix(palette.color(0), palette.color(1), amount);
+                            d.draw_rectangle(x as i32 * SCALE, y as i32 * SCALE, SCALE, SCALE, color);
+                        }
+                    }
+                }
+            }
+            None => {
+                for y in 0..ROWS {
+                    for x in 0..COLS {
+                        if chip8.pixel(x, y) {
+                            d.draw_rectangle(x as i32 * SCALE, y as i32 * SCALE, SCALE, SCALE, foreground);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    if let Some(recorder) = recorder {
+        if let Err(e) = recorder.finish() {
+            println!("{}", e);
+        }
     }
 }
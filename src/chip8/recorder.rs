@@ -0,0 +1,178 @@
+use crate::chip8::palette::Palette;
+use crate::chip8::screenshot::{framebuffer, pbm_text, scaled};
+use crate::chip8::Chip8;
+use crate::chip8::COLS;
+use crate::chip8::FRAMES_PER_SECOND;
+use crate::chip8::ROWS;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+// Records one display frame per emulated frame (60 fps). The format comes
+// from the file extension:
+//   .gif  animated gif in the palette colors
+//   .y4m  uncompressed greyscale video, e.g. ffmpeg -i out.y4m out.mp4
+//   .pbm  numbered plain PBM files, out.pbm becomes out-00000.pbm, out-00001.pbm ...
+//
+// With dedupe a frame identical to the one before is not written again. The
+// gif shows the previous frame for longer instead, pbm files keep their frame
+// number so the timing can be rebuilt. y4m has a fixed rate so it ignores dedupe.
+
+enum Output {
+    Gif(gif::Encoder<BufWriter<File>>),
+    Y4m(BufWriter<File>),
+    Pbm(PathBuf),
+}
+
+pub struct Recorder {
+    output: Output,
+    scale: usize,
+    palette: Palette,
+    dedupe: bool,
+    // frames seen so far
+    frame: u32,
+    // frame waiting to be written, the frame it started on and how many frames it lasts
+    pending: Option<(Vec<u8>, u32, u32)>,
+}
+
+// gif delays are in hundredths of a second. Rounding the running time keeps
+// 60 fps on average with delays of 1 and 2.
+pub fn gif_delay(start: u32, frames: u32) -> u16 {
+    let at = |frame: u32| (frame as u64 * 100 + FRAMES_PER_SECOND as u64 / 2) / FRAMES_PER_SECOND as u64;
+    (at(start + frames) - at(start)) as u16
+}
+
+// YUV4MPEG2 header for greyscale frames
+pub fn y4m_header(width: usize, height: usize) -> String {
+    format!("YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 Cmono\n", width, height, FRAMES_PER_SECOND)
+}
+
+fn luma(palette: &Palette, index: u8) -> u8 {
+    let c = palette.color(index);
+    ((c.r as u32 * 299 + c.g as u32 * 587 + c.b as u32 * 114) / 1000) as u8
+}
+
+impl Recorder {
+    pub fn create(path: &Path, scale: usize, palette: Palette, dedupe: bool) -> Result<Recorder, String> {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("")
+            .to_lowercase();
+        let width = COLS * scale;
+        let height = ROWS * scale;
+        let create = || File::create(path).map_err(|e| format!("Could not create {}: {}", path.display(), e));
+        let output = match extension.as_str() {
+            "gif" => {
+                let mut colors = Vec::new();
+                for index in 0..4 {
+                    let c = palette.color(index);
+                    colors.extend_from_slice(&[c.r, c.g, c.b]);
+                }
+                let mut encoder = gif::Encoder::new(BufWriter::new(create()?), width as u16, height as u16, &colors)
+                    .map_err(|e| e.to_string())?;
+                encoder.set_repeat(gif::Repeat::Infinite).map_err(|e| e.to_string())?;
+                Output::Gif(encoder)
+            }
+            "y4m" => {
+                let mut file = BufWriter::new(create()?);
+                file.write_all(y4m_header(width, height).as_bytes())
+                    .map_err(|e| e.to_string())?;
+                Output::Y4m(file)
+            }
+            "pbm" => Output::Pbm(path.to_path_buf()),
+            _ => return Err(format!("Unknown recording format: {}", path.display())),
+        };
+        Ok(Recorder {
+            output,
+            scale,
+            palette,
+            dedupe,
+            frame: 0,
+            pending: None,
+        })
+    }
+
+    // Call once at the end of every emulated frame
+    pub fn frame(&mut self, chip8: &Chip8) -> Result<(), String> {
+        let pixels = framebuffer(chip8);
+        let frame = self.frame;
+        self.frame += 1;
+        if let Some((last, _, count)) = &mut self.pending {
+            if self.dedupe && *last == pixels {
+                *count += 1;
+                return Ok(());
+            }
+        }
+        self.flush()?;
+        self.pending = Some((pixels, frame, 1));
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> Result<(), String> {
+        self.flush()?;
+        match &mut self.output {
+            Output::Gif(_) | Output::Pbm(_) => Ok(()),
+            Output::Y4m(file) => file.flush().map_err(|e| e.to_string()),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), String> {
+        let (pixels, start, count) = match self.pending.take() {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+        let width = COLS * self.scale;
+        let height = ROWS * self.scale;
+        let palette = &self.palette;
+        match &mut self.output {
+            Output::Gif(encoder) => {
+                let frame = gif::Frame {
+                    width: width as u16,
+                    height: height as u16,
+                    buffer: scaled(&pixels, COLS, ROWS, self.scale).into(),
+                    delay: gif_delay(start, count),
+                    ..gif::Frame::default()
+                };
+                encoder.write_frame(&frame).map_err(|e| e.to_string())
+            }
+            Output::Y4m(file) => {
+                let data: Vec<u8> = scaled(&pixels, COLS, ROWS, self.scale)
+                    .iter()
+                    .map(|p| luma(palette, *p))
+                    .collect();
+                // a fixed frame rate, so repeat deduped frames anyway
+                for _ in 0..count {
+                    file.write_all(b"FRAME\n").map_err(|e| e.to_string())?;
+                    file.write_all(&data).map_err(|e| e.to_string())?;
+                }
+                Ok(())
+            }
+            Output::Pbm(path) => {
+                let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("frame");
+                let name = path.with_file_name(format!("{}-{:05}.pbm", stem, start));
+                fs::write(&name, pbm_text(&pixels, COLS, ROWS, self.scale))
+                    .map_err(|e| format!("Could not write {}: {}", name.display(), e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gif_delay_test() {
+        // 60 frames add up to exactly one second
+        let total: u32 = (0..60).map(|n| gif_delay(n, 1) as u32).sum();
+        assert_eq!(100, total);
+        assert_eq!(100, gif_delay(0, 60));
+        assert_eq!(50, gif_delay(60, 30));
+    }
+
+    #[test]
+    fn y4m_header_test() {
+        assert_eq!("YUV4MPEG2 W64 H32 F60:1 Ip A1:1 Cmono\n", y4m_header(64, 32));
+    }
+}
@@ -0,0 +1,370 @@
+use crate::chip8::cartridge::{read_cartridge, CartridgeProgram};
+use crate::chip8::memory_map::MemoryMap;
+use crate::chip8::octo;
+use crate::chip8::symbols::Symbols;
+use crate::chip8::{DATA, MEMORY_SIZE};
+use std::fs;
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+// Reads a rom and checks it fits in memory. Besides plain binaries it takes
+//   .zip       the rom inside, --rom-entry picks one when there are several
+//   .hex/.ihx  Intel HEX, the records say where each byte goes
+//   .txt       hex digits as text, e.g. "00E0 A22A 600C", ';' comments
+//   .gif       Octo cartridges, their options become config settings
+//   .8o        Octo source, compiled with its symbols
+// The load address defaults to the machine's program start, 0x200 or 0x600
+// on the ETI-660.
+
+// Rom file extensions looked for in a zip
+const ROM_EXTENSIONS: [&str; 10] = ["ch8", "c8", "sc8", "xo8", "rom", "hex", "ihx", "txt", "gif", "8o"];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rom {
+    // the file, or archive:entry
+    pub name: String,
+    pub bytes: Vec<u8>,
+    // where the bytes go, and where the pc starts
+    pub address: usize,
+    // config settings that came with the rom, from cartridge options
+    pub settings: Vec<(&'static str, toml::Value)>,
+    // labels and lines of a compiled rom
+    pub symbols: Option<Symbols>,
+}
+
+#[derive(Clone, Debug)]
+pub struct RomLoader {
+    pub address: usize,
+    pub memory_size: usize,
+    // which file of a zip to load
+    pub entry: Option<String>,
+}
+
+impl RomLoader {
+    pub fn new() -> RomLoader {
+        RomLoader {
+            address: DATA,
+            memory_size: MEMORY_SIZE,
+            entry: None,
+        }
+    }
+
+    // Programs end where the machine keeps its stack and display
+    pub fn for_machine(map: &MemoryMap) -> RomLoader {
+        RomLoader {
+            address: map.program,
+            memory_size: map.callstack.min(map.display).min(MEMORY_SIZE),
+            ..RomLoader::new()
+        }
+    }
+
+    pub fn load(&self, path: &Path) -> Result<Rom, String> {
+        let bytes = match fs::read(path) {
+            Ok(x) => x,
+            Err(e) => return Err(format!("Could not read file from path: {}: {}", path.display(), e)),
+        };
+        self.load_bytes(&path.display().to_string(), &bytes)
+    }
+
+    pub fn load_bytes(&self, name: &str, bytes: &[u8]) -> Result<Rom, String> {
+        let rom = match extension(name).as_str() {
+            "zip" => self.load_zip(name, bytes)?,
+            _ if bytes.starts_with(b"PK\x03\x04") => self.load_zip(name, bytes)?,
+            _ => self.load_file(name, bytes)?,
+        };
+        self.validate(&rom)?;
+        Ok(rom)
+    }
+
+    // One file, by the extension
+    fn load_file(&self, name: &str, bytes: &[u8]) -> Result<Rom, String> {
+        let text = || String::from_utf8_lossy(bytes).to_string();
+        match extension(name).as_str() {
+            "gif" => return self.load_cartridge(name, bytes),
+            "8o" => return self.compile(name, &text(), Vec::new()),
+            _ => {}
+        }
+        let (bytes, address) = match extension(name).as_str() {
+            "hex" | "ihx" if text().trim_start().starts_with(':') => parse_intel_hex(&text())?,
+            "hex" | "txt" => (parse_hex_text(&text())?, self.address),
+            _ => (bytes.to_vec(), self.address),
+        };
+        Ok(Rom {
+            name: name.to_string(),
+            bytes,
+            address,
+            settings: Vec::new(),
+            symbols: None,
+        })
+    }
+
+    fn compile(&self, name: &str, source: &str, settings: Vec<(&'static str, toml::Value)>) -> Result<Rom, String> {
+        if self.address != DATA {
+            return Err(format!("{} is Octo source, which always loads at {:#05x}", name, DATA));
+        }
+        let file = Path::new(name).file_name().and_then(|n| n.to_str()).unwrap_or(name);
+        let compiled = octo::compile(source, file)?;
+        Ok(Rom {
+            name: name.to_string(),
+            bytes: compiled.bytes,
+            address: DATA,
+            settings,
+            symbols: Some(compiled.symbols),
+        })
+    }
+
+    fn load_cartridge(&self, name: &str, bytes: &[u8]) -> Result<Rom, String> {
+        let cartridge = read_cartridge(bytes).map_err(|e| format!("{}: {}", name, e))?;
+        let bytes = match &cartridge.program {
+            CartridgeProgram::Bytes(x) => x.clone(),
+            CartridgeProgram::Source(source) => return self.compile(name, source, cartridge.settings()),
+        };
+        Ok(Rom {
+            name: name.to_string(),
+            bytes,
+            address: self.address,
+            settings: cartridge.settings(),
+            symbols: None,
+        })
+    }
+
+    fn load_zip(&self, name: &str, bytes: &[u8]) -> Result<Rom, String> {
+        let mut archive = zip::ZipArchive::new(Cursor::new(bytes))
+            .map_err(|e| format!("Bad zip {}: {}", name, e))?;
+        let mut entries = Vec::new();
+        for i in 0..archive.len() {
+            if let Ok(file) = archive.by_index(i) {
+                if !file.is_dir() {
+                    entries.push(file.name().to_string());
+                }
+            }
+        }
+        let entry = choose_entry(&entries, self.entry.as_deref())
+            .map_err(|e| format!("{} in {}", e, name))?;
+        let mut file = archive
+            .by_name(&entry)
+            .map_err(|e| format!("Could not read {} from {}: {}", entry, name, e))?;
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)
+            .map_err(|e| format!("Could not read {} from {}: {}", entry, name, e))?;
+        self.load_file(&format!("{}:{}", name, entry), &contents)
+    }
+
+    fn validate(&self, rom: &Rom) -> Result<(), String> {
+        if rom.bytes.is_empty() {
+            return Err(format!("{} is empty", rom.name));
+        }
+        if rom.address < DATA {
+            return Err(format!(
+                "{} loads at {:#05x}, below the program area at {:#05x}",
+                rom.name, rom.address, DATA
+            ));
+        }
+        let room = self.memory_size.saturating_sub(rom.address);
+        if rom.bytes.len() > room {
+            return Err(format!(
+                "{} is {} bytes, only {} fit in memory from {:#05x}",
+                rom.name,
+                rom.bytes.len(),
+                room,
+                rom.address
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn extension(name: &str) -> String {
+    Path::new(name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase()
+}
+
+// The asked for entry (by path or file name), the only file, or the only rom
+fn choose_entry(entries: &[String], wanted: Option<&str>) -> Result<String, String> {
+    if let Some(wanted) = wanted {
+        let file_name = |e: &String| Path::new(e).file_name().and_then(|n| n.to_str()) == Some(wanted);
+        return match entries.iter().find(|e| *e == wanted || file_name(e)) {
+            Some(x) => Ok(x.clone()),
+            None => Err(format!("No {} (found {})", wanted, entries.join(", "))),
+        };
+    }
+    if entries.len() == 1 {
+        return Ok(entries[0].clone());
+    }
+    let roms: Vec<&String> = entries
+        .iter()
+        .filter(|e| ROM_EXTENSIONS.contains(&extension(e).as_str()))
+        .collect();
+    match roms.len() {
+        0 => Err("No rom".to_string()),
+        1 => Ok(roms[0].clone()),
+        _ => Err(format!(
+            "Several roms, pick one with --rom-entry: {}",
+            entries.join(", ")
+        )),
+    }
+}
+
+// Intel HEX records, returns the bytes from the lowest address written and
+// that address. Gaps are zero filled.
+pub fn parse_intel_hex(text: &str) -> Result<(Vec<u8>, usize), String> {
+    let mut data: Vec<(usize, u8)> = Vec::new();
+    let mut base = 0usize;
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let bad = |why: &str| format!("Bad Intel HEX on line {}: {}", n + 1, why);
+        if !line.starts_with(':') || line.len() < 11 || line.len() % 2 == 0 {
+            return Err(bad("not a record"));
+        }
+        let record = match parse_hex_bytes(&line[1..]) {
+            Some(x) => x,
+            None => return Err(bad("not hex")),
+        };
+        let count = record[0] as usize;
+        if record.len() != count + 5 {
+            return Err(bad("wrong length"));
+        }
+        if record.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+            return Err(bad("checksum"));
+        }
+        let offset = (record[1] as usize) << 8 | record[2] as usize;
+        let payload = &record[4..4 + count];
+        match record[3] {
+            0x00 => {
+                for (i, byte) in payload.iter().enumerate() {
+                    data.push((base + offset + i, *byte));
+                }
+            }
+            0x01 => break,
+            0x02 if count == 2 => base = ((payload[0] as usize) << 8 | payload[1] as usize) << 4,
+            0x04 if count == 2 => base = ((payload[0] as usize) << 8 | payload[1] as usize) << 16,
+            // start addresses, the pc starts at the load address anyway
+            0x03 | 0x05 => {}
+            kind => return Err(bad(&format!("record type {:02X}", kind))),
+        }
+    }
+    let start = match data.iter().map(|(a, _)| *a).min() {
+        Some(x) => x,
+        None => return Ok((Vec::new(), DATA)),
+    };
+    let end = data.iter().map(|(a, _)| *a).max().unwrap_or(start);
+    // a stray record far away would make a huge image, memory is at most 64k
+    if end - start >= 0x10000 {
+        return Err(format!("Intel HEX spans {:#x} to {:#x}", start, end));
+    }
+    let mut bytes = vec![0; end - start + 1];
+    for (address, byte) in data {
+        bytes[address - start] = byte;
+    }
+    Ok((bytes, start))
+}
+
+// Hex digits as text. Spaces, commas and 0x prefixes are ignored, ';' and
+// '#' start comments and a word ending in ':' is an address column.
+pub fn parse_hex_text(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.split([';', '#']).next().unwrap_or("");
+        for word in line.split(|c: char| c.is_whitespace() || c == ',') {
+            if word.is_empty() || word.ends_with(':') {
+                continue;
+            }
+            let digits = word.trim_start_matches("0x").trim_start_matches("0X");
+            match parse_hex_bytes(digits) {
+                Some(x) => bytes.extend(x),
+                None => return Err(format!("Bad hex on line {}: {}", n + 1, word)),
+            }
+        }
+    }
+    Ok(bytes)
+}
+
+fn parse_hex_bytes(digits: &str) -> Option<Vec<u8>> {
+    if digits.is_empty() || digits.len() % 2 == 1 || !digits.is_ascii() {
+        return None;
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn size_test() {
+        let loader = RomLoader::new();
+        assert!(loader.load_bytes("big.ch8", &[0; 3584]).is_ok());
+        assert!(loader.load_bytes("big.ch8", &[0; 3585]).is_err());
+        let eti = RomLoader::for_machine(&MemoryMap::preset("eti660").unwrap());
+        let rom = eti.load_bytes("eti.ch8", &[0; 0xEA0 - 0x600]).unwrap();
+        assert_eq!(0x600, rom.address);
+        assert!(eti.load_bytes("eti.ch8", &[0; 0xEA0 - 0x600 + 1]).is_err());
+        let vip = RomLoader::for_machine(&MemoryMap::preset("vip").unwrap());
+        assert!(vip.load_bytes("big.ch8", &[0; 3584]).is_err());
+        let schip = RomLoader::for_machine(&MemoryMap::preset("schip").unwrap());
+        assert!(schip.load_bytes("big.ch8", &[0; 3584]).is_ok());
+    }
+
+    #[test]
+    fn hex_test() {
+        let ihex = ":0402000000E0A22A4E\n:00000001FF\n";
+        let rom = RomLoader::new().load_bytes("x.hex", ihex.as_bytes()).unwrap();
+        assert_eq!((vec![0x00, 0xE0, 0xA2, 0x2A], 0x200), (rom.bytes, rom.address));
+        assert!(parse_intel_hex(":0402000000E0A22A4F\n").is_err());
+        let text = "0200: 00E0 A22A ; clear\n0x60,0x0C\n";
+        assert_eq!(vec![0x00, 0xE0, 0xA2, 0x2A, 0x60, 0x0C], parse_hex_text(text).unwrap());
+        assert!(parse_hex_text("00E").is_err());
+    }
+
+    #[test]
+    fn zip_entry_test() {
+        let entries = vec!["README".to_string(), "games/brix.ch8".to_string()];
+        assert_eq!("games/brix.ch8", choose_entry(&entries, None).unwrap());
+        let entries = vec!["brix.ch8".to_string(), "pong.ch8".to_string()];
+        assert!(choose_entry(&entries, None).is_err());
+        assert_eq!("pong.ch8", choose_entry(&entries, Some("pong.ch8")).unwrap());
+        assert!(choose_entry(&entries, Some("tank.ch8")).is_err());
+    }
+
+    // A deflated zip with the given files
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        use std::io::Write;
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+        for (name, bytes) in files {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(bytes).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn load_zip_test() {
+        let brix = [0x00, 0xE0, 0x12, 0x02];
+        let pong = [0x6A, 0x02, 0x12, 0x02];
+        let one = zip(&[("README", b"read me"), ("games/brix.ch8", &brix)]);
+        let rom = RomLoader::new().load_bytes("brix.zip", &one).unwrap();
+        assert_eq!("brix.zip:games/brix.ch8", rom.name);
+        assert_eq!(brix.to_vec(), rom.bytes);
+
+        let two = zip(&[("brix.ch8", &brix), ("pong.ch8", &pong)]);
+        assert!(RomLoader::new().load_bytes("roms.zip", &two).is_err());
+        let loader = RomLoader {
+            entry: Some("pong.ch8".to_string()),
+            ..RomLoader::new()
+        };
+        // a zip is found by its contents as well as its name
+        let rom = loader.load_bytes("roms.bin", &two).unwrap();
+        assert_eq!("roms.bin:pong.ch8", rom.name);
+        assert_eq!(pong.to_vec(), rom.bytes);
+    }
+}
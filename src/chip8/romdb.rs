@@ -0,0 +1,236 @@
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+// Rom metadata looked up by the SHA-1 of the rom, in the format of the
+// community chip-8-database (https://github.com/chip-8/chip-8-database):
+//   sha1-hashes.json  sha1 -> index into programs.json
+//   programs.json     title, authors, release and per rom platforms,
+//                     tickrate, colors and keys
+//   platforms.json    default tickrate and quirks of each platform
+//
+// A copy is bundled from data/chip-8-database (update.sh refreshes it),
+// --rom-db points at a newer checkout. Of the database quirks only shift, logic, wrap and vblank are
+// emulated.
+
+const BUNDLED_PROGRAMS: &str = include_str!("../../data/chip-8-database/programs.json");
+const BUNDLED_HASHES: &str = include_str!("../../data/chip-8-database/sha1-hashes.json");
+const BUNDLED_PLATFORMS: &str = include_str!("../../data/chip-8-database/platforms.json");
+
+// Database quirk names and the config settings they set
+const QUIRKS: [(&str, &str); 4] = [
+    ("shift", "quirks.shift"),
+    ("logic", "quirks.logic"),
+    ("wrap", "quirks.wrap"),
+    ("vblank", "quirks.vblank"),
+];
+
+// Database platforms and the memory map of the machine, modernChip8 keeps
+// the default
+const MACHINES: [(&str, &str); 6] = [
+    ("originalChip8", "vip"),
+    ("hybridVIP", "vip"),
+    ("chip48", "schip"),
+    ("superchip1", "schip"),
+    ("superchip", "schip"),
+    ("xochip", "xochip"),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Platform {
+    pub id: String,
+    pub name: String,
+    pub tickrate: Option<u32>,
+    pub quirks: Vec<(String, bool)>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RomInfo {
+    pub title: String,
+    pub authors: Vec<String>,
+    pub release: Option<String>,
+    pub platform: Option<Platform>,
+    // instructions per frame
+    pub tickrate: Option<u32>,
+    // background, foreground and the XO-CHIP plane colors
+    pub colors: Vec<String>,
+    // what the rom uses each hex key for, e.g. ("up", 5)
+    pub keys: Vec<(String, u8)>,
+}
+
+pub struct RomDb {
+    programs: Vec<Value>,
+    hashes: Value,
+    platforms: Vec<Platform>,
+}
+
+fn parse(name: &str, text: &str) -> Result<Value, String> {
+    serde_json::from_str(text).map_err(|e| format!("Bad rom database {}: {}", name, e))
+}
+
+fn parse_platform(value: &Value) -> Platform {
+    let quirks = value
+        .get("quirks")
+        .and_then(|q| q.as_object())
+        .map(|q| {
+            q.iter()
+                .filter_map(|(name, on)| on.as_bool().map(|on| (name.clone(), on)))
+                .collect()
+        })
+        .unwrap_or_default();
+    Platform {
+        id: value["id"].as_str().unwrap_or("").to_string(),
+        name: value["name"].as_str().unwrap_or("").to_string(),
+        tickrate: value["defaultTickrate"].as_u64().map(|t| t as u32),
+        quirks,
+    }
+}
+
+impl RomDb {
+    pub fn bundled() -> RomDb {
+        RomDb::from_json(BUNDLED_PROGRAMS, BUNDLED_HASHES, BUNDLED_PLATFORMS)
+            .expect("bundled rom database")
+    }
+
+    // A chip-8-database checkout, or its database directory
+    pub fn load_dir(dir: &Path) -> Result<RomDb, String> {
+        let dir = match dir.join("database").is_dir() {
+            true => dir.join("database"),
+            false => dir.to_path_buf(),
+        };
+        let read = |name: &str| {
+            fs::read_to_string(dir.join(name))
+                .map_err(|e| format!("Could not read {}: {}", dir.join(name).display(), e))
+        };
+        RomDb::from_json(
+            &read("programs.json")?,
+            &read("sha1-hashes.json")?,
+            &read("platforms.json")?,
+        )
+    }
+
+    pub fn from_json(programs: &str, hashes: &str, platforms: &str) -> Result<RomDb, String> {
+        let programs = match parse("programs.json", programs)? {
+            Value::Array(x) => x,
+            _ => return Err("programs.json should be a list".to_string()),
+        };
+        let platforms = match parse("platforms.json", platforms)? {
+            Value::Array(x) => x.iter().map(parse_platform).collect(),
+            _ => return Err("platforms.json should be a list".to_string()),
+        };
+        Ok(RomDb {
+            programs,
+            hashes: parse("sha1-hashes.json", hashes)?,
+            platforms,
+        })
+    }
+
+    pub fn lookup(&self, sha1: &str) -> Option<RomInfo> {
+        let index = self.hashes.get(sha1)?.as_u64()? as usize;
+        let program = self.programs.get(index)?;
+        let rom = program.get("roms").and_then(|r| r.get(sha1));
+        let field = |name: &str| rom.and_then(|r| r.get(name));
+        let platform = field("platforms")
+            .and_then(|p| p.get(0))
+            .and_then(|p| p.as_str())
+            .and_then(|id| self.platforms.iter().find(|p| p.id == id))
+            .cloned();
+        let strings = |value: Option<&Value>| -> Vec<String> {
+            value
+                .and_then(|v| v.as_array())
+                .map(|v| v.iter().filter_map(|s| s.as_str()).map(|s| s.to_string()).collect())
+                .unwrap_or_default()
+        };
+        let keys = field("keys")
+            .and_then(|k| k.as_object())
+            .map(|k| {
+                k.iter()
+                    .filter_map(|(name, hex)| hex.as_u64().filter(|h| *h < 16).map(|h| (name.clone(), h as u8)))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Some(RomInfo {
+            title: program["title"].as_str().unwrap_or("Untitled").to_string(),
+            authors: strings(program.get("authors")),
+            release: program["release"].as_str().map(|r| r.to_string()),
+            platform,
+            tickrate: field("tickrate").and_then(|t| t.as_u64()).map(|t| t as u32),
+            colors: strings(field("colors").and_then(|c| c.get("pixels"))),
+            keys,
+        })
+    }
+}
+
+impl RomInfo {
+    // "Title by Author (release) for platform"
+    pub fn summary(&self) -> String {
+        let mut out = self.title.clone();
+        if !self.authors.is_empty() {
+            out.push_str(&format!(" by {}", self.authors.join(", ")));
+        }
+        if let Some(release) = &self.release {
+            out.push_str(&format!(" ({})", release));
+        }
+        if let Some(platform) = &self.platform {
+            out.push_str(&format!(" for {}", platform.name));
+        }
+        out
+    }
+
+    // Config settings for the platform and rom
+    pub fn settings(&self) -> Vec<(&'static str, toml::Value)> {
+        let mut settings = Vec::new();
+        if let Some(platform) = &self.platform {
+            for (name, on) in &platform.quirks {
+                if let Some((_, key)) = QUIRKS.iter().find(|(q, _)| q == name) {
+                    settings.push((*key, toml::Value::Boolean(*on)));
+                }
+            }
+            if let Some((_, machine)) = MACHINES.iter().find(|(id, _)| *id == platform.id) {
+                settings.push(("machine.memory", toml::Value::String(machine.to_string())));
+            }
+        }
+        let tickrate = self.tickrate.or(self.platform.as_ref().and_then(|p| p.tickrate));
+        if let Some(tickrate) = tickrate {
+            settings.push(("run.ips", toml::Value::Integer(tickrate as i64 * 60)));
+        }
+        if self.colors.len() >= 2 {
+            settings.push(("display.palette", toml::Value::String(self.colors.join(" "))));
+        }
+        settings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup_test() {
+        let programs = r##"[{"title": "Blinky", "authors": ["Hans Christian Egeberg"], "release": "1991",
+            "roms": {"abc": {"platforms": ["superchip"], "keys": {"up": 3, "down": 6},
+            "colors": {"pixels": ["#000000", "#ff0000"]}}}}]"##;
+        let db = RomDb::from_json(programs, r#"{"abc": 0}"#, BUNDLED_PLATFORMS).unwrap();
+        assert_eq!(None, db.lookup("def"));
+        let info = db.lookup("abc").unwrap();
+        assert_eq!("Blinky by Hans Christian Egeberg (1991) for SUPER-CHIP 1.1", info.summary());
+        assert_eq!(vec![("down".to_string(), 6), ("up".to_string(), 3)], info.keys);
+        let settings = info.settings();
+        assert!(settings.contains(&("quirks.shift", toml::Value::Boolean(true))));
+        assert!(settings.contains(&("run.ips", toml::Value::Integer(30 * 60))));
+        assert!(settings.contains(&("machine.memory", toml::Value::String("schip".to_string()))));
+        assert!(settings.contains(&("display.palette", toml::Value::String("#000000 #ff0000".to_string()))));
+    }
+
+    #[test]
+    fn bundled_test() {
+        let db = RomDb::bundled();
+        assert!(db.platforms.iter().any(|p| p.id == "originalChip8"));
+        // every bundled hash finds its program and gives settings
+        for sha1 in db.hashes.as_object().unwrap().keys() {
+            let info = db.lookup(sha1).unwrap();
+            assert!(!info.summary().is_empty());
+            assert!(!info.settings().is_empty(), "no settings for {}", sha1);
+        }
+    }
+}
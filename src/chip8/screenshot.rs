@@ -0,0 +1,180 @@
+use crate::chip8::palette::Palette;
+use crate::chip8::Chip8;
+use crate::chip8::COLS;
+use crate::chip8::ROWS;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+// Exact captures of the display. The format comes from the file extension:
+// .png (scaled, in the palette colors), .pbm (plain black and white) or
+// .pgm (plain greyscale of the palette).
+
+pub const DEFAULT_SCALE: usize = 8;
+
+// Palette index of every pixel, row by row
+pub fn framebuffer(chip8: &Chip8) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity(COLS * ROWS);
+    for y in 0..ROWS {
+        for x in 0..COLS {
+            pixels.push(chip8.pixel(x, y) as u8);
+        }
+    }
+    pixels
+}
+
+// Repeat every pixel scale times in both directions
+pub fn scaled(pixels: &[u8], width: usize, height: usize, scale: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(pixels.len() * scale * scale);
+    for y in 0..height * scale {
+        for x in 0..width * scale {
+            out.push(pixels[(y / scale) * width + (x / scale)]);
+        }
+    }
+    out
+}
+
+// For --scale and the monitor's shot, a pixel has to be at least 1x1
+pub fn parse_scale(text: &str) -> Result<usize, String> {
+    match text.parse::<usize>() {
+        Ok(x) if x >= 1 => Ok(x),
+        _ => Err(format!("Bad scale {}, expected a whole number from 1", text)),
+    }
+}
+
+pub fn save_screenshot(path: &Path, chip8: &Chip8, scale: usize, palette: &Palette) -> Result<(), String> {
+    if scale < 1 {
+        return Err(format!("Bad scale {} for {}", scale, path.display()));
+    }
+    let pixels = framebuffer(chip8);
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    let result = match extension.as_str() {
+        "png" => write_png(path, &pixels, COLS, ROWS, scale, palette),
+        "pbm" => write_pbm(path, &pixels, COLS, ROWS, scale),
+        "pgm" => write_pgm(path, &pixels, COLS, ROWS, scale, palette),
+        _ => return Err(format!("Unknown screenshot format: {}", path.display())),
+    };
+    result.map_err(|e| format!("Could not write {}: {}", path.display(), e))
+}
+
+pub fn write_png(
+    path: &Path,
+    pixels: &[u8],
+    width: usize,
+    height: usize,
+    scale: usize,
+    palette: &Palette,
+) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| e.to_string())?;
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(file),
+        (width * scale) as u32,
+        (height * scale) as u32,
+    );
+    encoder.set_color(png::ColorType::RGB);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut data = Vec::with_capacity(width * height * scale * scale * 3);
+    for index in scaled(pixels, width, height, scale) {
+        let color = palette.color(index);
+        data.extend_from_slice(&[color.r, color.g, color.b]);
+    }
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer.write_image_data(&data).map_err(|e| e.to_string())
+}
+
+// P1, 1 is black so lit pixels are 1
+pub fn pbm_text(pixels: &[u8], width: usize, height: usize, scale: usize) -> String {
+    let mut out = format!("P1\n{} {}\n", width * scale, height * scale);
+    let data = scaled(pixels, width, height, scale);
+    for row in data.chunks(width * scale) {
+        let line: Vec<&str> = row.iter().map(|p| if *p != 0 { "1" } else { "0" }).collect();
+        out.push_str(&line.join(" "));
+        out.push('\n');
+    }
+    out
+}
+
+// P2 with the luma of each palette color
+pub fn pgm_text(pixels: &[u8], width: usize, height: usize, scale: usize, palette: &Palette) -> String {
+    let mut out = format!("P2\n{} {}\n255\n", width * scale, height * scale);
+    let data = scaled(pixels, width, height, scale);
+    for row in data.chunks(width * scale) {
+        let line: Vec<String> = row
+            .iter()
+            .map(|p| {
+                let c = palette.color(*p);
+                let luma = (c.r as u32 * 299 + c.g as u32 * 587 + c.b as u32 * 114) / 1000;
+                luma.to_string()
+            })
+            .collect();
+        out.push_str(&line.join(" "));
+        out.push('\n');
+    }
+    out
+}
+
+fn write_pbm(path: &Path, pixels: &[u8], width: usize, height: usize, scale: usize) -> Result<(), String> {
+    fs::write(path, pbm_text(pixels, width, height, scale)).map_err(|e| e.to_string())
+}
+
+fn write_pgm(
+    path: &Path,
+    pixels: &[u8],
+    width: usize,
+    height: usize,
+    scale: usize,
+    palette: &Palette,
+) -> Result<(), String> {
+    fs::write(path, pgm_text(pixels, width, height, scale, palette)).map_err(|e| e.to_string())
+}
+
+// Screenshots to take at the end of given frames in a headless run
+pub struct ScreenshotSchedule {
+    pub frames: Vec<(u32, PathBuf)>,
+    pub scale: usize,
+    pub palette: Palette,
+}
+
+impl ScreenshotSchedule {
+    pub fn frame_done(&self, frame: u32, chip8: &Chip8) {
+        for (at, path) in &self.frames {
+            if *at == frame {
+                match save_screenshot(path, chip8, self.scale, &self.palette) {
+                    Ok(()) => println!("Saved screenshot {}", path.display()),
+                    Err(e) => println!("{}", e),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pbm_scaled_test() {
+        let text = pbm_text(&[1, 0], 2, 1, 2);
+        assert_eq!("P1\n4 2\n1 1 0 0\n1 1 0 0\n", text);
+    }
+
+    #[test]
+    fn scale_test() {
+        assert_eq!(Ok(3), parse_scale("3"));
+        assert!(parse_scale("0").is_err());
+        assert!(parse_scale("-1").is_err());
+        let path = Path::new("never-written.png");
+        assert!(save_screenshot(path, &Chip8::new(), 0, &Palette::mono()).is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn pgm_uses_palette_test() {
+        let text = pgm_text(&[0, 1], 2, 1, 1, &Palette::mono());
+        assert_eq!("P2\n2 1\n255\n0 255\n", text);
+    }
+}
@@ -0,0 +1,246 @@
+use crate::chip8::emu_utils::display_render;
+use crate::chip8::Chip8;
+use crate::chip8::COLS;
+use crate::chip8::MEMORY_SIZE;
+use crate::chip8::ROWS;
+use rhai::{Dynamic, Engine, EvalAltResult, FnPtr, NativeCallContext};
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+
+// Rhai scripts driving the emulator for scripted playtests, cheats and splitters.
+// https://rhai.rs/book/
+//
+//   reg(n) set_reg(n, v) get_i() set_i(v) pc() set_pc(v) dt() st()
+//   peek(addr) poke(addr, v) pixel(x, y)
+//   press(k) release(k) step() run_frames(n) frame() screen()
+//   draws() instructions()    counts for the last frame run_frames finished
+//   on_pc(addr, f) on_frame(f) on_draw(f) on_sound(f)
+//
+// on_pc callbacks get the address, on_frame gets the frame number.
+
+#[derive(Default)]
+struct Hooks {
+    pc: Vec<(u16, FnPtr)>,
+    frame: Vec<FnPtr>,
+    draw: Vec<FnPtr>,
+    sound: Vec<FnPtr>,
+}
+
+type Machine = Rc<RefCell<Chip8>>;
+
+fn out_of_range(what: &str, value: i64) -> Box<EvalAltResult> {
+    format!("{} out of range: {}", what, value).into()
+}
+
+fn address(value: i64) -> Result<usize, Box<EvalAltResult>> {
+    match value >= 0 && (value as usize) < MEMORY_SIZE {
+        true => Ok(value as usize),
+        false => Err(out_of_range("address", value)),
+    }
+}
+
+fn nibble(what: &str, value: i64) -> Result<usize, Box<EvalAltResult>> {
+    match (0..16).contains(&value) {
+        true => Ok(value as usize),
+        false => Err(out_of_range(what, value)),
+    }
+}
+
+fn call_all(
+    context: &NativeCallContext,
+    callbacks: Vec<FnPtr>,
+    arg: i64,
+) -> Result<(), Box<EvalAltResult>> {
+    for callback in callbacks {
+        let _ = callback.call_within_context::<Dynamic>(context, (arg,))?;
+    }
+    Ok(())
+}
+
+// One instruction plus the pc, draw and sound callbacks around it
+fn step(context: &NativeCallContext, chip8: &Machine, hooks: &Rc<RefCell<Hooks>>) -> Result<(), Box<EvalAltResult>> {
+    let pc = chip8.borrow().pc;
+    let at_pc: Vec<FnPtr> = hooks
+        .borrow()
+        .pc
+        .iter()
+        .filter(|(a, _)| *a == pc)
+        .map(|(_, f)| f.clone())
+        .collect();
+    call_all(context, at_pc, pc as i64)?;
+
+    let (drew, sound_started) = {
+        let mut c = chip8.borrow_mut();
+        if c.pc as usize + 1 >= MEMORY_SIZE {
+            return Err(format!("pc ran off the end of memory at {:#05X}", c.pc).into());
+        }
+        let sound_before = c.timer_sound;
+        let (b0, b1) = c.fetch();
+        c.decode_execute(b0, b1);
+        let drew = c.should_draw;
+        c.should_draw = false;
+        (drew, sound_before == 0 && c.timer_sound > 0)
+    };
+    if drew {
+        let draw = hooks.borrow().draw.clone();
+        call_all(context, draw, pc as i64)?;
+    }
+    if sound_started {
+        let sound = hooks.borrow().sound.clone();
+        call_all(context, sound, pc as i64)?;
+    }
+    Ok(())
+}
+
+pub fn run_script(chip8: Chip8, path: &Path, glyph: char) {
+    let engine = create_engine(&Rc::new(RefCell::new(chip8)), glyph);
+    if let Err(e) = engine.run_file(path.to_path_buf()) {
+        println!("Script error: {}", e);
+    }
+}
+
+// An engine with the functions above bound to the machine
+fn create_engine(chip8: &Machine, glyph: char) -> Engine {
+    let hooks = Rc::new(RefCell::new(Hooks::default()));
+    let frames = Rc::new(RefCell::new(0i64));
+    let mut engine = Engine::new();
+
+    // registers and memory
+    let c = chip8.clone();
+    engine.register_fn("reg", move |n: i64| -> Result<i64, Box<EvalAltResult>> {
+        Ok(c.borrow().v[nibble("register", n)?] as i64)
+    });
+    let c = chip8.clone();
+    engine.register_fn("set_reg", move |n: i64, value: i64| -> Result<(), Box<EvalAltResult>> {
+        c.borrow_mut().v[nibble("register", n)?] = value as u8;
+        Ok(())
+    });
+    let c = chip8.clone();
+    engine.register_fn("get_i", move || c.borrow().i as i64);
+    let c = chip8.clone();
+    engine.register_fn("set_i", move |value: i64| c.borrow_mut().i = value as u16);
+    let c = chip8.clone();
+    engine.register_fn("pc", move || c.borrow().pc as i64);
+    let c = chip8.clone();
+    engine.register_fn("set_pc", move |value: i64| c.borrow_mut().pc = value as u16);
+    let c = chip8.clone();
+    engine.register_fn("dt", move || c.borrow().timer_delay as i64);
+    let c = chip8.clone();
+    engine.register_fn("st", move || c.borrow().timer_sound as i64);
+    let c = chip8.clone();
+    engine.register_fn("peek", move |at: i64| -> Result<i64, Box<EvalAltResult>> {
+        Ok(c.borrow().memory[address(at)?] as i64)
+    });
+    let c = chip8.clone();
+    engine.register_fn("poke", move |at: i64, value: i64| -> Result<(), Box<EvalAltResult>> {
+        c.borrow_mut().memory[address(at)?] = value as u8;
+        Ok(())
+    });
+    let c = chip8.clone();
+    engine.register_fn("pixel", move |x: i64, y: i64| -> bool {
+        let x = x.rem_euclid(COLS as i64) as usize;
+        let y = y.rem_euclid(ROWS as i64) as usize;
+        c.borrow().pixel(x, y)
+    });
+    let c = chip8.clone();
+    engine.register_fn("screen", move || display_render(&c.borrow(), false, glyph));
+
+    // keypad
+    let c = chip8.clone();
+    engine.register_fn("press", move |k: i64| -> Result<(), Box<EvalAltResult>> {
+        c.borrow_mut().press_key(nibble("key", k)? as u8);
+        Ok(())
+    });
+    let c = chip8.clone();
+    engine.register_fn("release", move |k: i64| -> Result<(), Box<EvalAltResult>> {
+        c.borrow_mut().release_key(nibble("key", k)? as u8);
+        Ok(())
+    });
+
+    // callbacks
+    let h = hooks.clone();
+    engine.register_fn("on_pc", move |at: i64, f: FnPtr| h.borrow_mut().pc.push((at as u16, f)));
+    let h = hooks.clone();
+    engine.register_fn("on_frame", move |f: FnPtr| h.borrow_mut().frame.push(f));
+    let h = hooks.clone();
+    engine.register_fn("on_draw", move |f: FnPtr| h.borrow_mut().draw.push(f));
+    let h = hooks.clone();
+    engine.register_fn("on_sound", move |f: FnPtr| h.borrow_mut().sound.push(f));
+
+    // running
+    let (c, h) = (chip8.clone(), hooks.clone());
+    engine.register_fn("step", move |context: NativeCallContext| step(&context, &c, &h));
+    let f = frames.clone();
+    engine.register_fn("frame", move || *f.borrow());
+    let c = chip8.clone();
+    engine.register_fn("draws", move || c.borrow().last_frame.draws as i64);
+    let c = chip8.clone();
+    engine.register_fn("instructions", move || c.borrow().last_frame.instructions as i64);
+    let (c, h, f) = (chip8.clone(), hooks.clone(), frames.clone());
+    engine.register_fn(
+        "run_frames",
+        move |context: NativeCallContext, n: i64| -> Result<(), Box<EvalAltResult>> {
+            for _ in 0..n {
+                while !c.borrow().frame_over() {
+                    step(&context, &c, &h)?;
+                }
+                c.borrow_mut().end_frame();
+                *f.borrow_mut() += 1;
+                let frame = *f.borrow();
+                let on_frame = h.borrow().frame.clone();
+                call_all(&context, on_frame, frame)?;
+            }
+            Ok(())
+        },
+    );
+    engine
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // draw a dot at 0,0, Fx0A so the next key press lands in v2 (it doesn't block here),
+    // then v3 := 7 and jump to itself
+    const ROM: [u8; 11] = [
+        0xA2, 0x0A, 0xD0, 0x11, 0xF2, 0x0A, 0x63, 0x07, 0x12, 0x08, 0x80,
+    ];
+
+    fn machine() -> Machine {
+        let mut chip8 = Chip8::new();
+        chip8.load_program_at(&ROM, 0x200);
+        Rc::new(RefCell::new(chip8))
+    }
+
+    #[test]
+    fn script_drives_rom_test() {
+        let chip8 = machine();
+        let engine = create_engine(&chip8, '#');
+        let script = r#"
+            fn count_draw(pc) { poke(0x300, peek(0x300) + 1); }
+            on_draw(Fn("count_draw"));
+            step();
+            step();
+            let drawn = [pixel(0, 0), pixel(1, 0), pixel(64, 32)];
+            run_frames(2);
+            press(5);
+            drawn + [frame(), draws(), instructions()]
+        "#;
+        let result: Vec<Dynamic> = engine.eval(script).unwrap();
+        let values: Vec<String> = result.iter().map(|v| v.to_string()).collect();
+        assert_eq!(vec!["true", "false", "true", "2", "0", "10"], values);
+        let c = chip8.borrow();
+        assert_eq!(1, c.memory[0x300]);
+        assert_eq!((5, 7, 0x208), (c.v[2], c.v[3], c.pc));
+    }
+
+    #[test]
+    fn script_errors_test() {
+        let engine = create_engine(&machine(), '#');
+        assert!(engine.eval::<i64>("reg(16)").is_err());
+        assert!(engine.eval::<i64>("peek(0x1000)").is_err());
+        assert!(engine.eval::<()>("press(-1)").is_err());
+        assert_eq!(0x200, engine.eval::<i64>("pc()").unwrap());
+    }
+}
@@ -0,0 +1,205 @@
+use crate::chip8::lint::Draw;
+use crate::chip8::palette::Palette;
+use crate::chip8::screenshot::write_png;
+use crate::chip8::symbols::Symbols;
+use crate::chip8::Chip8;
+use crate::chip8::MEMORY_SIZE;
+use std::path::Path;
+
+// chip8 sprites: pull the art out of a rom. Sprites are found where an
+// Annn leads to a Dxyn, either from the lint walk or by running the rom
+// headless and noting I at every draw. Dxy0 is a 16x16 SCHIP sprite.
+
+// Frames each key is held for while tracing, so menus move along
+const KEY_FRAMES: u32 = 20;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sprite {
+    pub address: u16,
+    pub width: usize,
+    pub height: usize,
+    pub bytes: Vec<u8>,
+    // where it's drawn from
+    pub draws: Vec<u16>,
+}
+
+impl Sprite {
+    fn bytes_per_row(&self) -> usize {
+        self.width / 8
+    }
+
+    pub fn is_set(&self, x: usize, y: usize) -> bool {
+        let byte = self.bytes[y * self.bytes_per_row() + x / 8];
+        byte & (0x80 >> (x % 8)) != 0
+    }
+
+    // Palette index of every pixel, row by row
+    pub fn pixels(&self) -> Vec<u8> {
+        let mut pixels = Vec::with_capacity(self.width * self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                pixels.push(self.is_set(x, y) as u8);
+            }
+        }
+        pixels
+    }
+
+    pub fn name(&self, symbols: Option<&Symbols>) -> String {
+        match symbols.and_then(|s| s.label_at(self.address)) {
+            Some(label) => label.to_string(),
+            None => format!("sprite_{:03X}", self.address),
+        }
+    }
+
+    // Like debug_font, a glyph for every set pixel
+    pub fn to_text(&self, glyph: char) -> String {
+        let mut out = String::new();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                out.push(if self.is_set(x, y) { glyph } else { ' ' });
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    // A label and a db line a row, the row drawn in the comment
+    pub fn to_asm(&self, symbols: Option<&Symbols>) -> String {
+        let mut out = format!("{}:\n", self.name(symbols));
+        let per_row = self.bytes_per_row();
+        for (y, row) in self.bytes.chunks(per_row).enumerate() {
+            let values: Vec<String> = row.iter().map(|b| format!("0x{:02X}", b)).collect();
+            let picture: String = (0..self.width)
+                .map(|x| if self.is_set(x, y) { '#' } else { '.' })
+                .collect();
+            out.push_str(&format!("    db {}  ; {}\n", values.join(", "), picture));
+        }
+        out
+    }
+
+    pub fn save_png(&self, path: &Path, scale: usize) -> Result<(), String> {
+        write_png(path, &self.pixels(), self.width, self.height, scale, &Palette::mono())
+            .map_err(|e| format!("Could not write {}: {}", path.display(), e))
+    }
+}
+
+// Run the rom for some frames, holding each key in turn, and note every draw
+pub fn trace_draws(chip8: &mut Chip8, frames: u32) -> Vec<Draw> {
+    let mut draws = Vec::new();
+    for frame in 0..frames {
+        if frame % KEY_FRAMES == 0 {
+            let key = ((frame / KEY_FRAMES) % 16) as u8;
+            chip8.release_key(key.wrapping_sub(1) & 0xF);
+            chip8.press_key(key);
+        }
+        while !chip8.frame_over() {
+            let pc = chip8.pc;
+            if pc as usize + 1 >= MEMORY_SIZE {
+                return draws;
+            }
+            let (b0, b1) = chip8.fetch();
+            if b0 >> 4 == 0xD {
+                let draw = Draw {
+                    pc,
+                    address: chip8.i,
+                    rows: b1 & 0x0F,
+                };
+                if !draws.contains(&draw) {
+                    draws.push(draw);
+                }
+            }
+            chip8.decode_execute(b0, b1);
+        }
+        chip8.end_frame();
+    }
+    draws
+}
+
+// One sprite per address, as tall as the tallest draw of it
+pub fn collect_sprites(draws: &[Draw], memory: &[u8]) -> Vec<Sprite> {
+    let mut sprites: Vec<Sprite> = Vec::new();
+    for draw in draws {
+        let (width, height) = match draw.rows {
+            0 => (16, 16),
+            n => (8, n as usize),
+        };
+        let start = draw.address as usize;
+        let end = start + height * width / 8;
+        if end > memory.len() {
+            continue;
+        }
+        let bytes = memory[start..end].to_vec();
+        match sprites.iter_mut().find(|s| s.address == draw.address && s.width == width) {
+            Some(sprite) => {
+                if height > sprite.height {
+                    sprite.height = height;
+                    sprite.bytes = bytes;
+                }
+                if !sprite.draws.contains(&draw.pc) {
+                    sprite.draws.push(draw.pc);
+                }
+            }
+            None => sprites.push(Sprite {
+                address: draw.address,
+                width,
+                height,
+                bytes,
+                draws: vec![draw.pc],
+            }),
+        }
+    }
+    sprites.sort_by_key(|s| (s.address, s.width));
+    sprites
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::lint::draws;
+    use crate::chip8::DATA;
+
+    // i := ball, draw it 3 rows then 4 rows, then a 16x16 one through i += v0
+    const ROM: [u8; 20] = [
+        0xA2, 0x0C, // i := 0x20C
+        0xD0, 0x13, // sprite v0 v1 3
+        0xD0, 0x14, // sprite v0 v1 4
+        0xF0, 0x1E, // i += v0
+        0xD0, 0x10, // sprite v0 v1 0
+        0x12, 0x0A, // halt
+        0x60, 0xF0, 0x90, 0x60, // ball
+        0x00, 0x00, 0x00, 0x00,
+    ];
+
+    fn memory() -> Vec<u8> {
+        let mut chip8 = Chip8::new();
+        chip8.load_program_at(&ROM, DATA);
+        chip8.memory.to_vec()
+    }
+
+    #[test]
+    fn static_sprites_test() {
+        let sprites = collect_sprites(&draws(&ROM, DATA), &memory());
+        assert_eq!(1, sprites.len());
+        let ball = &sprites[0];
+        assert_eq!((0x20C, 8, 4), (ball.address, ball.width, ball.height));
+        assert_eq!(vec![0x202, 0x204], ball.draws);
+        assert_eq!(" ##     \n####    \n#  #    \n ##     \n", ball.to_text('#'));
+        assert_eq!(
+            "sprite_20C:\n    db 0x60  ; .##.....\n    db 0xF0  ; ####....\n    db 0x90  ; #..#....\n    db 0x60  ; .##.....\n",
+            ball.to_asm(None)
+        );
+    }
+
+    #[test]
+    fn traced_sprites_test() {
+        let mut chip8 = Chip8::new();
+        chip8.load_program_at(&ROM, DATA);
+        chip8.v[0] = 2;
+        let sprites = collect_sprites(&trace_draws(&mut chip8, 1), &memory());
+        // the i += v0 one is only found by running
+        assert_eq!(2, sprites.len());
+        assert_eq!((0x20E, 16, 16), (sprites[1].address, sprites[1].width, sprites[1].height));
+        assert_eq!(32, sprites[1].bytes.len());
+        assert!(sprites[1].is_set(0, 0));
+    }
+}
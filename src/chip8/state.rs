@@ -0,0 +1,91 @@
+use crate::chip8::memory_map::MACHINE_MEMORY;
+use crate::chip8::Chip8;
+use std::fs;
+use std::path::Path;
+
+// Save states are the raw machine: a magic header, the registers, then all of memory.
+const MAGIC: &[u8; 4] = b"C8S2";
+const HEADER_SIZE: usize = 4 + 16 + 2 + 2 + 1 + 1 + 1 + 2 + 1 + 1;
+pub const STATE_SIZE: usize = HEADER_SIZE + MACHINE_MEMORY;
+
+pub fn state_to_bytes(chip8: &Chip8) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(STATE_SIZE);
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&chip8.v);
+    bytes.extend_from_slice(&chip8.i.to_be_bytes());
+    bytes.extend_from_slice(&chip8.pc.to_be_bytes());
+    bytes.push(chip8.sp);
+    bytes.push(chip8.timer_delay);
+    bytes.push(chip8.timer_sound);
+    bytes.extend_from_slice(&chip8.keyboard.to_be_bytes());
+    bytes.push(chip8.wait_key as u8);
+    bytes.push(chip8.wait_key_v_x as u8);
+    bytes.extend_from_slice(&chip8.memory);
+    bytes
+}
+
+pub fn state_from_bytes(chip8: &mut Chip8, bytes: &[u8]) -> Result<(), String> {
+    if bytes.len() != STATE_SIZE || &bytes[0..4] != MAGIC {
+        return Err("Not a save state".to_string());
+    }
+    let mut at = 4;
+    chip8.v.copy_from_slice(&bytes[at..at + 16]);
+    at += 16;
+    chip8.i = u16::from_be_bytes([bytes[at], bytes[at + 1]]);
+    chip8.pc = u16::from_be_bytes([bytes[at + 2], bytes[at + 3]]);
+    at += 4;
+    chip8.sp = bytes[at];
+    chip8.timer_delay = bytes[at + 1];
+    chip8.timer_sound = bytes[at + 2];
+    at += 3;
+    chip8.keyboard = u16::from_be_bytes([bytes[at], bytes[at + 1]]);
+    chip8.wait_key = bytes[at + 2] != 0;
+    chip8.wait_key_v_x = bytes[at + 3] as usize & 0xF;
+    at += 4;
+    chip8.memory.copy_from_slice(&bytes[at..]);
+    chip8.should_draw = true;
+    Ok(())
+}
+
+pub fn save_state(chip8: &Chip8, path: &Path) -> Result<(), String> {
+    fs::write(path, state_to_bytes(chip8)).map_err(|e| format!("Could not save state: {}", e))
+}
+
+pub fn load_state(chip8: &mut Chip8, path: &Path) -> Result<(), String> {
+    let bytes = fs::read(path).map_err(|e| format!("Could not load state: {}", e))?;
+    state_from_bytes(chip8, &bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn state_round_trip_test() {
+        let mut chip8 = Chip8::new();
+        chip8.load_program_at(&[0x60, 0x05], 0x200);
+        chip8.v[3] = 0x10;
+        chip8.i = 0x321;
+        chip8.sp = 2;
+        chip8.timer_delay = 7;
+        chip8.keyboard = 0x8001;
+        let bytes = state_to_bytes(&chip8);
+        assert_eq!(STATE_SIZE, bytes.len());
+
+        let mut restored = Chip8::new();
+        state_from_bytes(&mut restored, &bytes).unwrap();
+        assert_eq!(0x10, restored.v[3]);
+        assert_eq!(0x321, restored.i);
+        assert_eq!(0x200, restored.pc);
+        assert_eq!(2, restored.sp);
+        assert_eq!(7, restored.timer_delay);
+        assert_eq!(0x8001, restored.keyboard);
+        assert_eq!(0x60, restored.memory[0x200]);
+    }
+
+    #[test]
+    fn state_rejects_other_files_test() {
+        let mut chip8 = Chip8::new();
+        assert!(state_from_bytes(&mut chip8, &[0; 16]).is_err());
+    }
+}
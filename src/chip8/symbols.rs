@@ -0,0 +1,221 @@
+use std::fs;
+use std::path::Path;
+
+// Symbol files map assembler labels to addresses and addresses back to the
+// source line that produced them. One entry per line, '#' or ';' comments:
+//
+//   main        0x200      <- label
+//   draw_player = 0x2A4    <- label, assembler style
+//   0x2B0 sprite_data      <- label, address first
+//   0x200 game.8o:12       <- line map (address file:line)
+//   0x2A4 game.8o:40
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceLine {
+    pub file: String,
+    pub line: u32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Symbols {
+    pub labels: Vec<(String, u16)>,
+    pub lines: Vec<(u16, SourceLine)>,
+}
+
+impl Symbols {
+    pub fn label_at(&self, address: u16) -> Option<&str> {
+        self.labels
+            .iter()
+            .find(|(_, a)| *a == address)
+            .map(|(name, _)| name.as_str())
+    }
+
+    pub fn line_at(&self, address: u16) -> Option<&SourceLine> {
+        self.lines
+            .iter()
+            .find(|(a, _)| *a == address)
+            .map(|(_, line)| line)
+    }
+
+    pub fn address_of(&self, label: &str) -> Option<u16> {
+        self.labels
+            .iter()
+            .find(|(name, _)| name == label)
+            .map(|(_, a)| *a)
+    }
+
+    // Closest label at or below the address
+    pub fn nearest_label(&self, address: u16) -> Option<(&str, u16)> {
+        self.labels
+            .iter()
+            .filter(|(_, a)| *a <= address)
+            .max_by_key(|(_, a)| *a)
+            .map(|(name, a)| (name.as_str(), address - a))
+    }
+
+    // draw_player+0x4 style name for an address
+    pub fn describe(&self, address: u16) -> String {
+        match self.nearest_label(address) {
+            Some((name, 0)) => name.to_string(),
+            Some((name, offset)) => format!("{}+{:#X}", name, offset),
+            None => format!("{:#05X}", address),
+        }
+    }
+
+    // The symbol file format parse_symbols reads
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        for (name, address) in &self.labels {
+            out.push_str(&format!("{} {:#05x}\n", name, address));
+        }
+        for (address, line) in &self.lines {
+            out.push_str(&format!("{:#05x} {}:{}\n", address, line.file, line.line));
+        }
+        out
+    }
+
+    // Accepts an address, a label or label+offset
+    pub fn resolve(&self, text: &str) -> Option<u16> {
+        if let Some(address) = parse_address(text) {
+            return Some(address);
+        }
+        match text.find('+') {
+            Some(split) => {
+                let base = self.address_of(&text[..split])?;
+                let offset = parse_address(&text[split + 1..])?;
+                Some(base.wrapping_add(offset))
+            }
+            None => self.address_of(text),
+        }
+    }
+}
+
+// Address as a label when we have symbols, plain hex otherwise
+pub fn describe_address(symbols: Option<&Symbols>, address: u16) -> String {
+    match symbols {
+        Some(symbols) => symbols.describe(address),
+        None => format!("{:#05X}", address),
+    }
+}
+
+pub fn load_symbols(path: &Path) -> Result<Symbols, String> {
+    let text = match fs::read_to_string(path) {
+        Ok(x) => x,
+        Err(e) => return Err(format!("Could not read symbol file: {}", e)),
+    };
+    parse_symbols(&text)
+}
+
+pub fn parse_symbols(text: &str) -> Result<Symbols, String> {
+    let mut symbols = Symbols::default();
+    for (line_i, raw) in text.lines().enumerate() {
+        let line = raw.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        let mut parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.len() == 3 && parts[1] == "=" {
+            parts.remove(1);
+        }
+        if parts.len() != 2 {
+            return Err(format!("line {}: expected two fields", line_i + 1));
+        }
+        if let Some(address) = parse_address(parts[0]).filter(|_| !parts[1].contains(':')) {
+            // address label
+            symbols.labels.push((parts[1].to_string(), address));
+        } else if let Some(address) = parse_address(parts[0]) {
+            // address file:line
+            let source = match parts[1].rfind(':') {
+                Some(split) => {
+                    let (file, number) = parts[1].split_at(split);
+                    match number[1..].parse::<u32>() {
+                        Ok(n) => SourceLine {
+                            file: file.to_string(),
+                            line: n,
+                        },
+                        Err(_) => return Err(format!("line {}: bad line number", line_i + 1)),
+                    }
+                }
+                None => return Err(format!("line {}: expected file:line", line_i + 1)),
+            };
+            symbols.lines.push((address, source));
+        } else if let Some(address) = parse_address(parts[1]) {
+            // label address
+            symbols.labels.push((parts[0].to_string(), address));
+        } else {
+            return Err(format!("line {}: no address found", line_i + 1));
+        }
+    }
+    Ok(symbols)
+}
+
+// Accepts 0x2A4, 2A4h or plain decimal. Addresses start with a digit, so
+// labels like each are never read as hex (write 0EACh).
+pub fn parse_address(text: &str) -> Option<u16> {
+    if !text.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    let lower = text.to_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x") {
+        return u16::from_str_radix(hex, 16).ok();
+    }
+    if lower.ends_with('h') {
+        return u16::from_str_radix(&lower[..lower.len() - 1], 16).ok();
+    }
+    lower.parse::<u16>().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_labels_and_lines_test() {
+        let text = "# test\nmain 0x200\n0x200 game.8o:12\ndraw 2A4h\n";
+        let symbols = parse_symbols(text).unwrap();
+        assert_eq!(Some("main"), symbols.label_at(0x200));
+        assert_eq!(Some("draw"), symbols.label_at(0x2A4));
+        assert_eq!(12, symbols.line_at(0x200).unwrap().line);
+        assert_eq!("game.8o", symbols.line_at(0x200).unwrap().file);
+        assert_eq!(symbols, parse_symbols(&symbols.to_text()).unwrap());
+    }
+
+    #[test]
+    fn assembler_style_labels_test() {
+        let symbols = parse_symbols("draw = 0x2A4\n0x2B0 sprite\n").unwrap();
+        assert_eq!(Some(0x2A4), symbols.address_of("draw"));
+        assert_eq!(Some(0x2B0), symbols.address_of("sprite"));
+    }
+
+    #[test]
+    fn describe_and_resolve_test() {
+        let symbols = parse_symbols("main 0x200\ndraw_player 0x2A0\n").unwrap();
+        assert_eq!("draw_player+0x4", symbols.describe(0x2A4));
+        assert_eq!("main", symbols.describe(0x200));
+        assert_eq!("0x100", symbols.describe(0x100));
+        assert_eq!(Some(0x2A4), symbols.resolve("draw_player+0x4"));
+        assert_eq!(Some(0x2A4), symbols.resolve("0x2a4"));
+        assert_eq!(None, symbols.resolve("missing"));
+    }
+
+    #[test]
+    fn parse_address_test() {
+        assert_eq!(Some(0x2A4), parse_address("0x2a4"));
+        assert_eq!(Some(0x2A4), parse_address("2A4h"));
+        assert_eq!(Some(512), parse_address("512"));
+        assert_eq!(None, parse_address("main"));
+        assert_eq!(None, parse_address("each"));
+        assert_eq!(Some(0xEAC), parse_address("0EACh"));
+    }
+
+    #[test]
+    fn hex_looking_labels_test() {
+        let symbols = parse_symbols("each 0x206
+beefh 0x208
+").unwrap();
+        assert_eq!(Some(0x206), symbols.resolve("each"));
+        assert_eq!(Some(0x208), symbols.resolve("beefh"));
+        assert_eq!(Some(0x20A), symbols.resolve("each+4"));
+        assert_eq!(symbols, parse_symbols(&symbols.to_text()).unwrap());
+    }
+}
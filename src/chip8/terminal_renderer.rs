@@ -0,0 +1,287 @@
+use crate::chip8::emu_utils::display_render;
+use crate::chip8::filter::{DisplayFilter, FilterKind, LIT_THRESHOLD};
+use crate::chip8::palette::{ansi_bg, ansi_fg, Palette, ANSI_RESET};
+use crate::chip8::screenshot::framebuffer;
+use crate::chip8::Chip8;
+use crate::chip8::COLS;
+use crate::chip8::ROWS;
+
+// Denser text output than display_render's one char per pixel.
+// Half blocks put 2 pixels in a cell (64x16 cells for 64x32),
+// braille puts 2x4 pixels in a cell (32x8 cells).
+
+const GLYPH_UPPER: char = '\u{2580}'; // ▀
+const GLYPH_LOWER: char = '\u{2584}'; // ▄
+const GLYPH_FULL: char = '\u{2588}'; // █
+const BRAILLE_BLANK: u32 = 0x2800;
+
+// Braille dot bit for the pixel at (x, y) inside a 2x4 cell
+const BRAILLE_DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+const ANSI_CLEAR: &str = "\x1b[2J";
+const ANSI_HOME: &str = "\x1b[H";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RenderMode {
+    Glyph(char),
+    HalfBlock,
+    Braille,
+}
+
+impl RenderMode {
+    // Half blocks and braille need unicode, fall back to the glyph without it
+    pub fn from_name(name: &str, glyph: char, unicode: bool) -> Option<RenderMode> {
+        match name {
+            "char" => Some(RenderMode::Glyph(glyph)),
+            "half" if unicode => Some(RenderMode::HalfBlock),
+            "braille" if unicode => Some(RenderMode::Braille),
+            "half" | "braille" => Some(RenderMode::Glyph(glyph)),
+            _ => None,
+        }
+    }
+}
+
+pub fn frame_text<F: Fn(usize, usize) -> bool>(
+    mode: RenderMode,
+    width: usize,
+    height: usize,
+    pixel: F,
+) -> String {
+    let mut out = String::new();
+    match mode {
+        RenderMode::Glyph(glyph) => {
+            for y in 0..height {
+                for x in 0..width {
+                    out.push(if pixel(x, y) { glyph } else { ' ' });
+                }
+                out.push('\n');
+            }
+        }
+        RenderMode::HalfBlock => {
+            for y in (0..height).step_by(2) {
+                for x in 0..width {
+                    let top = pixel(x, y);
+                    let bottom = y + 1 < height && pixel(x, y + 1);
+                    out.push(match (top, bottom) {
+                        (true, true) => GLYPH_FULL,
+                        (true, false) => GLYPH_UPPER,
+                        (false, true) => GLYPH_LOWER,
+                        (false, false) => ' ',
+                    });
+                }
+                out.push('\n');
+            }
+        }
+        RenderMode::Braille => {
+            for y in (0..height).step_by(4) {
+                for x in (0..width).step_by(2) {
+                    let mut dots = 0;
+                    for (dy, row) in BRAILLE_DOTS.iter().enumerate() {
+                        for (dx, dot) in row.iter().enumerate() {
+                            if x + dx < width && y + dy < height && pixel(x + dx, y + dy) {
+                                dots |= dot;
+                            }
+                        }
+                    }
+                    out.push(std::char::from_u32(BRAILLE_BLANK + dots).unwrap_or(' '));
+                }
+                out.push('\n');
+            }
+        }
+    }
+    out
+}
+
+// Same layouts as frame_text in truecolor. pixel returns a palette index.
+// Half blocks color the top half with the foreground and the bottom with the
+// background, a braille cell takes the highest index inside it.
+pub fn frame_text_colored<F: Fn(usize, usize) -> u8>(
+    mode: RenderMode,
+    width: usize,
+    height: usize,
+    palette: &Palette,
+    pixel: F,
+) -> String {
+    let mut out = String::new();
+    // only emit escapes when the colors change
+    let mut current: Option<(u8, u8)> = None;
+    let cell = |out: &mut String, current: &mut Option<(u8, u8)>, fg: u8, bg: u8, glyph: char| {
+        if *current != Some((fg, bg)) {
+            out.push_str(&ansi_fg(palette.color(fg)));
+            out.push_str(&ansi_bg(palette.color(bg)));
+            *current = Some((fg, bg));
+        }
+        out.push(glyph);
+    };
+    match mode {
+        RenderMode::Glyph(glyph) => {
+            for y in 0..height {
+                for x in 0..width {
+                    let index = pixel(x, y);
+                    cell(&mut out, &mut current, index, 0, if index == 0 { ' ' } else { glyph });
+                }
+                out.push_str(ANSI_RESET);
+                out.push('\n');
+                current = None;
+            }
+        }
+        RenderMode::HalfBlock => {
+            for y in (0..height).step_by(2) {
+                for x in 0..width {
+                    let top = pixel(x, y);
+                    let bottom = if y + 1 < height { pixel(x, y + 1) } else { 0 };
+                    cell(&mut out, &mut current, top, bottom, GLYPH_UPPER);
+                }
+                out.push_str(ANSI_RESET);
+                out.push('\n');
+                current = None;
+            }
+        }
+        RenderMode::Braille => {
+            for y in (0..height).step_by(4) {
+                for x in (0..width).step_by(2) {
+                    let mut dots = 0;
+                    let mut index = 0;
+                    for (dy, row) in BRAILLE_DOTS.iter().enumerate() {
+                        for (dx, dot) in row.iter().enumerate() {
+                            if x + dx < width && y + dy < height {
+                                let value = pixel(x + dx, y + dy);
+                                if value != 0 {
+                                    dots |= dot;
+                                    index = index.max(value);
+                                }
+                            }
+                        }
+                    }
+                    let glyph = std::char::from_u32(BRAILLE_BLANK + dots).unwrap_or(' ');
+                    cell(&mut out, &mut current, index, 0, glyph);
+                }
+                out.push_str(ANSI_RESET);
+                out.push('\n');
+                current = None;
+            }
+        }
+    }
+    out
+}
+
+pub struct TerminalRenderer {
+    pub mode: RenderMode,
+    // Truecolor output, plain text without one
+    pub palette: Option<Palette>,
+    // Redraw over the last frame instead of scrolling
+    pub in_place: bool,
+    // Flicker filter, rendered once per frame instead of on every draw
+    pub filter: Option<DisplayFilter>,
+    cleared: bool,
+    last_text: String,
+}
+
+impl TerminalRenderer {
+    pub fn new(
+        mode: RenderMode,
+        palette: Option<Palette>,
+        in_place: bool,
+        filter: Option<FilterKind>,
+    ) -> TerminalRenderer {
+        TerminalRenderer {
+            mode,
+            palette,
+            in_place,
+            filter: filter.map(DisplayFilter::new),
+            cleared: false,
+            last_text: String::new(),
+        }
+    }
+
+    pub fn filtered(&self) -> bool {
+        self.filter.is_some()
+    }
+
+    pub fn render(&mut self, chip8: &Chip8, debug: bool) {
+        if let Some(filter) = self.filter.as_mut() {
+            let glow = filter.apply(&framebuffer(chip8));
+            let lit = |x: usize, y: usize| glow[y * COLS + x] >= LIT_THRESHOLD;
+            let text = match &self.palette {
+                Some(palette) => frame_text_colored(self.mode, COLS, ROWS, palette, |x, y| lit(x, y) as u8),
+                None => frame_text(self.mode, COLS, ROWS, lit),
+            };
+            // called every frame, only print when the filtered picture changes
+            if text != self.last_text {
+                self.print(&text);
+                self.last_text = text;
+            }
+            return;
+        }
+        if !self.in_place && self.palette.is_none() {
+            if let RenderMode::Glyph(glyph) = self.mode {
+                display_render(chip8, debug, glyph);
+                return;
+            }
+        }
+        let text = match &self.palette {
+            Some(palette) => frame_text_colored(self.mode, COLS, ROWS, palette, |x, y| {
+                chip8.pixel(x, y) as u8
+            }),
+            None => frame_text(self.mode, COLS, ROWS, |x, y| chip8.pixel(x, y)),
+        };
+        self.print(&text);
+    }
+
+    fn print(&mut self, text: &str) {
+        if self.in_place {
+            if !self.cleared {
+                print!("{}", ANSI_CLEAR);
+                self.cleared = true;
+            }
+            print!("{}", ANSI_HOME);
+        }
+        print!("{}", text);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn half_block_test() {
+        // column 0 top only, column 1 bottom only, column 2 both
+        let pixels = [[true, false, true], [false, true, true]];
+        let text = frame_text(RenderMode::HalfBlock, 3, 2, |x, y| pixels[y][x]);
+        assert_eq!("\u{2580}\u{2584}\u{2588}\n", text);
+    }
+
+    #[test]
+    fn braille_test() {
+        let text = frame_text(RenderMode::Braille, 2, 4, |x, y| x == 0 || y == 3);
+        // left column 1,2,3,7 and bottom right 8
+        assert_eq!("\u{28C7}\n", text);
+        let blank = frame_text(RenderMode::Braille, 4, 4, |_, _| false);
+        assert_eq!("\u{2800}\u{2800}\n", blank);
+    }
+
+    #[test]
+    fn colored_half_block_test() {
+        let palette = Palette::builtin("high-contrast").unwrap();
+        let text = frame_text_colored(RenderMode::HalfBlock, 2, 2, &palette, |x, y| {
+            if y == 0 { x as u8 + 1 } else { 0 }
+        });
+        let expected = format!(
+            "{}{}\u{2580}{}{}\u{2580}{}\n",
+            ansi_fg(palette.color(1)),
+            ansi_bg(palette.color(0)),
+            ansi_fg(palette.color(2)),
+            ansi_bg(palette.color(0)),
+            ANSI_RESET
+        );
+        assert_eq!(expected, text);
+    }
+
+    #[test]
+    fn mode_without_unicode_test() {
+        assert_eq!(Some(RenderMode::Glyph('x')), RenderMode::from_name("braille", 'x', false));
+        assert_eq!(Some(RenderMode::Braille), RenderMode::from_name("braille", 'x', true));
+        assert_eq!(None, RenderMode::from_name("sixel", 'x', true));
+    }
+}
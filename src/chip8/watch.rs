@@ -0,0 +1,230 @@
+use crate::chip8::rom_loader::{Rom, RomLoader};
+use crate::chip8::state::load_state;
+use crate::chip8::Chip8;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant, SystemTime};
+
+// --watch: reload the rom (or the .8o source it's compiled from) whenever
+// the file changes, without leaving the renderer. What happens to the running
+// program is the reload mode:
+//   reset   start over from the new program (the default)
+//   keep    swap the program bytes, keep registers, stack, timers and display
+//   FILE    restore a save state, then swap in the new program
+//
+// --watch-source game.8o runs game.ch8 but watches and recompiles the source.
+// Other sources (e.g. .asm) need --build, a shell command that rebuilds the
+// rom, which is then loaded.
+
+// How often to look at the file's modification time
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReloadMode {
+    Reset,
+    KeepRegisters,
+    Checkpoint(PathBuf),
+}
+
+impl ReloadMode {
+    pub fn from_name(name: &str) -> ReloadMode {
+        match name {
+            "reset" => ReloadMode::Reset,
+            "keep" => ReloadMode::KeepRegisters,
+            path => ReloadMode::Checkpoint(PathBuf::from(path)),
+        }
+    }
+}
+
+pub struct RomWatcher {
+    pub path: PathBuf,
+    pub mode: ReloadMode,
+    // the file looked at for changes, the rom itself or its source
+    pub source: PathBuf,
+    // command that rebuilds the rom from the source
+    pub build: Option<String>,
+    loader: RomLoader,
+    modified: Option<SystemTime>,
+    last_poll: Instant,
+    // where the current program is and how long, to clear it on reload
+    loaded: (usize, usize),
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+impl RomWatcher {
+    pub fn new(path: &Path, loader: RomLoader, mode: ReloadMode, rom: &Rom) -> RomWatcher {
+        RomWatcher {
+            path: path.to_path_buf(),
+            mode,
+            source: path.to_path_buf(),
+            build: None,
+            loader,
+            modified: modified(path),
+            last_poll: Instant::now(),
+            loaded: (rom.address, rom.bytes.len()),
+        }
+    }
+
+    // Watch a source instead of the rom. Octo source compiles here, anything
+    // else needs a build command.
+    pub fn watch_source(&mut self, source: &Path, build: Option<String>) -> Result<(), String> {
+        let compiles = source.extension().and_then(|e| e.to_str()) == Some("8o");
+        if !compiles && build.is_none() {
+            return Err(format!("Give --build to rebuild the rom from {}", source.display()));
+        }
+        self.source = source.to_path_buf();
+        self.build = build;
+        self.modified = modified(source);
+        Ok(())
+    }
+
+    // The rebuilt rom, or the source compiled
+    fn load(&self) -> Result<Rom, String> {
+        let command = match &self.build {
+            Some(x) => x,
+            None => return self.loader.load(&self.source),
+        };
+        let output = match Command::new("sh").arg("-c").arg(command).output() {
+            Ok(x) => x,
+            Err(e) => return Err(format!("Could not run {}: {}", command, e)),
+        };
+        if !output.status.success() {
+            return Err(format!(
+                "{} failed: {}",
+                command,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        self.loader.load(&self.path)
+    }
+
+    // Call once a frame. Some when the file changed: the new rom once it is
+    // running, or why it couldn't be loaded (the old program keeps running).
+    pub fn poll(&mut self, chip8: &mut Chip8) -> Option<Result<Rom, String>> {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return None;
+        }
+        self.last_poll = Instant::now();
+        let now = modified(&self.source);
+        if now.is_none() || now == self.modified {
+            return None;
+        }
+        self.modified = now;
+        let rom = match self.load() {
+            Ok(x) => x,
+            Err(e) => return Some(Err(e)),
+        };
+        Some(self.reload(chip8, &rom).map(|_| rom))
+    }
+
+    pub fn reload(&mut self, chip8: &mut Chip8, rom: &Rom) -> Result<(), String> {
+        match &self.mode {
+            ReloadMode::Reset => {
+                let mut fresh = Chip8::new();
+                fresh.memory_map = chip8.memory_map;
+                fresh.quirks = chip8.quirks;
+                fresh.instructions_per_frame = chip8.instructions_per_frame;
+                // keep the fonts the machine was given
+                let fonts_end = chip8.memory_map.fonts_end();
+                fresh.memory[..fonts_end].copy_from_slice(&chip8.memory[..fonts_end]);
+                fresh.load_program_at(&rom.bytes, rom.address);
+                *chip8 = fresh;
+            }
+            ReloadMode::KeepRegisters => {
+                let pc = chip8.pc;
+                self.swap_program(chip8, rom);
+                // carry on where we were if that's still in the program
+                if (pc as usize) >= rom.address && (pc as usize) < rom.address + rom.bytes.len() {
+                    chip8.pc = pc;
+                }
+            }
+            ReloadMode::Checkpoint(path) => {
+                load_state(chip8, path)?;
+                let pc = chip8.pc;
+                self.swap_program(chip8, rom);
+                chip8.pc = pc;
+            }
+        }
+        self.loaded = (rom.address, rom.bytes.len());
+        chip8.should_draw = true;
+        Ok(())
+    }
+
+    // Clear the old program and copy in the new one
+    fn swap_program(&self, chip8: &mut Chip8, rom: &Rom) {
+        let (address, len) = self.loaded;
+        for byte in chip8.memory[address..address + len].iter_mut() {
+            *byte = 0;
+        }
+        chip8.load_program_at(&rom.bytes, rom.address);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::DATA;
+
+    fn rom(bytes: &[u8]) -> Rom {
+        Rom {
+            name: "test.ch8".to_string(),
+            bytes: bytes.to_vec(),
+            address: DATA,
+            settings: Vec::new(),
+            symbols: None,
+        }
+    }
+
+    #[test]
+    fn reload_modes_test() {
+        let old = rom(&[0x60, 0x05, 0x70, 0x01, 0x12, 0x02]);
+        let new = rom(&[0x61, 0x07, 0x12, 0x02]);
+        let mut chip8 = Chip8::new();
+        chip8.load_program_at(&old.bytes, old.address);
+        chip8.v[0] = 9;
+        chip8.pc = 0x202;
+
+        let mut watcher = RomWatcher::new(Path::new("test.ch8"), RomLoader::new(), ReloadMode::KeepRegisters, &old);
+        watcher.reload(&mut chip8, &new).unwrap();
+        assert_eq!((9, 0x202), (chip8.v[0], chip8.pc));
+        assert_eq!(&[0x61, 0x07, 0x12, 0x02, 0x00, 0x00], &chip8.memory[DATA..DATA + 6]);
+
+        watcher.mode = ReloadMode::Reset;
+        chip8.quirks.vblank = true;
+        watcher.reload(&mut chip8, &new).unwrap();
+        assert_eq!((0, DATA as u16), (chip8.v[0], chip8.pc));
+        assert!(chip8.quirks.vblank);
+
+        watcher.mode = ReloadMode::from_name("missing.state");
+        assert!(watcher.reload(&mut chip8, &new).is_err());
+    }
+
+    #[test]
+    fn watch_source_test() {
+        let dir = std::env::temp_dir().join(format!("chip8-watch-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("game.8o");
+        fs::write(&source, ": main\n  v0 := 5\n").unwrap();
+        let old = rom(&[0x12, 0x00]);
+        let mut chip8 = Chip8::new();
+        let mut watcher = RomWatcher::new(&dir.join("game.ch8"), RomLoader::new(), ReloadMode::Reset, &old);
+        assert!(watcher.watch_source(&dir.join("game.asm"), None).is_err());
+        watcher.watch_source(&source, None).unwrap();
+        // changed since the watcher last looked
+        watcher.modified = None;
+        watcher.last_poll -= POLL_INTERVAL;
+        let new = watcher.poll(&mut chip8).unwrap().unwrap();
+        assert!(new.symbols.is_some());
+        assert_eq!(&[0x12, 0x02, 0x60, 0x05], &chip8.memory[DATA..DATA + 4]);
+
+        watcher.build = Some("exit 3".to_string());
+        watcher.modified = None;
+        watcher.last_poll -= POLL_INTERVAL;
+        assert!(watcher.poll(&mut chip8).unwrap().is_err());
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -1,14 +1,39 @@
+
+use crate::chip8::audio::{Audio, AudioBackend, NullBackend, Tone, Waveform, WavWriter, SAMPLE_RATE};
+use crate::chip8::config::{parse_assignment, sha1_hex, user_config_path, Config, Source};
+use crate::chip8::coverage::Coverage;
 use crate::chip8::cursive_renderer;
+use crate::chip8::dap_server;
 use crate::chip8::raylib_renderer;
 use crate::chip8::emu_utils;
 use crate::chip8::emu_utils::{display_render, display_text};
+use crate::chip8::filter::FilterKind;
+use crate::chip8::fonts::{find_font, load_font_file, FontSet, Fonts, BIG_HEIGHT, SMALL_HEIGHT};
+use crate::chip8::gamepad::{load_gamepad_map, GamepadMap};
+use crate::chip8::gdb_server;
+use crate::chip8::keymap::{load_keymap, Keymap};
+use crate::chip8::lint;
+use crate::chip8::lint::{Severity, Target};
+use crate::chip8::memory_map::MemoryMap;
+use crate::chip8::monitor;
+use crate::chip8::octo;
+use crate::chip8::palette::{find_palette, Palette};
+use crate::chip8::quirks::Quirks;
+use crate::chip8::rom_loader::{Rom, RomLoader};
+use crate::chip8::romdb::{RomDb, RomInfo};
+use crate::chip8::scripting;
+use crate::chip8::sprites;
 use crate::chip8::Chip8;
 use crate::chip8::COLS;
-use crate::chip8::DISPLAY;
 use crate::chip8::ECHO_SOUND;
-use crate::chip8::MEMORY_SIZE;
+use crate::chip8::FRAMES_PER_SECOND;
 use crate::chip8::ROWS;
 use crate::chip8::ROW_LEN;
+use crate::chip8::recorder::Recorder;
+use crate::chip8::screenshot::{parse_scale, ScreenshotSchedule};
+use crate::chip8::symbols::{describe_address, load_symbols, Symbols};
+use crate::chip8::terminal_renderer::{RenderMode, TerminalRenderer};
+use crate::chip8::watch::{ReloadMode, RomWatcher};
 use c8_disasm_lib::decode;
 use std::env;
 use std::fs;
@@ -28,9 +53,15 @@ struct Opt {
     #[structopt(short = "b", long = "bios-check")]
     bios_check: bool,
 
+    /// Run in a raylib window
     #[structopt(short, long)]
     gui_mode: bool,
 
+    /// Step the rom in a cursive terminal ui showing each instruction and the registers
+    #[structopt(long = "tui")]
+    tui: bool,
+
+    /// Keep the --tui display refreshing on its own
     #[structopt(short, long)]
     autorun: bool,
 
@@ -40,12 +71,230 @@ struct Opt {
     #[structopt(short, long)]
     registers: bool,
 
-    #[structopt(short = "n", long = "iterations", default_value = "10")]
-    iterations: u32,
+    /// Instructions to run headless (default 10)
+    #[structopt(short = "n", long = "iterations")]
+    iterations: Option<u32>,
+
+    /// Instructions per second (default 600)
+    #[structopt(long = "ips")]
+    ips: Option<u32>,
+
+    /// Terminal output: char (one per pixel, the default), half (half blocks) or braille
+    #[structopt(long = "render")]
+    render: Option<String>,
+
+    /// Colors: mono, green, amber, lcd, high-contrast, octo or a palette file
+    #[structopt(long = "palette")]
+    palette: Option<String>,
+
+    /// Save the screen at the end of frame N to a .png, .pbm or .pgm file
+    #[structopt(long = "screenshot-at-frame", number_of_values = 2, value_names = &["N", "FILE"])]
+    screenshot_at_frame: Vec<String>,
+
+    /// Memory layout: vip (default), schip, xochip or eti660 (programs at 0x600)
+    #[structopt(long = "machine")]
+    machine: Option<String>,
+
+    /// Hex digit font: chip8 (default), vip, eti660, dream6800 or a font file
+    #[structopt(long = "font")]
+    font: Option<String>,
+
+    /// SCHIP big digit font for Fx30: schip (default) or a font file
+    #[structopt(long = "big-font")]
+    big_font: Option<String>,
+
+    /// Pixel scale for screenshots
+    #[structopt(long = "scale", default_value = "8", parse(try_from_str = parse_scale))]
+    scale: usize,
+
+    /// Keyboard layout for the hex pad: qwerty (default), azerty, qwertz, dvorak or hex
+    #[structopt(long = "keys")]
+    keys: Option<String>,
+
+    /// TOML file with keymap and [gamepad] overrides, per rom under [roms."file.ch8"]
+    #[structopt(long = "keymap", parse(from_os_str))]
+    keymap: Option<PathBuf>,
+
+    /// COSMAC VIP vblank quirk, a draw ends the frame
+    #[structopt(long = "vblank")]
+    vblank: bool,
+
+    /// Print instructions and draws for every frame
+    #[structopt(long = "frame-stats")]
+    frame_stats: bool,
+
+    /// Flicker filter for the display: none (default), max2, blend[:N] or phosphor[:decay]
+    #[structopt(long = "filter")]
+    filter: Option<String>,
+
+    /// Record every frame to a .gif, .y4m or numbered .pbm files
+    #[structopt(long = "record", parse(from_os_str))]
+    record: Option<PathBuf>,
+
+    /// Don't record frames that repeat the one before
+    #[structopt(long = "dedupe")]
+    dedupe: bool,
+
+    /// Write an annotated disassembly with execute/read/write coverage
+    #[structopt(long = "coverage", parse(from_os_str))]
+    coverage: Option<PathBuf>,
+
+    /// Write an lcov report for the source lines in the symbol file
+    #[structopt(long = "lcov", parse(from_os_str))]
+    lcov: Option<PathBuf>,
+
+    /// Symbol file with labels and address to source line map
+    #[structopt(long = "symbols", parse(from_os_str))]
+    symbols: Option<PathBuf>,
+
+    /// Stop when the pc reaches this address or label, e.g. draw_player+0x4
+    #[structopt(long = "break")]
+    breakpoints: Vec<String>,
+
+    /// Wait for a gdb remote connection on this local port
+    #[structopt(long = "gdbserver")]
+    gdbserver: Option<u16>,
+
+    /// Line oriented monitor with a chip8> prompt instead of running
+    #[structopt(long = "monitor")]
+    monitor: bool,
+
+    /// Record the sound timer beep to a .wav file
+    #[structopt(long = "wav", parse(from_os_str))]
+    wav: Option<PathBuf>,
+
+    /// Beep frequency in hz (default 440)
+    #[structopt(long = "tone")]
+    tone: Option<f32>,
+
+    /// Beep volume from 0.0 to 1.0 (default 0.25)
+    #[structopt(long = "volume")]
+    volume: Option<f32>,
+
+    /// Beep waveform: square (default), triangle, sawtooth or sine
+    #[structopt(long = "waveform")]
+    waveform: Option<String>,
+
+    /// Run a rhai script that drives the emulator
+    #[structopt(long = "script", parse(from_os_str))]
+    script: Option<PathBuf>,
+
+    /// Serve the Debug Adapter Protocol on this local port, the rom comes from launch
+    #[structopt(long = "dap")]
+    dap: Option<u16>,
+
+    /// Where the rom goes and the pc starts, e.g. 0x600 for ETI-660 programs
+    #[structopt(long = "load-address")]
+    load_address: Option<String>,
+
+    /// File to run from a .zip holding several roms
+    #[structopt(long = "rom-entry")]
+    rom_entry: Option<String>,
+
+    /// Reload the rom, or the .8o source, when the file changes
+    #[structopt(long = "watch")]
+    watch: bool,
+
+    /// Watch this source instead of the rom: .8o is recompiled, others need --build
+    #[structopt(long = "watch-source", parse(from_os_str))]
+    watch_source: Option<PathBuf>,
+
+    /// Shell command that rebuilds the rom from --watch-source, e.g. "c8asm game.asm"
+    #[structopt(long = "build")]
+    build: Option<String>,
+
+    /// On reload: reset (default), keep (registers and display) or a save state to restore
+    #[structopt(long = "reload", default_value = "reset")]
+    reload: String,
+
+    /// chip-8-database checkout to look roms up in instead of the bundled copy
+    #[structopt(long = "rom-db", parse(from_os_str))]
+    rom_db: Option<PathBuf>,
+
+    /// Settings file instead of ~/.config/chip8/config.toml
+    #[structopt(long = "config", parse(from_os_str))]
+    config: Option<PathBuf>,
+
+    /// Any setting as key=value, e.g. --set quirks.shift=false (after the other flags)
+    #[structopt(long = "set", number_of_values = 1)]
+    set: Vec<String>,
 
     /// Files to process
     #[structopt(name = "FILE", parse(from_os_str))]
-    file: PathBuf,
+    file: Option<PathBuf>,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Emulator settings
+    Config(ConfigCommand),
+    /// Run a rom, compiling Octo (.8o) sources first
+    Run {
+        #[structopt(name = "FILE", parse(from_os_str))]
+        file: PathBuf,
+    },
+    /// Compile an Octo (.8o) source to a rom and a symbol file
+    Build {
+        #[structopt(name = "FILE", parse(from_os_str))]
+        file: PathBuf,
+        /// Rom to write, FILE with a .ch8 extension by default
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+    /// Check a rom for likely bugs without running it
+    Lint {
+        #[structopt(name = "FILE", parse(from_os_str))]
+        file: PathBuf,
+        /// Machine the rom is for: chip8, schip or xochip
+        #[structopt(long, default_value = "chip8")]
+        target: String,
+    },
+    /// Show or edit the hex digit fonts
+    Font(FontCommand),
+    /// Find the sprites a rom draws and print them, or write them as PNGs or db blocks
+    Sprites {
+        #[structopt(name = "FILE", parse(from_os_str))]
+        file: PathBuf,
+        /// Also run the rom headless for this many frames, catching sprites drawn through i += vx
+        #[structopt(long, default_value = "0")]
+        frames: u32,
+        /// Print assembler db blocks instead of pictures
+        #[structopt(long)]
+        asm: bool,
+        /// Write each sprite to DIR/<name>.png
+        #[structopt(long, parse(from_os_str))]
+        png: Option<PathBuf>,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+enum FontCommand {
+    /// Print every glyph of a font
+    Show {
+        /// chip8, vip, eti660, dream6800, schip or a font file
+        #[structopt(name = "FONT", default_value = "chip8")]
+        font: String,
+    },
+    /// Edit a font in the terminal, s saves it as text and x exports the bytes
+    Edit {
+        #[structopt(name = "FONT", default_value = "chip8")]
+        font: String,
+        /// Text font to save, FONT itself if it's a file, else FONT.font
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+enum ConfigCommand {
+    /// Print the effective settings for a rom and where each came from
+    Show {
+        #[structopt(name = "FILE", parse(from_os_str))]
+        file: Option<PathBuf>,
+    },
 }
 
 mod chip8;
@@ -54,11 +303,81 @@ fn main() {
     let opt: Opt = Opt::from_args();
     //println!("{:#?}", opt);
 
+    if let Some(Command::Config(ConfigCommand::Show { file })) = &opt.command {
+        match build_config(&opt, file.as_ref().or(opt.file.as_ref())) {
+            Ok((config, info)) => {
+                if let Some(info) = info {
+                    println!("{}", info.summary());
+                }
+                print!("{}", config.describe())
+            }
+            Err(e) => println!("{}", e),
+        }
+        return;
+    }
+
+    if let Some(Command::Build { file, output }) = &opt.command {
+        build_octo(file, output.as_ref());
+        return;
+    }
+
+    if let Some(Command::Lint { file, target }) = &opt.command {
+        lint_rom(&opt, file, target);
+        return;
+    }
+
+    if let Some(Command::Font(command)) = &opt.command {
+        font_command(command);
+        return;
+    }
+
+    if let Some(Command::Sprites { file, frames, asm, png }) = &opt.command {
+        extract_sprites(&opt, file, *frames, *asm, png.as_ref());
+        return;
+    }
+
+    // `chip8 run FILE` is `chip8 FILE`
+    let rom_file = match &opt.command {
+        Some(Command::Run { file }) => Some(file.clone()),
+        _ => opt.file.clone(),
+    };
+
+    let (config, rom_info) = match build_config(&opt, rom_file.as_ref()) {
+        Ok(x) => x,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    if let Some(info) = &rom_info {
+        println!("{}", info.summary());
+    }
+
     let lang = env::var("LANG").unwrap_or("".to_string());
-    let glyph = determine_display_glyph(opt.override_glyph, lang);
+    let unicode = supports_unicode(&lang);
+    let glyph = match config.str("display.glyph") {
+        "auto" => determine_display_glyph(None, lang),
+        text => determine_display_glyph(text.chars().next(), lang),
+    };
+
+    let fonts = match config_fonts(&config) {
+        Ok(x) => x,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    let memory_map = match MemoryMap::preset(config.str("machine.memory")) {
+        Ok(x) => x,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
 
     if opt.font_check {
-        emu_utils::dump_fonts(glyph);
+        emu_utils::dump_fonts(&fonts.small, glyph);
         return;
     }
 
@@ -68,41 +387,566 @@ fn main() {
         return;
     }
 
-    if opt.gui_mode {
-        //cursive_renderer::run_gui_emulator(opt.file.as_path(), false, glyph, opt.autorun);
-        raylib_renderer::run();
+    let loader = match rom_loader(&opt, &memory_map) {
+        Ok(x) => x,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    let quirks = config_quirks(&config);
+    let instructions_per_frame = (config.int("run.ips") as u32 / FRAMES_PER_SECOND).max(1);
+
+    if let Some(port) = opt.dap {
+        dap_server::run_dap_server(port, loader, memory_map, fonts, quirks, instructions_per_frame);
+        return;
+    }
+
+    let file = match rom_file {
+        Some(x) => x,
+        None => {
+            println!("No FILE given, see --help");
+            return;
+        }
+    };
+
+    let (rom, symbols) = match load_rom(&opt, &file, &loader) {
+        Ok(x) => x,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    let mut watcher = match opt.watch || opt.watch_source.is_some() {
+        true => Some(RomWatcher::new(&file, loader.clone(), ReloadMode::from_name(&opt.reload), &rom)),
+        false => None,
+    };
+    if let (Some(watcher), Some(source)) = (watcher.as_mut(), &opt.watch_source) {
+        if let Err(e) = watcher.watch_source(source, opt.build.clone()) {
+            println!("{}", e);
+            return;
+        }
+    }
+
+    if let Some(port) = opt.gdbserver {
+        let chip8 = chip8::machine(memory_map, &fonts, &rom, quirks, instructions_per_frame);
+        gdb_server::run_gdb_server(chip8, port);
+        return;
+    }
+
+
+    let palette = match config.str("display.palette") {
+        "none" => None,
+        name => match find_palette(name) {
+            Ok(x) => Some(x),
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        },
+    };
+
+    if opt.monitor {
+        let chip8 = chip8::machine(memory_map, &fonts, &rom, quirks, instructions_per_frame);
+        let palette = palette.unwrap_or_else(Palette::mono);
+        monitor::run_monitor(chip8, symbols, glyph, palette, opt.scale);
+        return;
+    }
+
+    if let Some(script) = &opt.script {
+        let chip8 = chip8::machine(memory_map, &fonts, &rom, quirks, instructions_per_frame);
+        scripting::run_script(chip8, script, glyph);
+        return;
+    }
+
+    let filter = match FilterKind::from_name(config.str("display.filter")) {
+        Ok(x) => x,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    let mut recorder = match &opt.record {
+        Some(path) => {
+            let colors = palette.clone().unwrap_or_else(Palette::mono);
+            match Recorder::create(path, opt.scale, colors, opt.dedupe) {
+                Ok(x) => Some(x),
+                Err(e) => {
+                    println!("{}", e);
+                    return;
+                }
+            }
+        }
+        None => None,
+    };
+
+    let keys = config.str("input.keys");
+    let keymap_file = config.path("input.keymap");
+    let keymap = match &keymap_file {
+        Some(path) => load_keymap(path, keys, &file),
+        None => Keymap::preset(keys).ok_or(format!("Unknown keymap preset: {}", keys)),
+    };
+    let keymap = match keymap {
+        Ok(x) => x,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    let mut gamepad = GamepadMap::default();
+    if let Some(info) = &rom_info {
+        gamepad.apply_key_hints(&info.keys);
+        for (name, hex) in &info.keys {
+            match keymap.bindings().iter().find(|(h, _)| h == hex) {
+                Some((_, host)) => println!("{}: key {:X} ({})", name, hex, host),
+                None => println!("{}: key {:X}", name, hex),
+            }
+        }
+    }
+    if let Some(path) = &keymap_file {
+        gamepad = match load_gamepad_map(path, &file, gamepad) {
+            Ok(x) => x,
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        };
+    }
+
+    if config.bool("display.tui") {
+        let chip8 = chip8::machine(memory_map, &fonts, &rom, quirks, instructions_per_frame);
+        cursive_renderer::run_gui_emulator(chip8, glyph, opt.autorun, symbols, keymap, watcher);
+    } else if config.bool("display.gui") {
+        let chip8 = chip8::machine(memory_map, &fonts, &rom, quirks, instructions_per_frame);
+        raylib_renderer::run(
+            chip8,
+            &palette.unwrap_or_else(Palette::mono),
+            filter,
+            &keymap,
+            gamepad,
+            recorder.as_mut(),
+            watcher.as_mut(),
+        );
     } else {
-        run_emulator(opt.file.as_path(), opt.iterations, opt.registers, glyph);
+        let mut breakpoints = Vec::new();
+        for name in &opt.breakpoints {
+            let address = match &symbols {
+                Some(symbols) => symbols.resolve(name),
+                None => chip8::symbols::parse_address(name),
+            };
+            match address {
+                Some(x) => breakpoints.push(x),
+                None => {
+                    println!("Unknown breakpoint location: {}", name);
+                    return;
+                }
+            }
+        }
+        let mut frames = Vec::new();
+        for pair in opt.screenshot_at_frame.chunks(2) {
+            match pair[0].parse::<u32>() {
+                Ok(frame) => frames.push((frame, PathBuf::from(&pair[1]))),
+                Err(_) => {
+                    println!("Bad frame number for screenshot: {}", pair[0]);
+                    return;
+                }
+            }
+        }
+        let screenshots = ScreenshotSchedule {
+            frames,
+            scale: opt.scale,
+            palette: palette.clone().unwrap_or_else(Palette::mono),
+        };
+        let render = config.str("display.renderer");
+        let registers = config.bool("run.registers");
+        let mode = match RenderMode::from_name(render, glyph, unicode) {
+            Some(x) => x,
+            None => {
+                println!("Unknown render mode: {}", render);
+                return;
+            }
+        };
+        // Scrolling keeps the register dumps readable
+        let in_place = render != "char" && !registers;
+        let mut renderer = TerminalRenderer::new(mode, palette, in_place, filter);
+        let mut audio = match create_audio(&config, opt.wav.as_ref()) {
+            Some(x) => x,
+            None => return,
+        };
+        let mut coverage = match opt.coverage.is_some() || opt.lcov.is_some() {
+            true => {
+                let mut coverage = Coverage::new();
+                coverage.load_rom(rom.address, &rom.bytes);
+                Some(coverage)
+            }
+            false => None,
+        };
+        let chip8 = chip8::machine(memory_map, &fonts, &rom, quirks, instructions_per_frame);
+        let options = RunOptions {
+            iterations: config.int("run.iterations") as u32,
+            registers,
+            frame_stats: opt.frame_stats,
+            breakpoints: &breakpoints,
+            renderer: &mut renderer,
+            audio: &mut audio,
+            screenshots: &screenshots,
+            recorder: recorder.as_mut(),
+            coverage: coverage.as_mut(),
+            watcher: watcher.as_mut(),
+        };
+        run_emulator(chip8, symbols.clone(), options);
+        audio.finish();
+        if let Some(coverage) = coverage {
+            write_coverage_reports(&opt, &coverage, symbols.as_ref());
+        }
+    }
+}
+
+// Defaults, then the rom database and cartridge options, then the config
+// file and its rom section, then the flags
+fn build_config(opt: &Opt, file: Option<&PathBuf>) -> Result<(Config, Option<RomInfo>), String> {
+    let mut config = Config::new();
+    // only for the hash, the machine isn't known yet
+    let loader = rom_loader(opt, &MemoryMap::default())?;
+    let rom = file.and_then(|f| loader.load(f).ok());
+    let rom_hash = rom.as_ref().map(|rom| sha1_hex(&rom.bytes));
+    let db = match &opt.rom_db {
+        Some(dir) => RomDb::load_dir(dir)?,
+        None => RomDb::bundled(),
+    };
+    let rom_info = rom_hash.as_ref().and_then(|hash| db.lookup(hash));
+    if let Some(info) = &rom_info {
+        for (key, value) in info.settings() {
+            config.set(key, value, Source::Database)?;
+        }
+    }
+    if let (Some(rom), Some(path)) = (&rom, file) {
+        for (key, value) in rom.settings.iter().cloned() {
+            config.set(key, value, Source::Cartridge(path.clone()))?;
+        }
+    }
+    if let Some(path) = opt.config.clone().or_else(user_config_path) {
+        config.load_file(&path, rom_hash.as_deref())?;
+    }
+    let mut flags: Vec<(&str, toml::Value)> = Vec::new();
+    if opt.vblank {
+        flags.push(("quirks.vblank", toml::Value::Boolean(true)));
+    }
+    if let Some(ips) = opt.ips {
+        flags.push(("run.ips", toml::Value::Integer(ips as i64)));
+    }
+    if let Some(n) = opt.iterations {
+        flags.push(("run.iterations", toml::Value::Integer(n as i64)));
+    }
+    if opt.registers {
+        flags.push(("run.registers", toml::Value::Boolean(true)));
+    }
+    if opt.gui_mode {
+        flags.push(("display.gui", toml::Value::Boolean(true)));
+    }
+    if opt.tui {
+        flags.push(("display.tui", toml::Value::Boolean(true)));
+    }
+    let strings = [
+        ("display.renderer", opt.render.clone()),
+        ("display.glyph", opt.override_glyph.map(|c| c.to_string())),
+        ("display.palette", opt.palette.clone()),
+        ("display.filter", opt.filter.clone()),
+        ("font.small", opt.font.clone()),
+        ("font.big", opt.big_font.clone()),
+        ("machine.memory", opt.machine.clone()),
+        ("input.keys", opt.keys.clone()),
+        ("input.keymap", opt.keymap.as_ref().map(|p| p.display().to_string())),
+        ("audio.waveform", opt.waveform.clone()),
+    ];
+    for (key, value) in strings.iter() {
+        if let Some(value) = value {
+            flags.push((key, toml::Value::String(value.clone())));
+        }
+    }
+    if let Some(tone) = opt.tone {
+        flags.push(("audio.tone", toml::Value::Float(tone as f64)));
+    }
+    if let Some(volume) = opt.volume {
+        flags.push(("audio.volume", toml::Value::Float(volume as f64)));
+    }
+    for (key, value) in flags {
+        config.set(key, value, Source::Flag)?;
+    }
+    for text in &opt.set {
+        let (key, value) = parse_assignment(text)?;
+        config.set(&key, value, Source::Flag)?;
+    }
+    Ok((config, rom_info))
+}
+
+// `chip8 build game.8o` writes game.ch8 and game.sym
+fn build_octo(file: &Path, output: Option<&PathBuf>) {
+    let compiled = match octo::compile_file(file) {
+        Ok(x) => x,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    let rom_path = match output {
+        Some(x) => x.clone(),
+        None => file.with_extension("ch8"),
+    };
+    let symbols_path = rom_path.with_extension("sym");
+    if let Err(e) = fs::write(&rom_path, &compiled.bytes) {
+        println!("Could not write {}: {}", rom_path.display(), e);
+        return;
+    }
+    if let Err(e) = fs::write(&symbols_path, compiled.symbols.to_text()) {
+        println!("Could not write {}: {}", symbols_path.display(), e);
+        return;
+    }
+    println!(
+        "Wrote {} ({} bytes) and {}",
+        rom_path.display(),
+        compiled.bytes.len(),
+        symbols_path.display()
+    );
+}
+
+fn lint_rom(opt: &Opt, file: &Path, target: &str) {
+    let target = match Target::from_name(target) {
+        Ok(x) => x,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    let (rom, _, memory_map, symbols) = match open_rom(opt, file) {
+        Ok(x) => x,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    let findings = lint::lint(&rom.bytes, rom.address, target, &memory_map);
+    for finding in &findings {
+        println!(
+            "{}: {}: {}",
+            describe_address(symbols.as_ref(), finding.address),
+            finding.severity,
+            finding.message
+        );
+    }
+    let errors = findings.iter().filter(|f| f.severity == Severity::Error).count();
+    let warnings = findings.iter().filter(|f| f.severity == Severity::Warning).count();
+    println!("{}: {} errors, {} warnings", rom.name, errors, warnings);
+}
+
+fn extract_sprites(opt: &Opt, file: &Path, frames: u32, asm: bool, png: Option<&PathBuf>) {
+    let (rom, config, memory_map, symbols) = match open_rom(opt, file) {
+        Ok(x) => x,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    let fonts = match config_fonts(&config) {
+        Ok(x) => x,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    let instructions_per_frame = (config.int("run.ips") as u32 / FRAMES_PER_SECOND).max(1);
+    let mut chip8 = chip8::machine(memory_map, &fonts, &rom, config_quirks(&config), instructions_per_frame);
+    // sprite bytes as loaded, before the rom gets to change them
+    let memory = chip8.memory.to_vec();
+    let mut draws = lint::draws(&rom.bytes, rom.address);
+    for draw in sprites::trace_draws(&mut chip8, frames) {
+        if !draws.contains(&draw) {
+            draws.push(draw);
+        }
+    }
+    let found = sprites::collect_sprites(&draws, &memory);
+
+    let glyph = determine_display_glyph(None, env::var("LANG").unwrap_or("".to_string()));
+    for sprite in &found {
+        if let Some(dir) = png {
+            let path = dir.join(format!("{}.png", sprite.name(symbols.as_ref())));
+            match sprite.save_png(&path, opt.scale) {
+                Ok(()) => println!("Wrote {}", path.display()),
+                Err(e) => println!("{}", e),
+            }
+        } else if asm {
+            println!("{}", sprite.to_asm(symbols.as_ref()));
+        } else {
+            let drawn_from: Vec<String> = sprite
+                .draws
+                .iter()
+                .map(|pc| describe_address(symbols.as_ref(), *pc))
+                .collect();
+            println!(
+                "{} {}x{}, drawn at {}",
+                sprite.name(symbols.as_ref()),
+                sprite.width,
+                sprite.height,
+                drawn_from.join(", ")
+            );
+            println!("{}", sprite.to_text(glyph));
+        }
+    }
+    if found.is_empty() {
+        println!("No sprites found in {}", rom.name);
+    }
+}
+
+// A rom with the settings for it, for the commands that don't run it
+fn open_rom(opt: &Opt, file: &Path) -> Result<(Rom, Config, MemoryMap, Option<Symbols>), String> {
+    let (config, _) = build_config(opt, Some(&file.to_path_buf()))?;
+    let memory_map = MemoryMap::preset(config.str("machine.memory"))?;
+    let loader = rom_loader(opt, &memory_map)?;
+    let (rom, symbols) = load_rom(opt, file, &loader)?;
+    Ok((rom, config, memory_map, symbols))
+}
+
+// The rom and its symbols
+fn load_rom(opt: &Opt, file: &Path, loader: &RomLoader) -> Result<(Rom, Option<Symbols>), String> {
+    println!("Loading {} into memory", file.display());
+    let rom = loader.load(file)?;
+    let symbols = rom_symbols(opt, &rom)?;
+    Ok((rom, symbols))
+}
+
+// A symbol file wins over the symbols of a compiled rom
+fn rom_symbols(opt: &Opt, rom: &Rom) -> Result<Option<Symbols>, String> {
+    match &opt.symbols {
+        Some(path) => load_symbols(path).map(Some),
+        None => Ok(rom.symbols.clone()),
+    }
+}
+
+fn config_quirks(config: &Config) -> Quirks {
+    Quirks {
+        shift: config.bool("quirks.shift"),
+        logic: config.bool("quirks.logic"),
+        wrap: config.bool("quirks.wrap"),
+        vblank: config.bool("quirks.vblank"),
+    }
+}
+
+fn font_command(command: &FontCommand) {
+    let name = match command {
+        FontCommand::Show { font } => font,
+        FontCommand::Edit { font, .. } => font,
+    };
+    // any height, the editor works on big fonts too
+    let font = match FontSet::preset(name) {
+        Some(x) => Ok(x),
+        None => load_font_file(Path::new(name)),
+    };
+    let font = match font {
+        Ok(x) => x,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    let glyph = determine_display_glyph(None, env::var("LANG").unwrap_or("".to_string()));
+    match command {
+        FontCommand::Show { .. } => emu_utils::dump_fonts(&font, glyph),
+        FontCommand::Edit { output, .. } => {
+            let path = match output {
+                Some(x) => x.clone(),
+                None if FontSet::preset(name).is_none() => PathBuf::from(name),
+                None => PathBuf::from(format!("{}.font", name)),
+            };
+            cursive_renderer::run_font_editor(font, path, glyph);
+        }
+    }
+}
+
+fn config_fonts(config: &Config) -> Result<Fonts, String> {
+    Ok(Fonts {
+        small: find_font(config.str("font.small"), SMALL_HEIGHT)?,
+        big: find_font(config.str("font.big"), BIG_HEIGHT)?,
+    })
+}
+
+fn rom_loader(opt: &Opt, memory_map: &MemoryMap) -> Result<RomLoader, String> {
+    let mut loader = RomLoader::for_machine(memory_map);
+    if let Some(text) = &opt.load_address {
+        loader.address = match chip8::symbols::parse_address(text) {
+            Some(x) => x as usize,
+            None => return Err(format!("Bad load address: {}", text)),
+        };
+    }
+    loader.entry = opt.rom_entry.clone();
+    Ok(loader)
+}
+
+fn create_audio(config: &Config, wav: Option<&PathBuf>) -> Option<Audio> {
+    let name = config.str("audio.waveform");
+    let waveform = match Waveform::from_name(name) {
+        Some(x) => x,
+        None => {
+            println!("Unknown waveform: {}", name);
+            return None;
+        }
+    };
+    let tone = Tone {
+        frequency: config.float("audio.tone") as f32,
+        volume: (config.float("audio.volume") as f32).clamp(0.0, 1.0),
+        waveform,
+    };
+    let backend: Box<dyn AudioBackend> = match wav {
+        Some(path) => match WavWriter::create(path, SAMPLE_RATE) {
+            Ok(x) => Box::new(x),
+            Err(e) => {
+                println!("Could not create wav file: {}", e);
+                return None;
+            }
+        },
+        None => Box::new(NullBackend),
+    };
+    Some(Audio::new(tone, backend))
+}
+
+fn write_coverage_reports(opt: &Opt, coverage: &Coverage, symbols: Option<&Symbols>) {
+    if let Some(path) = &opt.coverage {
+        if let Err(e) = fs::write(path, coverage.listing(symbols)) {
+            println!("Could not write coverage listing: {}", e);
+        }
+    }
+    if let Some(path) = &opt.lcov {
+        match symbols {
+            Some(symbols) => {
+                if let Err(e) = fs::write(path, coverage.lcov(symbols)) {
+                    println!("Could not write lcov report: {}", e);
+                }
+            }
+            None => println!("An lcov report needs a symbol file, use --symbols"),
+        }
     }
 }
 
 fn determine_display_glyph(override_glyph: Option<char>, lang: String) -> char {
-    if override_glyph.is_some() {
-        return override_glyph.unwrap();
+    if let Some(glyph) = override_glyph {
+        return glyph;
     }
     // Return Defaults: Default to unicode BLOCK if env LANG for UTF-8 is supported
-    return match lang.to_lowercase().contains("utf-8") {
+    match supports_unicode(&lang) {
         true => GLYPH_BLOCK,
         false => GLYPH_X,
-    };
+    }
+}
+
+fn supports_unicode(lang: &str) -> bool {
+    lang.to_lowercase().contains("utf-8")
 }
 
 fn bios_check(glyph: char) {
-    let memory: [u8; MEMORY_SIZE] = [0; MEMORY_SIZE];
-    let mut chip8 = Chip8 {
-        memory: memory,
-        v: [0; 16],
-        address: 0,
-        timer_delay: 0,
-        timer_sound: 0,
-        pc: 0,
-        sp: 0,
-        i: 0,
-        keyboard: 0,
-        should_draw: false,
-        wait_key: false,
-        wait_key_v_x: 0,
-    };
+    let mut chip8 = Chip8::new();
     chip8.load_fonts();
 
     chip8.load(2, 250);
@@ -127,83 +971,116 @@ fn bios_check(glyph: char) {
     display_text(&mut chip8, glyph);
 }
 
-fn run_emulator(path: &Path, iterations: u32, debug_registers: bool, glyph: char) {
-    let memory: [u8; MEMORY_SIZE] = [0; MEMORY_SIZE];
-    let mut chip8 = Chip8 {
-        memory: memory,
-        v: [0; 16],
-        address: 0,
-        timer_delay: 0,
-        timer_sound: 0,
-        pc: 0,
-        sp: 0,
-        i: 0,
-        keyboard: 0,
-        should_draw: false,
-        wait_key: false,
-        wait_key_v_x: 0,
-    };
-
-    // Input - hex keyboard: 16 keys 0-F.
-    // 1 2 3 C
-    // 4 5 6 D
-    // 7 8 9 E
-    // A 0 B F
+// What the terminal run loop does besides running the machine
+struct RunOptions<'a> {
+    iterations: u32,
+    registers: bool,
+    frame_stats: bool,
+    breakpoints: &'a [u16],
+    renderer: &'a mut TerminalRenderer,
+    audio: &'a mut Audio,
+    screenshots: &'a ScreenshotSchedule,
+    recorder: Option<&'a mut Recorder>,
+    coverage: Option<&'a mut Coverage>,
+    watcher: Option<&'a mut RomWatcher>,
+}
 
-    let keyboard_state: [bool; 16] = [false; 16];
+fn run_emulator(mut chip8: Chip8, mut symbols: Option<Symbols>, options: RunOptions) {
+    let RunOptions {
+        iterations,
+        registers: debug_registers,
+        frame_stats,
+        breakpoints,
+        renderer,
+        audio,
+        screenshots,
+        mut recorder,
+        mut coverage,
+        mut watcher,
+    } = options;
 
     // Graphics is 64x32 monochrome
     // Sprites are 8 wide 1-15 in height
     // xor'd to screen pixels
     // Carry flag VF is set to 1 if pixels are flipped when sprite drawn or else 0
-    let graphics_state: [bool; ROWS * COLS] = [false; ROWS * COLS];
-
-    // load fonts
-    chip8.load_fonts();
-
-    // fetch
-
-    println!(
-        "Loading {} into memory",
-        path.to_str().unwrap_or("BAD_PATH")
-    );
-    let bytes = match fs::read(path) {
-        Ok(x) => x,
-        Err(_e) => {
-            println!(
-                "Could not read file from path: {}",
-                path.to_str().unwrap_or("BAD_PATH")
-            );
-            return;
-        }
-    };
-    let start = chip8::DATA;
-    for i in 0..bytes.len() {
-        chip8.memory[start + i] = bytes[i];
-    }
-
-    chip8.pc = 0x200;
 
     if debug_registers {
-        console_debug_registers(&chip8);
+        console_debug_registers(&chip8, symbols.as_ref());
     }
 
+    let mut frame = 0;
     for _ in 0..iterations {
+        if breakpoints.contains(&chip8.pc) {
+            println!(
+                "\nBreakpoint at {}",
+                describe_address(symbols.as_ref(), chip8.pc)
+            );
+            console_debug_registers(&chip8, symbols.as_ref());
+            // still finish the recording below
+            break;
+        }
+        if let Some(coverage) = coverage.as_mut() {
+            coverage.record_step(&chip8);
+        }
+        let pc = chip8.pc;
         let (b0, b1) = chip8.fetch();
         if debug_registers {
-            println!("\nfetch: {:02X}  {:02X}", b0, b1)
+            println!(
+                "\n{}  fetch: {:02X}  {:02X}",
+                describe_address(symbols.as_ref(), pc),
+                b0,
+                b1
+            )
         }
         if debug_registers {
             decode_print_byte(b0, b1, true);
         }
         chip8.decode_execute(b0, b1);
         if debug_registers {
-            console_debug_registers(&chip8);
+            console_debug_registers(&chip8, symbols.as_ref());
         }
         if chip8.should_draw {
-            display_render(&chip8, debug_registers, glyph);
+            if !renderer.filtered() {
+                renderer.render(&chip8, debug_registers);
+            }
             chip8.should_draw = false;
-            // TODO Need to implement timers for sounds and delays
+        }
+        if chip8.frame_over() {
+            if renderer.filtered() {
+                renderer.render(&chip8, debug_registers);
+            }
+            audio.frame(&chip8);
+            let stats = chip8.end_frame();
+            if let Some(watcher) = watcher.as_mut() {
+                match watcher.poll(&mut chip8) {
+                    Some(Ok(rom)) => {
+                        println!("Reloaded {}", rom.name);
+                        if rom.symbols.is_some() {
+                            symbols = rom.symbols;
+                        }
+                    }
+                    Some(Err(e)) => println!("{}", e),
+                    None => {}
+                }
+            }
+            frame += 1;
+            if frame_stats {
+                println!(
+                    "frame {}: {} instructions, {} draws",
+                    frame, stats.instructions, stats.draws
+                );
+            }
+            screenshots.frame_done(frame, &chip8);
+            if let Some(recorder) = recorder.as_mut() {
+                if let Err(e) = recorder.frame(&chip8) {
+                    println!("{}", e);
+                }
+            }
+        }
+    }
+    if let Some(recorder) = recorder {
+        if let Err(e) = recorder.finish() {
+            println!("{}", e);
         }
     }
 }
@@ -221,9 +1098,20 @@ fn decode_print_byte(b0: u8, b1: u8, should_show_ascii: bool) {
     }
 }
 
-fn console_debug_registers(chip8: &Chip8) {
+fn console_debug_registers(chip8: &Chip8, symbols: Option<&Symbols>) {
     println!("PC    SP    I");
     println!("{:#X} {:#X} {:#X}", chip8.pc, chip8.sp, chip8.i);
+    if symbols.is_some() {
+        println!("at {}", describe_address(symbols, chip8.pc));
+    }
+    let stack = chip8.call_stack();
+    if !stack.is_empty() {
+        let names: Vec<String> = stack
+            .iter()
+            .map(|ret| describe_address(symbols, *ret))
+            .collect();
+        println!("stack: {}", names.join(" < "));
+    }
     println!("v0 v1 v2 v3 v4 v5 v6 v7 v8 v9 va vb vc vd ve vf dt st  k\n");
     println!("{:2X} {:2X} {:2X} {:2X} {:2X} {:2X} {:2X} {:2X} {:2X} {:2X} {:2X} {:2X} {:2X} {:2X} {:2X} {:2X} {:2X} {:2X} {:2X}",
              chip8.v[0], chip8.v[1], chip8.v[2], chip8.v[3],